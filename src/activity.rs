@@ -0,0 +1,79 @@
+use crate::error::SpaggiariError;
+use chrono::{DateTime, Local};
+use std::sync::Mutex;
+
+/// Operazione registrata nel log delle attività della sessione
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityEntry {
+    /// Istante in cui l'operazione è terminata
+    pub timestamp: DateTime<Local>,
+    /// Endpoint del portale contattato (percorso o URL)
+    pub endpoint: String,
+    /// Status HTTP dell'ultima risposta del portale, `None` se non è arrivata nessuna risposta
+    pub status: Option<u16>,
+    /// `None` se l'operazione è riuscita, altrimenti il messaggio di errore
+    pub errore: Option<String>,
+    /// Byte ricevuti dal portale (0 se l'operazione è fallita)
+    pub bytes: u64,
+}
+
+// Log delle attività di una sessione, disattivato finché non viene abilitato
+#[derive(Debug, Default)]
+pub(crate) struct ActivityLog {
+    entries: Mutex<Option<Vec<ActivityEntry>>>,
+}
+
+impl ActivityLog {
+    pub(crate) fn enable(&self) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get_or_insert_with(Vec::new);
+    }
+
+    // Registra l'esito di un'operazione e lo status HTTP ricevuto; `bytes` viene calcolato solo se il log è abilitato
+    pub(crate) fn record<T>(&self, endpoint: &str, status: Option<u16>, result: &Result<T, SpaggiariError>, bytes: impl FnOnce(&T) -> usize) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entries) = entries.as_mut() {
+            let (errore, bytes) = match result {
+                Ok(value) => (None, bytes(value) as u64),
+                Err(e) => (Some(e.to_string()), 0),
+            };
+            entries.push(ActivityEntry {
+                timestamp: Local::now(),
+                endpoint: endpoint.to_string(),
+                status,
+                errore,
+                bytes,
+            });
+        }
+    }
+
+    pub(crate) fn entries(&self) -> Vec<ActivityEntry> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_log_records_nothing() {
+        let log = ActivityLog::default();
+        log.record("/bacheca", Some(200), &Ok::<_, SpaggiariError>("abc"), |s| s.len());
+        assert!(log.entries().is_empty());
+    }
+
+    #[test]
+    fn test_enabled_log_records_outcome() {
+        let log = ActivityLog::default();
+        log.enable();
+        log.record("/bacheca", Some(200), &Ok::<_, SpaggiariError>("abc"), |s| s.len());
+        log.record::<&str>("/file", Some(404), &Err(SpaggiariError::InvalidSessionToken), |s| s.len());
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].endpoint.as_str(), entries[0].bytes, entries[0].errore.is_none()), ("/bacheca", 3, true));
+        assert_eq!(entries[1].errore.as_deref(), Some("Token di sessione non valido o scaduto"));
+        assert_eq!((entries[0].status, entries[1].status), (Some(200), Some(404)));
+    }
+}
@@ -0,0 +1,196 @@
+//! Agenda della classe: compiti, verifiche e annotazioni dei docenti
+
+use crate::bacheca_personale::{cookie_header, BASE_URL};
+use crate::error::SpaggiariError;
+use crate::http::Http;
+use crate::utils::{classifica_pagina_html, parse_json_body};
+use chrono::NaiveDate;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{debug, trace};
+
+pub(crate) const PATH_AGENDA: &str = "/fml/app/default/agenda_studenti.php";
+
+/// Tipo di evento dell'agenda, dal campo `tipo` restituito dal portale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TipoEvento {
+    /// Compiti assegnati per casa (`compito`)
+    Compito,
+    /// Verifica scritta o orale (`verifica`)
+    Verifica,
+    /// Annotazione generica del docente (`nota`)
+    Nota,
+    /// Tipo non riconosciuto, conservato così come restituito dal portale
+    Altro(String),
+}
+
+impl TipoEvento {
+    /// Interpreta il tipo indicato dal portale (senza distinzione di maiuscole)
+    pub fn from_code(code: &str) -> TipoEvento {
+        match code.trim().to_lowercase().as_str() {
+            "compito" | "compiti" => TipoEvento::Compito,
+            "verifica" => TipoEvento::Verifica,
+            "nota" => TipoEvento::Nota,
+            _ => TipoEvento::Altro(code.to_string()),
+        }
+    }
+}
+
+/// Evento dell'agenda
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgendaEvent {
+    /// Materia dell'evento, assente per gli eventi che riguardano tutta la classe
+    pub materia: Option<String>,
+    pub descrizione: String,
+    pub data: NaiveDate,
+    pub tipo: TipoEvento,
+}
+
+// Evento come restituito dal portale
+#[derive(Debug, Deserialize)]
+struct EventoGrezzo {
+    title: Option<String>,
+    start: String,
+    nota_2: Option<String>,
+    materia_desc: Option<String>,
+    tipo: Option<String>,
+}
+
+impl EventoGrezzo {
+    fn into_evento(self) -> Result<AgendaEvent, SpaggiariError> {
+        // `start` è nel formato `2024-10-14 08:00:00`: conta solo il giorno
+        let giorno = self.start.get(..10).unwrap_or(&self.start);
+        let data = NaiveDate::parse_from_str(giorno, "%Y-%m-%d").map_err(|e| SpaggiariError::ParseError {
+            details: format!("Data dell'evento non valida '{}': {}", self.start, e),
+        })?;
+        let non_vuoto = |testo: Option<String>| testo.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+
+        Ok(AgendaEvent {
+            materia: non_vuoto(self.materia_desc),
+            descrizione: non_vuoto(self.nota_2).or_else(|| non_vuoto(self.title)).unwrap_or_default(),
+            data,
+            tipo: TipoEvento::from_code(self.tipo.as_deref().unwrap_or("")),
+        })
+    }
+}
+
+/// Interpreta il JSON degli eventi dell'agenda.
+///
+/// La descrizione è presa dal testo dell'annotazione (`nota_2`) o, in sua assenza, dal titolo.
+///
+/// # Argomenti
+///
+/// * `text` - Il JSON restituito dall'agenda.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<AgendaEvent>)` con gli eventi nell'ordine restituito dal portale.
+/// * `Err(SpaggiariError)` se il portale ha risposto con una pagina HTML (es. sessione scaduta)
+///   o se il JSON non è interpretabile.
+pub fn parse_agenda(text: &str) -> Result<Vec<AgendaEvent>, SpaggiariError> {
+    if let Some(e) = classifica_pagina_html(text) {
+        debug!(errore = %e, "l'agenda ha restituito una pagina HTML");
+        return Err(e);
+    }
+
+    parse_json_body::<Vec<EventoGrezzo>>(text)?.into_iter().map(EventoGrezzo::into_evento).collect()
+}
+
+/// Ottiene gli eventi dell'agenda in un intervallo di date (estremi inclusi).
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `session_id` - L'ID di sessione (PHPSESSID).
+/// * `webidentity` - L'identità web associata alla sessione.
+/// * `from` - Prima data dell'intervallo.
+/// * `to` - Ultima data dell'intervallo.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<AgendaEvent>)` con gli eventi dell'intervallo.
+/// * `Err(SpaggiariError)` se la richiesta fallisce o la risposta non è interpretabile.
+pub async fn get_agenda(client: &Client, session_id: &str, webidentity: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<AgendaEvent>, SpaggiariError> {
+    let text = fetch_agenda_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, from, to).await?;
+    parse_agenda(&text)
+}
+
+// Scarica il JSON grezzo degli eventi dell'agenda
+pub(crate) async fn fetch_agenda_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, from: NaiveDate, to: NaiveDate) -> Result<String, SpaggiariError> {
+    let query = [
+        ("ope", "get_events".to_string()),
+        ("start", from.format("%Y-%m-%d").to_string()),
+        ("end", to.format("%Y-%m-%d").to_string()),
+    ];
+
+    let response = http
+        .send(|client| {
+            client
+                .get(format!("{}{}", base_url, PATH_AGENDA))
+                .query(&query)
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta agenda ricevuta");
+
+    if status.is_success() {
+        Ok(response.text().await?)
+    } else {
+        debug!(status = status.as_u16(), "token non valido o scaduto");
+        Err(SpaggiariError::ParseError {
+            details: "Il token non sembra funzionare".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn giorno(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, d).unwrap()
+    }
+
+    #[test]
+    fn test_parse_agenda_settimana() {
+        let eventi = parse_agenda(include_str!("../tests/fixtures/agenda_settimana.json")).unwrap();
+
+        assert_eq!(
+            eventi,
+            vec![
+                AgendaEvent {
+                    materia: Some("MATEMATICA".to_string()),
+                    descrizione: "Esercizi 12-18 pag. 145".to_string(),
+                    data: giorno(14),
+                    tipo: TipoEvento::Compito,
+                },
+                AgendaEvent {
+                    materia: Some("STORIA".to_string()),
+                    descrizione: "Verifica sul Rinascimento".to_string(),
+                    data: giorno(16),
+                    tipo: TipoEvento::Verifica,
+                },
+                AgendaEvent {
+                    materia: None,
+                    descrizione: "Uscita didattica al museo: rientro previsto alle 13:00".to_string(),
+                    data: giorno(17),
+                    tipo: TipoEvento::Nota,
+                },
+                AgendaEvent {
+                    materia: Some("INGLESE".to_string()),
+                    descrizione: "Listening test".to_string(),
+                    data: giorno(18),
+                    tipo: TipoEvento::Altro("interrogazione".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_agenda_login_page() {
+        let result = parse_agenda(include_str!("../tests/fixtures/login_page.html"));
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)));
+    }
+}
@@ -0,0 +1,163 @@
+//! Assenze, ritardi e uscite anticipate dello studente, letti dalla pagina delle assenze del portale
+
+use crate::bacheca_personale::{cookie_header, BASE_URL};
+use crate::error::SpaggiariError;
+use crate::http::Http;
+use crate::utils::{classifica_pagina_html, selettore, testo_elemento};
+use reqwest::Client;
+use scraper::{CaseSensitivity, Html};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace, warn};
+
+pub(crate) const PATH_ASSENZE: &str = "/cvv/app/default/genitori_assenze.php";
+
+/// Tipo di evento registrato nella pagina delle assenze
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TipoAssenza {
+    Assenza,
+    Ritardo,
+    Uscita,
+}
+
+impl TipoAssenza {
+    // Riconosce il tipo dal testo mostrato dal portale (es. `Ritardo breve`, `Uscita anticipata`)
+    fn from_testo(testo: &str) -> Option<Self> {
+        let testo = testo.to_lowercase();
+        if testo.starts_with("assenza") {
+            Some(TipoAssenza::Assenza)
+        } else if testo.starts_with("ritardo") {
+            Some(TipoAssenza::Ritardo)
+        } else if testo.starts_with("uscita") {
+            Some(TipoAssenza::Uscita)
+        } else {
+            None
+        }
+    }
+}
+
+/// Assenza, ritardo o uscita anticipata
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Assenza {
+    /// Data dell'evento come riportata dal portale (es. `14/10/2024`)
+    pub data: String,
+    pub tipo: TipoAssenza,
+    pub giustificata: bool,
+    /// Motivo indicato nella giustificazione, se presente
+    pub motivo: Option<String>,
+}
+
+/// Estrae assenze, ritardi e uscite dalla pagina delle assenze del portale.
+///
+/// Ogni riga `tr.riga_evento` della tabella `table#assenze` riporta data, tipo, stato
+/// della giustificazione (classe `giustificata` sulla cella) e motivo. Le righe di tipo
+/// sconosciuto vengono ignorate.
+///
+/// # Argomenti
+///
+/// * `html` - Il contenuto HTML della pagina delle assenze.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<Assenza>)` con gli eventi nell'ordine della pagina.
+/// * `Err(SpaggiariError::InvalidSessionToken)` se il portale ha risposto con la pagina di accesso.
+/// * `Err(SpaggiariError::ParseError)` se la pagina non contiene la tabella delle assenze.
+pub fn parse_assenze(html: &str) -> Result<Vec<Assenza>, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let tabella = selettore("table#assenze")?;
+    let riga = selettore("tr.riga_evento")?;
+    let data = selettore("td.evento_data")?;
+    let tipo = selettore("td.evento_tipo")?;
+    let giustificazione = selettore("td.evento_giustificazione")?;
+    let motivo = selettore("td.evento_motivo")?;
+
+    let Some(tabella) = document.select(&tabella).next() else {
+        // Pagina di accesso (sessione scaduta) o di manutenzione al posto delle assenze
+        if let Some(errore) = classifica_pagina_html(html) {
+            return Err(errore);
+        }
+        return Err(SpaggiariError::ParseError {
+            details: "Tabella delle assenze non trovata nella pagina".to_string(),
+        });
+    };
+
+    let mut assenze = Vec::new();
+    for riga in tabella.select(&riga) {
+        let testo_tipo = riga.select(&tipo).next().map(testo_elemento).unwrap_or_default();
+        let Some(tipo) = TipoAssenza::from_testo(&testo_tipo) else {
+            warn!(tipo = %testo_tipo, "tipo di evento sconosciuto nella pagina delle assenze, riga ignorata");
+            continue;
+        };
+        assenze.push(Assenza {
+            data: riga.select(&data).next().map(testo_elemento).unwrap_or_default(),
+            tipo,
+            giustificata: riga
+                .select(&giustificazione)
+                .next()
+                .is_some_and(|cella| cella.value().has_class("giustificata", CaseSensitivity::AsciiCaseInsensitive)),
+            motivo: riga.select(&motivo).next().map(testo_elemento).filter(|m| !m.is_empty()),
+        });
+    }
+
+    Ok(assenze)
+}
+
+/// Ottiene assenze, ritardi e uscite anticipate dello studente.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `session_id` - L'ID di sessione (PHPSESSID).
+/// * `webidentity` - L'identità web associata alla sessione.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<Assenza>)` con gli eventi presenti nella pagina.
+/// * `Err(SpaggiariError)` se la richiesta fallisce o la pagina non è interpretabile.
+pub async fn get_assenze(client: &Client, session_id: &str, webidentity: &str) -> Result<Vec<Assenza>, SpaggiariError> {
+    let html = fetch_assenze_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await?;
+    parse_assenze(&html)
+}
+
+// Scarica la pagina HTML grezza delle assenze
+pub(crate) async fn fetch_assenze_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<String, SpaggiariError> {
+    let response = http
+        .send(|client| client.get(format!("{}{}", base_url, PATH_ASSENZE)).header("Cookie", cookie_header(session_id, webidentity)))
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta assenze ricevuta");
+
+    if status.is_success() {
+        Ok(response.text().await?)
+    } else {
+        debug!(status = status.as_u16(), "token non valido o scaduto");
+        Err(SpaggiariError::ParseError {
+            details: "Il token non sembra funzionare".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tipo_assenza_from_testo() {
+        assert_eq!(TipoAssenza::from_testo("Ritardo breve"), Some(TipoAssenza::Ritardo));
+        assert_eq!(TipoAssenza::from_testo("USCITA ANTICIPATA"), Some(TipoAssenza::Uscita));
+        assert_eq!(TipoAssenza::from_testo("Assenza"), Some(TipoAssenza::Assenza));
+        assert_eq!(TipoAssenza::from_testo("Nota disciplinare"), None);
+    }
+
+    #[test]
+    fn test_parse_assenze_rejects_unrelated_page() {
+        let result = parse_assenze("<html><body><form id=\"login\"></form></body></html>");
+        assert!(matches!(result, Err(SpaggiariError::ParseError { .. })));
+    }
+
+    #[test]
+    fn test_parse_assenze_login_page_is_invalid_token() {
+        let result = parse_assenze(include_str!("../tests/fixtures/login_page.html"));
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)), "{:?}", result);
+    }
+}
@@ -1,14 +1,32 @@
 use crate::error::SpaggiariError;
-use log::{debug, error};
+use crate::file_type::FileType;
+use crate::http::{Http, Risposta};
+use crate::utils::{classifica_pagina_html, pagina_di_accesso, parse_json_body, selettore, sessione_sostituita};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use futures::stream::{self, StreamExt, TryStreamExt};
 use regex::Regex;
-use reqwest::Client;
-use scraper::{Html, Selector};
-use serde::{Deserialize, Deserializer};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response};
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tracing::{debug, trace, warn};
 
-const URL_BACHECA: &str = "https://web.spaggiari.eu/sif/app/default/bacheca_personale.php";
-const URL_COMUNICAZIONI: &str = "https://web.spaggiari.eu/sif/app/default/bacheca_comunicazione.php";
+pub(crate) const BASE_URL: &str = "https://web.spaggiari.eu";
+pub(crate) const PATH_BACHECA: &str = "/sif/app/default/bacheca_personale.php";
+pub(crate) const PATH_COMUNICAZIONI: &str = "/sif/app/default/bacheca_comunicazione.php";
+
+// Header `Cookie` con il token di sessione e l'identità dell'utente
+pub(crate) fn cookie_header(session_id: &str, webidentity: &str) -> String {
+    format!("PHPSESSID={}; webidentity={}", session_id, webidentity)
+}
 
 #[derive(Deserialize)]
 #[serde(untagged)]
@@ -27,7 +45,7 @@ where
     }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Circolare {
     pub id: String,
     #[serde(deserialize_with = "de_i32")]
@@ -64,13 +82,318 @@ pub struct Circolare {
     pub evento_data: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Categoria della circolare, dal campo `tipo_com_filtro` restituito dal portale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TipoComunicazione {
+    /// Comunicazione generale della scuola (`GEN`)
+    Generale,
+    /// Comunicazione rivolta al singolo alunno (`ALUNNO`)
+    Alunno,
+    /// Avviso di pagamento (`PAGAMENTI`)
+    Pagamenti,
+    /// Codice non riconosciuto, conservato così come restituito dal portale
+    Other(String),
+}
+
+impl TipoComunicazione {
+    /// Interpreta il codice `tipo_com_filtro` (senza distinzione di maiuscole)
+    pub fn from_filtro(filtro: &str) -> TipoComunicazione {
+        match filtro.trim().to_uppercase().as_str() {
+            "GEN" => TipoComunicazione::Generale,
+            "ALUNNO" => TipoComunicazione::Alunno,
+            "PAGAMENTI" => TipoComunicazione::Pagamenti,
+            _ => TipoComunicazione::Other(filtro.to_string()),
+        }
+    }
+}
+
+impl Circolare {
+    /// Categoria della circolare, ricavata da `tipo_com_filtro`
+    pub fn tipo(&self) -> TipoComunicazione {
+        TipoComunicazione::from_filtro(&self.tipo_com_filtro)
+    }
+
+    /// `true` se la circolare richiede la conferma di lettura
+    pub fn richiede_conferma(&self) -> bool {
+        flag_attivo(&self.conf_lettura)
+    }
+
+    /// `true` se la circolare richiede una risposta
+    pub fn richiede_risposta(&self) -> bool {
+        flag_attivo(&self.flag_risp)
+    }
+
+    /// `true` se la circolare richiede di essere accettata o rifiutata
+    pub fn richiede_accettazione(&self) -> bool {
+        self.flag_accettazione.as_deref().is_some_and(flag_attivo)
+    }
+
+    /// `true` se tra le `richieste` della circolare c'è il caricamento di un file.
+    ///
+    /// `richieste` è un elenco di codici separati da virgola, punto e virgola o spazi:
+    /// il caricamento è indicato da `file`, `upload` o `allegato` (senza distinzione di maiuscole).
+    pub fn richiede_upload(&self) -> bool {
+        self.richieste
+            .as_deref()
+            .unwrap_or("")
+            .split(|c: char| c == ',' || c == ';' || c == '|' || c.is_whitespace())
+            .any(|richiesta| ["file", "upload", "allegato"].iter().any(|codice| richiesta.eq_ignore_ascii_case(codice)))
+    }
+
+    /// `true` se la circolare richiede un'azione da parte dell'utente (conferma, risposta o accettazione)
+    pub fn richiede_azione(&self) -> bool {
+        self.richiede_conferma() || self.richiede_risposta() || self.richiede_accettazione()
+    }
+
+    /// `true` se l'utente ha già risposto alla circolare (`testo_risp` o `file_risp` valorizzati)
+    pub fn azione_completata(&self) -> bool {
+        [&self.testo_risp, &self.file_risp].iter().any(|v| v.as_deref().is_some_and(|v| !v.trim().is_empty()))
+    }
+
+    /// Restituisce la risposta già inviata dall'utente, se presente.
+    ///
+    /// # Restituisce
+    ///
+    /// * `Some(RispostaData)` con il testo e/o il riferimento al file inviati (`testo_risp`, `file_risp`).
+    /// * `None` se entrambi i campi sono vuoti.
+    pub fn risposta(&self) -> Option<RispostaData> {
+        let valorizzato = |v: &Option<String>| v.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+        let testo = valorizzato(&self.testo_risp);
+        let file = valorizzato(&self.file_risp);
+        if testo.is_none() && file.is_none() {
+            return None;
+        }
+        Some(RispostaData { testo, file })
+    }
+
+    /// Data di inizio pubblicazione (`data_start`).
+    ///
+    /// # Restituisce
+    ///
+    /// * `Ok(Some(NaiveDate))` se il campo è nel formato `YYYY-MM-DD` o `DD-MM-YYYY`.
+    /// * `Ok(None)` se il campo è vuoto.
+    /// * `Err(SpaggiariError::ParseError)` se il campo non è una data valida.
+    pub fn data_start_parsed(&self) -> Result<Option<NaiveDate>, SpaggiariError> {
+        parse_data_campo("data_start", &self.data_start)
+    }
+
+    /// Data di fine pubblicazione (`data_stop`), interpretata come `data_start_parsed`.
+    pub fn data_stop_parsed(&self) -> Result<Option<NaiveDate>, SpaggiariError> {
+        parse_data_campo("data_stop", &self.data_stop)
+    }
+
+    /// Data dell'ultima modifica (`modificato`), interpretata come `data_start_parsed`.
+    ///
+    /// Un eventuale orario che segue la data viene ignorato.
+    pub fn modificato_parsed(&self) -> Result<Option<NaiveDate>, SpaggiariError> {
+        parse_data_campo("modificato", &self.modificato)
+    }
+
+    /// Data dell'evento associato (`evento_data`), interpretata come `data_start_parsed`.
+    pub fn evento_data_parsed(&self) -> Result<Option<NaiveDate>, SpaggiariError> {
+        parse_data_campo("evento_data", &self.evento_data)
+    }
+
+    /// Hash SHA-256 (esadecimale) dei campi che descrivono il contenuto della circolare.
+    ///
+    /// Considera `titolo`, `testo`, `nome_file`, `data_start` e `data_stop`: a differenza di
+    /// `modificato`, cambia solo quando cambia il contenuto e può essere usato per la sincronizzazione.
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        for campo in [&self.titolo, &self.testo, self.nome_file.as_deref().unwrap_or(""), &self.data_start, &self.data_stop] {
+            // La lunghezza evita che campi diversi producano la stessa concatenazione
+            hasher.update((campo.len() as u64).to_le_bytes());
+            hasher.update(campo.as_bytes());
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Riepilogo su una riga, come la rappresentazione `Display`: `[codice] titolo (tipo_com_desc, data_start)`
+    pub fn summary(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Circolare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {} ({}, {})", self.codice, self.titolo.trim(), self.tipo_com_desc, self.data_start)
+    }
+}
+
+/// Risposta inviata dall'utente a una circolare
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RispostaData {
+    /// Testo della risposta (`testo_risp`)
+    pub testo: Option<String>,
+    /// Riferimento al file inviato come risposta (`file_risp`)
+    pub file: Option<String>,
+}
+
+// I flag del portale sono stringhe: "1" (o "S") indica che il flag è attivo
+fn flag_attivo(value: &str) -> bool {
+    matches!(value.trim(), "1" | "S" | "s" | "true")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Bacheca {
     pub read: Vec<Circolare>,
     pub msg_new: Option<Vec<Circolare>>,
+    /// Campi di primo livello non previsti, presenti se il formato della risposta è cambiato
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+impl Bacheca {
+    /// Itera su tutte le circolari della bacheca: prima le lette (`read`), poi le nuove (`msg_new`)
+    pub fn all(&self) -> impl Iterator<Item = &Circolare> {
+        self.read.iter().chain(self.unread())
+    }
+
+    /// Le circolari non ancora lette (`msg_new`), vuoto se il portale non le restituisce
+    pub fn unread(&self) -> &[Circolare] {
+        self.msg_new.as_deref().unwrap_or(&[])
+    }
+
+    /// Numero di circolari non ancora lette
+    pub fn unread_count(&self) -> usize {
+        self.unread().len()
+    }
+
+    /// Restituisce le circolari (lette e nuove) del tipo indicato.
+    ///
+    /// Il tipo viene confrontato, senza distinzione di maiuscole, sia con `tipo_com`
+    /// che con `tipo_com_filtro`.
+    pub fn filter_by_tipo(&self, tipo: &str) -> Vec<&Circolare> {
+        let tipo = tipo.trim();
+        self.all()
+            .filter(|c| c.tipo_com.trim().eq_ignore_ascii_case(tipo) || c.tipo_com_filtro.trim().eq_ignore_ascii_case(tipo))
+            .collect()
+    }
+
+    /// Restituisce, in ordine alfabetico, i campi di primo livello della risposta non previsti dalla libreria.
+    ///
+    /// Un elenco non vuoto indica che il portale ha cambiato il formato della bacheca.
+    pub fn campi_sconosciuti(&self) -> Vec<&str> {
+        let mut campi: Vec<&str> = self.extra.keys().map(String::as_str).collect();
+        campi.sort_unstable();
+        campi
+    }
+
+    /// Restituisce le categorie (`tipo_com_desc`) presenti in bacheca, senza duplicati e ordinate.
+    ///
+    /// Considera sia le circolari lette che quelle nuove, escludendo le descrizioni vuote.
+    pub fn categorie(&self) -> Vec<String> {
+        let categorie: BTreeSet<&str> = self.all().map(|c| c.tipo_com_desc.trim()).filter(|c| !c.is_empty()).collect();
+        categorie.into_iter().map(str::to_string).collect()
+    }
+
+    /// Restituisce le circolari (lette e nuove) che richiedono il caricamento di un file
+    /// a cui l'utente non ha ancora risposto (`file_risp` vuoto).
+    pub fn richiedono_upload(&self) -> Vec<&Circolare> {
+        self.all().filter(|c| c.richiede_upload() && c.file_risp.as_deref().is_none_or(|f| f.trim().is_empty())).collect()
+    }
+
+    /// Rappresentazione testuale stabile della bacheca, utile per i test con file di riferimento.
+    ///
+    /// Per ciascuna sezione (`read` e `msg_new`) elenca codice, id e titolo delle circolari,
+    /// ordinate per codice e id: due bacheche con le stesse circolari producono lo stesso
+    /// snapshot indipendentemente dall'ordine restituito dal portale.
+    pub fn snapshot(&self) -> String {
+        let mut out = String::new();
+        for (sezione, circolari) in [("read", self.read.as_slice()), ("msg_new", self.unread())] {
+            let mut righe: Vec<&Circolare> = circolari.iter().collect();
+            righe.sort_by(|a, b| (a.codice, &a.id).cmp(&(b.codice, &b.id)));
+            out.push_str(&format!("[{}]\n", sezione));
+            for c in righe {
+                out.push_str(&format!("{}\t{}\t{}\n", c.codice, c.id, c.titolo));
+            }
+        }
+        out
+    }
+
+    /// Cerca tra le circolari (lette e nuove) quella con il `codice` indicato
+    pub fn find_by_codice(&self, codice: i32) -> Option<&Circolare> {
+        self.all().find(|c| c.codice == codice)
+    }
+
+    /// Conta le circolari (lette e nuove) per anno e mese di `data_start`.
+    ///
+    /// Le circolari con data non interpretabile non vengono conteggiate qui:
+    /// il loro numero è restituito da `date_non_valide`.
+    pub fn conteggio_mensile(&self) -> BTreeMap<(i32, u32), usize> {
+        let mut conteggio = BTreeMap::new();
+        for data in self.all().filter_map(|c| parse_data(&c.data_start)) {
+            *conteggio.entry((data.year(), data.month())).or_insert(0) += 1;
+        }
+        conteggio
+    }
+
+    /// Restituisce le circolari in attesa di un'azione la cui scadenza (`data_stop`) cade entro `within` da `now`.
+    ///
+    /// Sono escluse le circolari a cui l'utente ha già risposto e quelle già scadute.
+    /// La scadenza è considerata alla fine della giornata indicata da `data_stop`.
+    pub fn scadenze_imminenti(&self, within: Duration, now: NaiveDateTime) -> Vec<&Circolare> {
+        let limite = now + within;
+        self.all()
+            .filter(|c| c.richiede_azione() && !c.azione_completata())
+            .filter(|c| {
+                parse_data(&c.data_stop)
+                    .and_then(|data| data.and_hms_opt(23, 59, 59))
+                    .is_some_and(|scadenza| scadenza >= now && scadenza <= limite)
+            })
+            .collect()
+    }
+
+    /// Numero di circolari il cui `data_start` non è una data valida
+    pub fn date_non_valide(&self) -> usize {
+        self.all().filter(|c| parse_data(&c.data_start).is_none()).count()
+    }
+
+    /// Esporta le circolari (prima le lette, poi le nuove) in formato CSV.
+    ///
+    /// Colonne: `stato` (`letta` o `nuova`), `codice`, `id`, `titolo`, `data_start`,
+    /// `data_stop`, `tipo_com_desc` e `nome_file`.
+    pub fn to_csv<W: std::io::Write>(&self, writer: W) -> Result<(), SpaggiariError> {
+        let mut csv = csv::Writer::from_writer(writer);
+        csv.write_record(["stato", "codice", "id", "titolo", "data_start", "data_stop", "tipo_com_desc", "nome_file"])
+            .map_err(std::io::Error::from)?;
+        for (stato, circolari) in [("letta", self.read.as_slice()), ("nuova", self.unread())] {
+            for c in circolari {
+                let codice = c.codice.to_string();
+                csv.write_record([stato, &codice, &c.id, &c.titolo, &c.data_start, &c.data_stop, &c.tipo_com_desc, c.nome_file.as_deref().unwrap_or("")])
+                    .map_err(std::io::Error::from)?;
+            }
+        }
+        csv.flush()?;
+        Ok(())
+    }
+
+    /// Come `to_csv`, scrivendo nel file indicato (creato o sovrascritto)
+    pub fn export_csv_file(&self, path: impl AsRef<std::path::Path>) -> Result<(), SpaggiariError> {
+        self.to_csv(std::io::BufWriter::new(std::fs::File::create(path)?))
+    }
 }
 
-/// Estrae gli ID delle comunicazioni e degli allegati dall'HTML.
+// Interpreta le date del portale nei formati `YYYY-MM-DD` e `DD-MM-YYYY`,
+// ignorando un eventuale orario che segue la data
+fn parse_data(value: &str) -> Option<NaiveDate> {
+    let data = value.trim().get(..10)?;
+    NaiveDate::parse_from_str(data, "%Y-%m-%d").or_else(|_| NaiveDate::parse_from_str(data, "%d-%m-%Y")).ok()
+}
+
+// Come `parse_data`, distinguendo il campo vuoto da una data non valida
+fn parse_data_campo(campo: &str, value: &str) -> Result<Option<NaiveDate>, SpaggiariError> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_data(value).map(Some).ok_or_else(|| SpaggiariError::ParseError {
+        details: format!("Data non valida in {}: '{}'", campo, value),
+    })
+}
+
+/// Estrae gli allegati scaricabili (`a.dwl_allegato`) dall'HTML.
+///
+/// Il nome del file è preso dall'attributo `title` o, in sua assenza, dal testo del link.
 ///
 /// # Argomenti
 ///
@@ -78,8 +401,8 @@ pub struct Bacheca {
 ///
 /// # Restituisce
 ///
-/// * `Ok(Vec<(String, String)>)` contenente coppie di (comunicazione_id, allegato_id).
-pub fn extract_allegati(html: &str) -> Result<Vec<(String, String)>, SpaggiariError> {
+/// * `Ok(Vec<Allegato>)` con gli ID della comunicazione e dell'allegato e l'eventuale nome.
+pub fn extract_allegati(html: &str) -> Result<Vec<Allegato>, SpaggiariError> {
     let document = Html::parse_document(html);
     let selector = Selector::parse("a.dwl_allegato").map_err(|e| SpaggiariError::ParseError {
         details: format!("Errore nel parsing del selettore: {}", e),
@@ -89,12 +412,70 @@ pub fn extract_allegati(html: &str) -> Result<Vec<(String, String)>, SpaggiariEr
     for element in document.select(&selector) {
         let comunicazione_id = element.value().attr("comunicazione_id").unwrap_or("").to_string();
         let allegato_id = element.value().attr("allegato_id").unwrap_or("").to_string();
-        allegati.push((comunicazione_id, allegato_id));
+        let testo = element.text().collect::<String>();
+        let nome = [element.value().attr("title").unwrap_or(""), testo.as_str()]
+            .into_iter()
+            .map(str::trim)
+            .find(|n| !n.is_empty())
+            .map(str::to_string);
+        allegati.push(Allegato {
+            comunicazione_id,
+            allegato_id,
+            nome,
+            incorporato: None,
+        });
+    }
+
+    Ok(allegati)
+}
+
+/// Estrae gli allegati incorporati nell'HTML come `data:` URI codificati in base64.
+///
+/// Vengono considerati i link (`a[href]`) e gli elementi `embed`, `object` e `iframe`
+/// il cui riferimento inizia con `data:`. Gli URI non validi vengono ignorati.
+///
+/// # Argomenti
+///
+/// * `html` - Il contenuto HTML da analizzare.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<AllegatoIncorporato>)` con il contenuto già decodificato.
+pub fn extract_allegati_incorporati(html: &str) -> Result<Vec<AllegatoIncorporato>, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse(r#"a[href^="data:"], embed[src^="data:"], iframe[src^="data:"], object[data^="data:"]"#).map_err(|e| SpaggiariError::ParseError {
+        details: format!("Errore nel parsing del selettore: {}", e),
+    })?;
+
+    let mut allegati = Vec::new();
+    for element in document.select(&selector) {
+        let attrs = element.value();
+        let uri = attrs.attr("href").or_else(|| attrs.attr("src")).or_else(|| attrs.attr("data")).unwrap_or("");
+
+        match decode_data_uri(uri) {
+            Some((mime, contenuto)) => {
+                let nome = attrs
+                    .attr("download")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| format!("allegato_incorporato_{}.{}", allegati.len() + 1, mime.rsplit('/').next().unwrap_or("bin")));
+                allegati.push(AllegatoIncorporato { nome, mime, contenuto });
+            }
+            None => warn!("data: URI non valido, allegato incorporato ignorato"),
+        }
     }
 
     Ok(allegati)
 }
 
+// Decodifica un URI `data:<mime>;base64,<dati>` restituendo mime e contenuto
+fn decode_data_uri(uri: &str) -> Option<(String, Vec<u8>)> {
+    let (header, data) = uri.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.strip_suffix(";base64")?;
+    let mime = if mime.is_empty() { "application/octet-stream" } else { mime };
+    let contenuto = BASE64_STANDARD.decode(data.trim()).ok()?;
+    Some((mime.to_string(), contenuto))
+}
+
 /// Scarica un singolo file e ne restituisce il contenuto in memoria.
 ///
 /// # Argomenti
@@ -102,265 +483,3106 @@ pub fn extract_allegati(html: &str) -> Result<Vec<(String, String)>, SpaggiariEr
 /// * `client` - Il client HTTP.
 /// * `url` - L'URL del file da scaricare.
 /// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+///
+/// # Restituisce
+///
+/// * `Ok((String, Vec<u8>))` contenente il nome del file e il suo contenuto in bytes.
+pub async fn download_file_bytes(client: &Client, url: &str, session_id: &str, webidentity: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
+    download_file_bytes_via(&Http::new(client.clone()), url, session_id, webidentity).await
+}
+
+// Come `download_file_bytes`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_bytes_via(http: &Http, url: &str, session_id: &str, webidentity: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
+    download_file_bytes_with_progress_via(http, url, session_id, webidentity, |_, _| {}).await
+}
+
+// Spazio massimo riservato in anticipo per i download in memoria: `Content-Length` arriva dal server
+// e non può decidere da solo quanta memoria allocare
+const MAX_PREALLOCAZIONE: u64 = 8 * 1024 * 1024;
+
+/// Scarica un singolo file in memoria, riportando l'avanzamento a ogni blocco ricevuto.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `callback` - Funzione richiamata con (byte scaricati finora, `Content-Length` se indicato dal server).
 ///
 /// # Restituisce
 ///
 /// * `Ok((String, Vec<u8>))` contenente il nome del file e il suo contenuto in bytes.
-pub async fn download_file_bytes(client: &Client, url: &str, session_id: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
-    let response = client.get(url).header("Cookie", format!("PHPSESSID={}; webidentity=G13070983V", session_id)).send().await?;
+pub async fn download_file_bytes_with_progress<F>(client: &Client, url: &str, session_id: &str, webidentity: &str, callback: F) -> Result<(String, Vec<u8>), SpaggiariError>
+where
+    F: Fn(u64, Option<u64>),
+{
+    download_file_bytes_with_progress_via(&Http::new(client.clone()), url, session_id, webidentity, callback).await
+}
+
+// Come `download_file_bytes_with_progress`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_bytes_with_progress_via<F>(http: &Http, url: &str, session_id: &str, webidentity: &str, callback: F) -> Result<(String, Vec<u8>), SpaggiariError>
+where
+    F: Fn(u64, Option<u64>),
+{
+    let response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
 
     if response.status().is_success() {
         // Estrai filename da Content-Disposition
-        let content_disposition = response.headers().get("content-disposition").and_then(|v| v.to_str().ok()).unwrap_or("");
-        let filename = extract_filename_from_disposition(content_disposition).unwrap_or_else(|| "file_sconosciuto".to_string());
+        let filename = response_filename(&response);
+        let total = response.content_length();
 
-        // Scarica il contenuto come bytes
-        let bytes = response.bytes().await?;
-        debug!("📥 File scaricato in memoria: {} ({} bytes)", filename, bytes.len());
+        // Scarica il contenuto un blocco alla volta
+        let mut content = Vec::with_capacity(total.map_or(0, |t| t.min(MAX_PREALLOCAZIONE)) as usize);
+        let mut body = response.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            content.extend_from_slice(&chunk?);
+            callback(content.len() as u64, total);
+        }
+        debug!(filename = %filename, bytes = content.len(), "file scaricato in memoria");
 
-        Ok((filename, bytes.to_vec()))
+        Ok((filename, content))
     } else {
-        error!("❌ Download fallito per {}: Status {}", url, response.status());
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
         Err(SpaggiariError::ParseError {
             details: format!("Download fallito: {}", response.status()),
         })
     }
 }
 
-/// Scarica un singolo file e lo salva nel percorso specificato.
+/// Scarica un singolo file passando il contenuto a `sink` man mano che arriva.
+///
+/// Il file non viene mai tenuto interamente in memoria, quindi è adatto a inoltrare
+/// gli allegati verso un'altra destinazione (ad esempio un object storage).
 ///
 /// # Argomenti
 ///
 /// * `client` - Il client HTTP.
 /// * `url` - L'URL del file da scaricare.
 /// * `session_id` - L'ID di sessione.
-/// * `destination_path` - La cartella di destinazione.
+/// * `webidentity` - L'identità web.
+/// * `sink` - Funzione richiamata per ogni blocco di byte ricevuto.
 ///
 /// # Restituisce
 ///
-/// * `Ok(String)` contenente il percorso completo del file salvato.
-pub async fn download_file(client: &Client, url: &str, session_id: &str, destination_path: &str) -> Result<String, SpaggiariError> {
-    // Controlla se il file già esiste
-    if std::path::Path::new(destination_path).exists() {
-        debug!("📁 File già esistente, skip download: {}", destination_path);
-        return Ok(destination_path.to_string());
-    }
+/// * `Ok(String)` contenente il nome del file indicato dal server.
+pub async fn download_file_stream<F, E>(client: &Client, url: &str, session_id: &str, webidentity: &str, sink: F) -> Result<String, SpaggiariError>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    download_file_stream_via(&Http::new(client.clone()), url, session_id, webidentity, sink).await
+}
 
-    let response = client
-        .get(url)
-        .header("Cookie", format!("PHPSESSID={}; webidentity=G13070983V", session_id)) //TODO get from args
-        .send()
-        .await?;
+// Come `download_file_stream`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_stream_via<F, E>(http: &Http, url: &str, session_id: &str, webidentity: &str, mut sink: F) -> Result<String, SpaggiariError>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    let mut response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
 
     if response.status().is_success() {
-        // Estrai filename da Content-Disposition
-        let content_disposition = response.headers().get("content-disposition").and_then(|v| v.to_str().ok()).unwrap_or("");
-        let filename = extract_filename_from_disposition(content_disposition).unwrap_or_else(|| "file_sconosciuto".to_string());
+        let filename = response_filename(&response);
 
-        let filepath = format!("{}/{}", destination_path, filename); // destination_path è una directory, aggiungi il filename
-                                                                     // Assicurati che la directory esista
-        if let Some(parent) = std::path::Path::new(&filepath).parent() {
-            std::fs::create_dir_all(parent)?;
+        let mut total = 0u64;
+        while let Some(chunk) = response.chunk().await? {
+            total += chunk.len() as u64;
+            sink(&chunk).map_err(|e| SpaggiariError::Generic(format!("Errore nella scrittura di {}: {}", filename, e)))?;
         }
+        debug!(filename = %filename, bytes = total, "file inoltrato");
 
-        let mut file = File::create(&filepath).await?;
-        let bytes = response.bytes().await?;
-        file.write_all(&bytes).await?;
-        debug!("📥 File scaricato: {}", filepath);
-        Ok(filepath)
+        Ok(filename)
     } else {
-        error!("❌ Download fallito per {}: Status {}", url, response.status());
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
         Err(SpaggiariError::ParseError {
             details: format!("Download fallito: {}", response.status()),
         })
     }
 }
 
-// Funzione helper per estrarre il filename da Content-Disposition
-fn extract_filename_from_disposition(disposition: &str) -> Option<String> {
-    let re = Regex::new(r#"filename=([^;]+)"#).ok()?;
-    re.captures(disposition)?
-        .get(1)?
-        .as_str()
-        .trim_matches('"') // Rimuovi eventuali virgolette
-        .to_string()
-        .into()
-}
-
-/// Scarica tutti gli allegati specificati nella cartella di destinazione.
-///
-/// # Argomenti
+/// Scarica un singolo file e lo salva nel percorso specificato.
 ///
-/// * `client` - Il client HTTP.
-/// * `session_id` - L'ID di sessione.
-/// * `allegati` - Una slice di `Allegato` da scaricare.
-/// * `destination_path` - Il percorso della cartella dove salvare i file.
-pub async fn download_allegati(client: &Client, session_id: &str, allegati: &[Allegato], destination_path: &str) -> Result<(), SpaggiariError> {
-    for allegato in allegati {
-        let download_url = format!("https://web.spaggiari.eu/sif/app/default/bacheca_personale.php?action=file_download&com_id={}", allegato.allegato_id);
-        download_file(client, &download_url, session_id, destination_path).await?;
-    }
-    Ok(())
-}
-
-/// Scarica tutti gli allegati specificati e restituisce il loro contenuto in memoria.
+/// Il contenuto viene scritto su disco a blocchi man mano che arriva, senza tenerlo in memoria.
 ///
 /// # Argomenti
 ///
 /// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
 /// * `session_id` - L'ID di sessione.
-/// * `allegati` - Un vettore di `Allegato` da scaricare.
+/// * `webidentity` - L'identità web.
+/// * `destination_path` - La cartella di destinazione.
 ///
 /// # Restituisce
 ///
-/// * `Ok(Vec<(String, Vec<u8>)>)` contenente coppie di (nome file, contenuto).
-pub async fn download_allegati_bytes(client: &Client, session_id: &str, allegati: Vec<Allegato>) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
-    let mut results = Vec::new();
-
-    for allegato in allegati {
-        let download_url = format!("https://web.spaggiari.eu/sif/app/default/bacheca_personale.php?action=file_download&com_id={}", allegato.allegato_id);
+/// * `Ok(String)` contenente il percorso completo del file salvato.
+/// * `Err(SpaggiariError::NetworkError)` se il file ricevuto è più corto di quanto indicato da `Content-Length`.
+pub async fn download_file(client: &Client, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<String, SpaggiariError> {
+    download_file_via(&Http::new(client.clone()), url, session_id, webidentity, destination_path).await
+}
 
-        match download_file_bytes(client, &download_url, session_id).await {
-            Ok((filename, content)) => {
-                results.push((filename, content));
-            }
-            Err(e) => {
-                error!("❌ Errore durante il download dell'allegato {}: {}", allegato.allegato_id, e);
-                // Continua con gli altri allegati anche in caso di errore
-            }
-        }
-    }
+// Come `download_file`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_via(http: &Http, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<String, SpaggiariError> {
+    let result = download_file_checked_via(http, url, session_id, webidentity, destination_path).await?;
+    Ok(result.path.display().to_string())
+}
 
-    Ok(results)
+/// Risultato di un download salvato su disco
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadResult {
+    /// Percorso completo del file salvato
+    pub path: PathBuf,
+    /// Byte scritti nel file
+    pub bytes: u64,
 }
 
-/// Recupera la bacheca personale dell'utente.
+/// Come [`download_file`], restituendo anche il numero di byte scritti.
+///
+/// I byte scritti vengono confrontati con l'header `Content-Length`, se presente: un download
+/// troncato restituisce un errore e il file parziale viene rimosso. Se il file esiste già non
+/// viene scaricato e `bytes` riporta la sua dimensione su disco.
 ///
 /// # Argomenti
 ///
 /// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
 /// * `session_id` - L'ID di sessione.
 /// * `webidentity` - L'identità web.
+/// * `destination_path` - La cartella di destinazione.
 ///
 /// # Restituisce
 ///
-/// * `Ok(Bacheca)` contenente le circolari lette e nuove.
-pub async fn get_backeca(client: &Client, session_id: &str, webidentity: &str) -> Result<Bacheca, SpaggiariError> {
-    let response = client
-        .get(URL_BACHECA)
-        .query(&[("action", "get_comunicazioni"), ("ncna", "1")]) // Aggiunti i form data come query parameters
-        .header("Cookie", format!("PHPSESSID={}; webidentity={}", session_id, webidentity))
-        .send()
-        .await?;
+/// * `Ok(DownloadResult)` con il percorso del file e i byte scritti.
+/// * `Err(SpaggiariError::NetworkError)` se i byte ricevuti non corrispondono a `Content-Length`.
+pub async fn download_file_checked(client: &Client, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<DownloadResult, SpaggiariError> {
+    download_file_checked_via(&Http::new(client.clone()), url, session_id, webidentity, destination_path).await
+}
 
-    let status = response.status();
+// Come `download_file_checked`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_checked_via(http: &Http, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<DownloadResult, SpaggiariError> {
+    // Controlla se il file già esiste (destination_path può essere anche il percorso di un file)
+    if std::path::Path::new(destination_path).is_file() {
+        debug!(path = destination_path, "file già esistente, download saltato");
+        return Ok(DownloadResult {
+            path: PathBuf::from(destination_path),
+            bytes: std::fs::metadata(destination_path)?.len(),
+        });
+    }
 
-    debug!("📊 Risposta bacheca - Status: {}", status);
+    let response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
 
-    if status.is_success() {
-        let text = response.text().await?;
+    if response.status().is_success() {
+        // Estrai filename da Content-Disposition
+        let filename = response_filename(&response);
 
-        //println!("{}", text);
-        match serde_json::from_str::<Bacheca>(&text) {
-            Ok(bacheca) => {
-                // Chiama la funzione separata per scrivere il CSV
-                Ok(bacheca)
-            }
-            Err(e) => {
-                error!("Deserialize error {}", e.to_string());
-                Err(e.into())
-            }
+        let filepath = format!("{}/{}", destination_path, filename); // destination_path è una directory, aggiungi il filename
+                                                                     // Assicurati che la directory esista
+        if let Some(parent) = std::path::Path::new(&filepath).parent() {
+            std::fs::create_dir_all(parent)?;
         }
+
+        let scritti = scrivi_risposta_verificata(response, &filepath).await?;
+        debug!(path = %filepath, bytes = scritti, "file scaricato");
+        Ok(DownloadResult {
+            path: PathBuf::from(filepath),
+            bytes: scritti,
+        })
     } else {
-        error!("❌ Il token non sembra funzionare. Status: {}", status);
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
         Err(SpaggiariError::ParseError {
-            details: format!("Il token non sembra funzionare"),
+            details: format!("Download fallito: {}", response.status()),
         })
     }
 }
 
-/// Estrae il testo di una comunicazione dall'HTML.
+/// Esito di un download che può essere saltato se il file è già presente
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadStatus {
+    /// Il file è stato scaricato e salvato nel percorso indicato
+    Scaricato(String),
+    /// Il file era già presente con la dimensione attesa e non è stato riscritto
+    GiaPresente(String),
+}
+
+impl DownloadStatus {
+    /// Percorso del file, scaricato o già presente
+    pub fn path(&self) -> &str {
+        match self {
+            DownloadStatus::Scaricato(path) | DownloadStatus::GiaPresente(path) => path,
+        }
+    }
+
+    /// `true` se il download è stato saltato perché il file era già presente
+    pub fn is_skipped(&self) -> bool {
+        matches!(self, DownloadStatus::GiaPresente(_))
+    }
+}
+
+/// Scarica un singolo file nella cartella indicata, saltandolo se è già presente.
+///
+/// Nome e dimensione del file vengono letti prima con una richiesta HEAD, come in
+/// [`allegato_metadata`]: se nella cartella esiste già un file con quel nome e quella dimensione
+/// il contenuto non viene richiesto. Se il server non indica la dimensione il file viene sempre
+/// scaricato; un download troncato restituisce un errore e il file parziale viene rimosso.
 ///
 /// # Argomenti
 ///
-/// * `html` - Il contenuto HTML della pagina della comunicazione.
+/// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `destination_path` - La cartella di destinazione.
 ///
 /// # Restituisce
 ///
-/// * `Ok(String)` contenente il testo della comunicazione.
-pub fn extract_testo_comunicazione(html: &str) -> Result<String, SpaggiariError> {
-    let document = Html::parse_document(html);
-    let selector = Selector::parse("div.comunicazione_testo").map_err(|e| SpaggiariError::ParseError {
-        details: format!("Errore nel parsing del selettore: {}", e),
-    })?;
+/// * `Ok(DownloadStatus)` con il percorso del file e l'indicazione se è stato saltato.
+pub async fn download_file_skip_existing(client: &Client, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<DownloadStatus, SpaggiariError> {
+    download_file_skip_existing_via(&Http::new(client.clone()), url, session_id, webidentity, destination_path).await
+}
 
-    if let Some(element) = document.select(&selector).next() {
-        let testo = element.text().collect::<Vec<_>>().join(" ");
-        Ok(testo)
-    } else {
-        Ok("".to_string()) // Se non trovato, restituisci stringa vuota
+// Come `download_file_skip_existing`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_skip_existing_via(http: &Http, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<DownloadStatus, SpaggiariError> {
+    // Nome e dimensione con una richiesta HEAD, come `allegato_metadata`, prima di scaricare il contenuto
+    let meta = metadata_url_via(http, url, session_id, webidentity).await?;
+    let filepath = format!("{}/{}", destination_path, meta.filename);
+    let esistente = std::fs::metadata(&filepath).ok().filter(|m| m.is_file()).map(|m| m.len());
+    if esistente.is_some() && esistente == meta.content_length {
+        debug!(path = %filepath, "file già presente con la dimensione attesa, download saltato");
+        return Ok(DownloadStatus::GiaPresente(filepath));
     }
-}
 
-pub struct Allegato {
-    pub comunicazione_id: String,
-    pub allegato_id: String,
-}
+    let response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
+    if !response.status().is_success() {
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
+        return Err(SpaggiariError::ParseError {
+            details: format!("Download fallito: {}", response.status()),
+        });
+    }
 
-pub struct Comunicazione {
-    pub testo: String,
-    pub allegati: Vec<Allegato>,
+    let filepath = format!("{}/{}", destination_path, response_filename(&response));
+    std::fs::create_dir_all(destination_path)?;
+    let scritti = scrivi_risposta_verificata(response, &filepath).await?;
+    debug!(path = %filepath, bytes = scritti, "file scaricato");
+    Ok(DownloadStatus::Scaricato(filepath))
 }
 
-/// Recupera i dettagli di una specifica comunicazione.
+/// Scarica un singolo file e lo salva esattamente nel percorso indicato.
+///
+/// A differenza di `download_file`, il nome del file fornito dal server viene ignorato
+/// e un eventuale file esistente viene sovrascritto.
 ///
 /// # Argomenti
 ///
 /// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
 /// * `session_id` - L'ID di sessione.
-/// * `comm_id` - L'ID della comunicazione.
 /// * `webidentity` - L'identità web.
+/// * `exact_path` - Il percorso completo del file da creare.
 ///
 /// # Restituisce
 ///
-/// * `Ok(Comunicazione)` contenente il testo e gli allegati della comunicazione.
-pub async fn get_comunicazioni(client: &Client, session_id: &str, comm_id: &str, webidentity: &str) -> Result<Comunicazione, SpaggiariError> {
-    let response = client
-        .get(URL_COMUNICAZIONI)
-        .query(&[("action", "risposta_com"), ("com_id", comm_id)]) // Aggiunti i form data come query parameters
-        .header("Cookie", format!("PHPSESSID={}; webidentity={}", session_id, webidentity)) //TODO get from args
-        .send()
-        .await?;
+/// * `Ok(String)` contenente il percorso del file salvato.
+pub async fn download_file_to(client: &Client, url: &str, session_id: &str, webidentity: &str, exact_path: &str) -> Result<String, SpaggiariError> {
+    download_file_to_via(&Http::new(client.clone()), url, session_id, webidentity, exact_path).await
+}
 
-    let status = response.status();
+// Come `download_file_to`, inviando le richieste tramite `http`
+pub(crate) async fn download_file_to_via(http: &Http, url: &str, session_id: &str, webidentity: &str, exact_path: &str) -> Result<String, SpaggiariError> {
+    let response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
 
-    debug!("📊 Risposta bacheca - Status: {}", status);
+    if response.status().is_success() {
+        if let Some(parent) = std::path::Path::new(exact_path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
 
-    if status.is_success() {
-        let text = response.text().await?;
-        //println!("{}", text);
-
-        // Estrai gli allegati dal body HTML
-        let allegati = extract_allegati(&text)?;
-
-        // Estrai il testo della comunicazione
-        let testo = extract_testo_comunicazione(&text)?;
-        debug!("📝 Testo comunicazione: {}", testo);
-
-        Ok(Comunicazione {
-            testo,
-            allegati: allegati
-                .into_iter()
-                .map(|(com_id, all_id)| Allegato {
-                    comunicazione_id: com_id,
-                    allegato_id: all_id,
-                })
-                .collect(),
-        })
+        let scritti = scrivi_risposta(response, exact_path).await?;
+        debug!(path = %exact_path, bytes = scritti, "file scaricato");
+        Ok(exact_path.to_string())
     } else {
-        error!("❌ Il token non sembra funzionare. Status: {}", status);
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
         Err(SpaggiariError::ParseError {
-            details: format!("Il token non sembra funzionare"),
+            details: format!("Download fallito: {}", response.status()),
         })
     }
 }
+
+/// Scarica un singolo file scrivendolo su disco man mano che arriva.
+///
+/// Il contenuto non viene mai tenuto interamente in memoria, quindi è adatto agli allegati
+/// di grandi dimensioni. Equivale a [`download_file`], che scrive già il file a blocchi: un download
+/// troncato restituisce un errore e il file parziale viene rimosso.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `url` - L'URL del file da scaricare.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `destination_path` - La cartella di destinazione.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` contenente il percorso completo del file salvato.
+pub async fn download_file_streaming(client: &Client, url: &str, session_id: &str, webidentity: &str, destination_path: &str) -> Result<String, SpaggiariError> {
+    download_file_via(&Http::new(client.clone()), url, session_id, webidentity, destination_path).await
+}
+
+// Scrive il body della risposta nel file indicato un blocco alla volta, restituendo i byte scritti
+async fn scrivi_risposta(response: Risposta, filepath: &str) -> Result<u64, SpaggiariError> {
+    let mut file = File::create(filepath).await?;
+    let mut body = response.bytes_stream();
+    let mut scritti = 0u64;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        scritti += chunk.len() as u64;
+    }
+    file.flush().await?;
+    Ok(scritti)
+}
+
+// Come `scrivi_risposta`, verificando che i byte scritti corrispondano a `Content-Length`.
+// In caso di download incompleto il file parziale viene rimosso
+async fn scrivi_risposta_verificata(response: Risposta, filepath: &str) -> Result<u64, SpaggiariError> {
+    let attesi = response.content_length();
+    let esito = match scrivi_risposta(response, filepath).await {
+        Ok(scritti) if attesi.is_some_and(|attesi| attesi != scritti) => Err(SpaggiariError::NetworkError(format!(
+            "Download incompleto di {}: {} byte ricevuti su {} attesi",
+            filepath,
+            scritti,
+            attesi.unwrap_or_default()
+        ))),
+        Err(SpaggiariError::HttpError(e)) if e.is_body() || e.is_decode() => Err(SpaggiariError::NetworkError(format!(
+            "Download incompleto di {}: {} (attesi {} byte)",
+            filepath,
+            e,
+            attesi.map_or_else(|| "?".to_string(), |n| n.to_string())
+        ))),
+        esito => esito,
+    };
+
+    if let Err(e) = &esito {
+        debug!(errore = %e, "download interrotto, file parziale rimosso");
+        let _ = tokio::fs::remove_file(filepath).await;
+    }
+    esito
+}
+
+// Nome del file indicato dal server nell'header Content-Disposition della risposta
+fn response_filename(response: &Response) -> String {
+    filename_da_headers(response.headers())
+}
+
+// Come `response_filename`, a partire dagli header (condivisa con l'API bloccante)
+pub(crate) fn filename_da_headers(headers: &HeaderMap) -> String {
+    let content_disposition = headers.get("content-disposition").and_then(|v| v.to_str().ok()).unwrap_or("");
+    extract_filename_from_disposition(content_disposition).unwrap_or_else(|| "file_sconosciuto".to_string())
+}
+
+// Funzione helper per estrarre il filename da Content-Disposition.
+// Preferisce la forma RFC 5987 (`filename*=UTF-8''nome%20file.pdf`) e ripiega su `filename=`.
+// Il nome viene ripulito con `sanitize_filename` prima di essere usato come percorso
+fn extract_filename_from_disposition(disposition: &str) -> Option<String> {
+    let re_esteso = Regex::new(r#"(?i)filename\*\s*=\s*"?([^'";]*)'[^']*'([^;"]+)"#).ok()?;
+    if let Some(nome) = re_esteso.captures(disposition).and_then(|c| decode_rfc5987(&c[1], c[2].trim())) {
+        return Some(sanitize_filename(&nome));
+    }
+
+    let re = Regex::new(r#"filename=([^;]+)"#).ok()?;
+    let nome = re.captures(disposition)?.get(1)?.as_str().trim().trim_matches('"'); // Rimuovi eventuali virgolette
+    Some(sanitize_filename(nome))
+}
+
+// Riduce il nome indicato dal portale al solo nome del file, così che non possa uscire dalla
+// cartella di destinazione (es. `../../.bashrc`): toglie cartelle, separatori, NUL e punti iniziali
+pub(crate) fn sanitize_filename(nome: &str) -> String {
+    let senza_nul: String = nome.chars().filter(|&c| c != '\0').collect();
+    let base = senza_nul.rsplit(['/', '\\']).next().unwrap_or_default();
+    match base.trim().trim_start_matches('.') {
+        "" => "file_sconosciuto".to_string(),
+        pulito => pulito.to_string(),
+    }
+}
+
+// Decodifica il valore percent-encoded di un parametro RFC 5987 nel charset indicato
+fn decode_rfc5987(charset: &str, value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut input = value.bytes();
+    while let Some(b) = input.next() {
+        if b == b'%' {
+            let hex = [input.next()?, input.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+
+    if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        String::from_utf8(bytes).ok()
+    }
+}
+
+/// Numero predefinito di allegati scaricati contemporaneamente
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// Scarica tutti gli allegati specificati nella cartella di destinazione.
+///
+/// Gli allegati vengono scaricati in parallelo, al massimo `DEFAULT_CONCURRENCY` alla volta.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+pub async fn download_allegati(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], destination_path: &str) -> Result<(), SpaggiariError> {
+    download_allegati_concurrent(client, session_id, webidentity, allegati, destination_path, DEFAULT_CONCURRENCY).await
+}
+
+/// Scarica tutti gli allegati specificati nella cartella di destinazione, con al massimo `concurrency` download in parallelo.
+///
+/// Il primo download fallito interrompe gli altri e viene restituito come errore.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+/// * `concurrency` - Il numero massimo di download contemporanei (almeno 1).
+pub async fn download_allegati_concurrent(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], destination_path: &str, concurrency: usize) -> Result<(), SpaggiariError> {
+    download_allegati_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, destination_path, concurrency).await
+}
+
+// Come `download_allegati_concurrent`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    concurrency: usize,
+) -> Result<(), SpaggiariError> {
+    stream::iter(allegati)
+        .map(|allegato| async move {
+            if let Some(incorporato) = &allegato.incorporato {
+                let filepath = format!("{}/{}", destination_path, sanitize_filename(&incorporato.nome));
+                std::fs::create_dir_all(destination_path)?;
+                std::fs::write(&filepath, &incorporato.contenuto)?;
+                debug!(path = %filepath, bytes = incorporato.contenuto.len(), "allegato incorporato salvato");
+                return Ok(());
+            }
+            let download_url = allegato.download_url(base_url);
+            let result = download_file_checked_via(http, &download_url, session_id, webidentity, destination_path).await;
+            http.registra(&download_url, &result, |download| download.bytes as usize);
+            result.map(|_| ())
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
+/// Scarica gli allegati nella cartella di destinazione, saltando quelli già presenti.
+///
+/// Rende ripetibile un download interrotto: gli allegati già salvati con la dimensione attesa
+/// non vengono riscritti (vedi [`download_file_skip_existing`]). Gli allegati vengono scaricati
+/// in parallelo, al massimo `DEFAULT_CONCURRENCY` alla volta.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<DownloadStatus>)` con l'esito di ciascun allegato, nello stesso ordine.
+pub async fn download_allegati_skip_existing(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], destination_path: &str) -> Result<Vec<DownloadStatus>, SpaggiariError> {
+    download_allegati_skip_existing_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, destination_path, DEFAULT_CONCURRENCY).await
+}
+
+// Come `download_allegati_skip_existing`, usando l'indirizzo base e la concorrenza indicati
+pub(crate) async fn download_allegati_skip_existing_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    concurrency: usize,
+) -> Result<Vec<DownloadStatus>, SpaggiariError> {
+    stream::iter(allegati)
+        .map(|allegato| async move {
+            let Some(incorporato) = &allegato.incorporato else {
+                let download_url = allegato.download_url(base_url);
+                let result = download_file_skip_existing_via(http, &download_url, session_id, webidentity, destination_path).await;
+                // Un file già presente non viene scaricato: nessun byte ricevuto
+                http.registra(&download_url, &result, |esito| match esito {
+                    DownloadStatus::Scaricato(path) => std::fs::metadata(path).map_or(0, |m| m.len() as usize),
+                    DownloadStatus::GiaPresente(_) => 0,
+                });
+                return result;
+            };
+            let filepath = format!("{}/{}", destination_path, sanitize_filename(&incorporato.nome));
+            if std::fs::metadata(&filepath).is_ok_and(|m| m.is_file() && m.len() == incorporato.contenuto.len() as u64) {
+                return Ok(DownloadStatus::GiaPresente(filepath));
+            }
+            std::fs::create_dir_all(destination_path)?;
+            std::fs::write(&filepath, &incorporato.contenuto)?;
+            Ok(DownloadStatus::Scaricato(filepath))
+        })
+        .buffered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
+/// Riepilogo del download dell'intera bacheca
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DownloadSummary {
+    /// Comunicazioni salvate, ciascuna nella propria cartella
+    pub comunicazioni: usize,
+    /// File scritti, compresi i `README.txt` con il testo delle comunicazioni
+    pub file: usize,
+}
+
+/// Modello predefinito per i nomi dei file in `download_allegati_flat`
+pub const DEFAULT_NAME_TEMPLATE: &str = "{codice}_{allegato_id}_{filename}";
+
+/// Compone il nome di un allegato sostituendo i segnaposto del modello.
+///
+/// I segnaposto riconosciuti sono `{codice}`, `{allegato_id}` e `{filename}`.
+///
+/// # Argomenti
+///
+/// * `template` - Il modello del nome, ad esempio `DEFAULT_NAME_TEMPLATE`.
+/// * `codice` - Il codice della circolare.
+/// * `allegato_id` - L'ID dell'allegato.
+/// * `filename` - Il nome del file indicato dal server.
+///
+/// # Restituisce
+///
+/// * Il nome del file da creare.
+pub fn nome_allegato(template: &str, codice: i32, allegato_id: &str, filename: &str) -> String {
+    template.replace("{codice}", &codice.to_string()).replace("{allegato_id}", allegato_id).replace("{filename}", filename)
+}
+
+/// Scarica gli allegati di una circolare in una cartella condivisa, nominandoli secondo un modello.
+///
+/// Con un modello che include `{codice}` e `{allegato_id}` gli allegati di tutte le circolari
+/// possono essere salvati nella stessa cartella senza che file con lo stesso nome si sovrascrivano.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `codice` - Il codice della circolare a cui appartengono gli allegati.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - La cartella condivisa dove salvare i file.
+/// * `template` - Il modello del nome dei file (vedi `nome_allegato`).
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<String>)` con i percorsi dei file salvati.
+pub async fn download_allegati_flat(
+    client: &Client,
+    session_id: &str,
+    webidentity: &str,
+    codice: i32,
+    allegati: &[Allegato],
+    destination_path: &str,
+    template: &str,
+) -> Result<Vec<String>, SpaggiariError> {
+    download_allegati_flat_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, codice, allegati, destination_path, template).await
+}
+
+// Come `download_allegati_flat`, usando l'indirizzo base indicato
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn download_allegati_flat_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    codice: i32,
+    allegati: &[Allegato],
+    destination_path: &str,
+    template: &str,
+) -> Result<Vec<String>, SpaggiariError> {
+    std::fs::create_dir_all(destination_path)?;
+    let mut paths = Vec::new();
+
+    for allegato in allegati {
+        let (filename, content) = match &allegato.incorporato {
+            Some(incorporato) => (sanitize_filename(&incorporato.nome), incorporato.contenuto.clone()),
+            None => download_file_bytes_via(http, &allegato.download_url(base_url), session_id, webidentity).await?,
+        };
+
+        let filepath = format!("{}/{}", destination_path, nome_allegato(template, codice, &allegato.allegato_id, &filename));
+        std::fs::write(&filepath, &content)?;
+        debug!(path = %filepath, bytes = content.len(), "allegato salvato");
+        paths.push(filepath);
+    }
+
+    Ok(paths)
+}
+
+/// Scarica nella cartella di destinazione solo gli allegati del tipo richiesto.
+///
+/// Il tipo di ciascun allegato viene determinato senza scaricarlo per intero: con una richiesta
+/// HEAD si usa il `Content-Type` (o, se generico, l'estensione del nome del file). Se il server
+/// non supporta HEAD si leggono solo i primi byte con una richiesta `Range` e si riconoscono
+/// i magic bytes, ricadendo sull'estensione del nome del file. Gli allegati incorporati
+/// vengono riconosciuti direttamente dal contenuto.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` tra cui scegliere.
+/// * `types` - I tipi di file da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<String>)` con i percorsi dei file salvati.
+pub async fn download_allegati_by_type(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], types: &[FileType], destination_path: &str) -> Result<Vec<String>, SpaggiariError> {
+    download_allegati_by_type_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, types, destination_path).await
+}
+
+// Come `download_allegati_by_type`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_by_type_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    types: &[FileType],
+    destination_path: &str,
+) -> Result<Vec<String>, SpaggiariError> {
+    let mut paths = Vec::new();
+
+    for allegato in allegati {
+        if let Some(incorporato) = &allegato.incorporato {
+            if types.contains(&FileType::from_bytes(&incorporato.contenuto)) {
+                let filepath = format!("{}/{}", destination_path, sanitize_filename(&incorporato.nome));
+                std::fs::create_dir_all(destination_path)?;
+                std::fs::write(&filepath, &incorporato.contenuto)?;
+                paths.push(filepath);
+            }
+            continue;
+        }
+
+        let download_url = allegato.download_url(base_url);
+        let file_type = sniff_file_type(http, &download_url, session_id, webidentity).await?;
+        if types.contains(&file_type) {
+            let result = download_file_checked_via(http, &download_url, session_id, webidentity, destination_path).await;
+            http.registra(&download_url, &result, |download| download.bytes as usize);
+            paths.push(result?.path.display().to_string());
+        } else {
+            debug!(allegato_id = %allegato.allegato_id, tipo = ?file_type, "allegato ignorato per tipo");
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Scarica gli allegati suddividendoli in sottocartelle in base al tipo di file.
+///
+/// Il tipo viene riconosciuto dal contenuto (o, se sconosciuto, dall'estensione del nome)
+/// e ogni file viene salvato in `pdf/`, `images/`, `docs/` oppure `other/` sotto `destination_root`.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_root` - La cartella in cui creare le sottocartelle.
+///
+/// # Restituisce
+///
+/// * `Ok(BTreeMap<String, usize>)` con il numero di file salvati in ciascuna sottocartella.
+pub async fn download_allegati_sorted(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], destination_root: &str) -> Result<BTreeMap<String, usize>, SpaggiariError> {
+    download_allegati_sorted_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, destination_root).await
+}
+
+// Come `download_allegati_sorted`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_sorted_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_root: &str,
+) -> Result<BTreeMap<String, usize>, SpaggiariError> {
+    let mut report = BTreeMap::new();
+
+    for allegato in allegati {
+        let (filename, content) = match &allegato.incorporato {
+            Some(incorporato) => (sanitize_filename(&incorporato.nome), incorporato.contenuto.clone()),
+            None => {
+                let download_url = allegato.download_url(base_url);
+                let result = download_file_bytes_via(http, &download_url, session_id, webidentity).await;
+                http.registra(&download_url, &result, |(_, content)| content.len());
+                result?
+            }
+        };
+
+        let file_type = match FileType::from_bytes(&content) {
+            FileType::Unknown => FileType::from_filename(&filename),
+            file_type => file_type,
+        };
+        let cartella = format!("{}/{}", destination_root, file_type.sottocartella());
+        std::fs::create_dir_all(&cartella)?;
+        let filepath = format!("{}/{}", cartella, filename);
+        std::fs::write(&filepath, &content)?;
+        debug!(path = %filepath, bytes = content.len(), "allegato salvato");
+
+        *report.entry(file_type.sottocartella().to_string()).or_insert(0) += 1;
+    }
+
+    Ok(report)
+}
+
+/// Scarica gli allegati come `download_allegati`, estraendo automaticamente gli archivi ZIP.
+/// Disponibile con la feature `zip`.
+///
+/// Gli archivi vengono riconosciuti dai magic bytes (non solo dall'estensione) ed estratti
+/// in una sottocartella con il nome dell'archivio senza estensione. I documenti Office e
+/// OpenDocument, che sono anch'essi file ZIP, vengono salvati senza estrarli.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+/// * `keep_archive` - Se `true` l'archivio `.zip` viene conservato accanto alla cartella estratta.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<String>)` con i percorsi dei file salvati, compresi quelli estratti.
+#[cfg(feature = "zip")]
+pub async fn download_allegati_extract_zip(
+    client: &Client,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    keep_archive: bool,
+) -> Result<Vec<String>, SpaggiariError> {
+    download_allegati_extract_zip_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, destination_path, keep_archive).await
+}
+
+// Come `download_allegati_extract_zip`, usando l'indirizzo base indicato
+#[cfg(feature = "zip")]
+pub(crate) async fn download_allegati_extract_zip_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    keep_archive: bool,
+) -> Result<Vec<String>, SpaggiariError> {
+    std::fs::create_dir_all(destination_path)?;
+    let mut paths = Vec::new();
+
+    for allegato in allegati {
+        let (filename, content) = match &allegato.incorporato {
+            Some(incorporato) => (sanitize_filename(&incorporato.nome), incorporato.contenuto.clone()),
+            None => download_file_bytes_via(http, &allegato.download_url(base_url), session_id, webidentity).await?,
+        };
+
+        let mut filepath = format!("{}/{}", destination_path, filename);
+        if is_archivio_zip(&filename, &content) {
+            let nome_cartella = match filename.rsplit_once('.') {
+                Some((nome, _)) => nome,
+                None => {
+                    // Senza estensione la cartella avrebbe lo stesso nome dell'archivio
+                    filepath.push_str(".zip");
+                    filename.as_str()
+                }
+            };
+            let cartella = format!("{}/{}", destination_path, nome_cartella);
+            paths.extend(estrai_zip(&content, std::path::Path::new(&cartella))?);
+            debug!(archivio = %filename, cartella = %cartella, "archivio estratto");
+            if !keep_archive {
+                continue;
+            }
+        }
+
+        std::fs::write(&filepath, &content)?;
+        debug!(path = %filepath, bytes = content.len(), "allegato salvato");
+        paths.push(filepath);
+    }
+
+    Ok(paths)
+}
+
+// `true` se il contenuto è un archivio ZIP e non un documento basato su ZIP (docx, odt, ...)
+#[cfg(feature = "zip")]
+fn is_archivio_zip(filename: &str, content: &[u8]) -> bool {
+    const DOCUMENTI_ZIP: [&str; 7] = ["docx", "xlsx", "pptx", "odt", "ods", "odp", "epub"];
+
+    let estensione = filename.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase()).unwrap_or_default();
+    FileType::from_bytes(content) == FileType::Zip && !DOCUMENTI_ZIP.contains(&estensione.as_str())
+}
+
+// Estrae un archivio ZIP nella cartella indicata, ignorando i percorsi che ne uscirebbero
+#[cfg(feature = "zip")]
+fn estrai_zip(content: &[u8], cartella: &std::path::Path) -> Result<Vec<String>, SpaggiariError> {
+    let errore_zip = |e: zip::result::ZipError| SpaggiariError::ParseError {
+        details: format!("Archivio ZIP non valido: {}", e),
+    };
+
+    let mut archivio = zip::ZipArchive::new(std::io::Cursor::new(content)).map_err(errore_zip)?;
+    let mut paths = Vec::new();
+    for i in 0..archivio.len() {
+        let mut voce = archivio.by_index(i).map_err(errore_zip)?;
+        let Some(relativo) = voce.enclosed_name() else {
+            warn!(voce = voce.name(), "voce dell'archivio ignorata, percorso non sicuro");
+            continue;
+        };
+        let percorso = cartella.join(relativo);
+        if voce.is_dir() {
+            std::fs::create_dir_all(&percorso)?;
+            continue;
+        }
+        if let Some(parent) = percorso.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::File::create(&percorso)?;
+        std::io::copy(&mut voce, &mut file)?;
+        paths.push(percorso.to_string_lossy().to_string());
+    }
+    Ok(paths)
+}
+
+/// Scarica gli allegati scrivendoli come voci di un unico archivio ZIP.
+///
+/// Ogni allegato diventa una voce con il nome indicato dal server; i nomi ripetuti
+/// ricevono un suffisso numerico (`modulo.pdf`, `modulo_2.pdf`, ...). L'archivio viene
+/// composto in memoria e poi scritto su `out`.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Gli allegati da scaricare.
+/// * `out` - Destinazione dell'archivio (file, buffer, ...).
+///
+/// # Restituisce
+///
+/// * `Ok(())` se l'archivio è stato scritto completamente.
+#[cfg(feature = "zip")]
+pub async fn download_allegati_zip(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato], out: impl std::io::Write) -> Result<(), SpaggiariError> {
+    download_allegati_zip_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, out).await
+}
+
+// Come `download_allegati_zip`, usando l'indirizzo base indicato
+#[cfg(feature = "zip")]
+pub(crate) async fn download_allegati_zip_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, allegati: &[Allegato], mut out: impl std::io::Write) -> Result<(), SpaggiariError> {
+    use std::io::Write;
+
+    let errore_zip = |e: zip::result::ZipError| SpaggiariError::Generic(format!("Errore nella scrittura dell'archivio ZIP: {}", e));
+    let mut archivio = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut nomi = BTreeSet::new();
+
+    for allegato in allegati {
+        let (filename, content) = match &allegato.incorporato {
+            Some(incorporato) => (sanitize_filename(&incorporato.nome), incorporato.contenuto.clone()),
+            None => download_file_bytes_via(http, &allegato.download_url(base_url), session_id, webidentity).await?,
+        };
+        let nome = nome_voce_univoco(&filename, &mut nomi);
+        archivio.start_file(nome.as_str(), zip::write::SimpleFileOptions::default()).map_err(errore_zip)?;
+        archivio.write_all(&content)?;
+        debug!(voce = %nome, bytes = content.len(), "allegato aggiunto all'archivio");
+    }
+
+    out.write_all(&archivio.finish().map_err(errore_zip)?.into_inner())?;
+    Ok(())
+}
+
+// Nome della voce dell'archivio, con un suffisso numerico se `filename` è già stato usato
+#[cfg(feature = "zip")]
+fn nome_voce_univoco(filename: &str, nomi: &mut BTreeSet<String>) -> String {
+    let (base, estensione) = match filename.rsplit_once('.') {
+        Some((base, estensione)) if !base.is_empty() => (base, format!(".{}", estensione)),
+        _ => (filename, String::new()),
+    };
+    let mut nome = filename.to_string();
+    let mut n = 1;
+    while !nomi.insert(nome.clone()) {
+        n += 1;
+        nome = format!("{}_{}{}", base, n, estensione);
+    }
+    nome
+}
+
+// Determina il tipo di un file remoto senza scaricarlo per intero
+async fn sniff_file_type(http: &Http, url: &str, session_id: &str, webidentity: &str) -> Result<FileType, SpaggiariError> {
+    let cookie = cookie_header(session_id, webidentity);
+
+    let response = http.send(|client| client.head(url).header("Cookie", &cookie)).await?;
+    if response.status().is_success() {
+        let content_type = response.headers().get("content-type").and_then(|v| v.to_str().ok()).unwrap_or("");
+        return Ok(match FileType::from_mime(content_type) {
+            FileType::Unknown => FileType::from_filename(&response_filename(&response)),
+            file_type => file_type,
+        });
+    }
+
+    // HEAD non supportato: legge solo i primi byte del file
+    debug!(url = %url, status = response.status().as_u16(), "HEAD non supportato, uso una richiesta Range");
+    let mut response = http.send(|client| client.get(url).header("Cookie", &cookie).header("Range", "bytes=0-15")).await?;
+    if !response.status().is_success() {
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
+        return Err(SpaggiariError::ParseError {
+            details: format!("Download fallito: {}", response.status()),
+        });
+    }
+    let filename = response_filename(&response);
+    let head = response.chunk().await?.unwrap_or_default();
+    Ok(match FileType::from_bytes(&head) {
+        FileType::Unknown => FileType::from_filename(&filename),
+        file_type => file_type,
+    })
+}
+
+/// Informazioni su un allegato ottenute senza scaricarlo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllegatoMeta {
+    /// Nome del file indicato dal server in `Content-Disposition`
+    pub filename: String,
+    /// Dimensione in byte, se indicata dal server
+    pub content_length: Option<u64>,
+    /// Tipo MIME (`Content-Type`), se indicato dal server
+    pub content_type: Option<String>,
+}
+
+/// Recupera nome, dimensione e tipo di un allegato senza scaricarne il contenuto.
+///
+/// Usa una richiesta HEAD; se il portale non la supporta ripiega su una richiesta
+/// `Range` del solo primo byte, ricavando la dimensione da `Content-Range`.
+/// Per gli allegati incorporati le informazioni sono ricavate dal contenuto già in memoria.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegato` - L'allegato di cui leggere le informazioni.
+///
+/// # Restituisce
+///
+/// * `Ok(AllegatoMeta)` con nome, dimensione e tipo del file.
+pub async fn allegato_metadata(client: &Client, session_id: &str, webidentity: &str, allegato: &Allegato) -> Result<AllegatoMeta, SpaggiariError> {
+    allegato_metadata_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegato).await
+}
+
+// Come `allegato_metadata`, usando l'indirizzo base indicato
+pub(crate) async fn allegato_metadata_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, allegato: &Allegato) -> Result<AllegatoMeta, SpaggiariError> {
+    if let Some(incorporato) = &allegato.incorporato {
+        return Ok(AllegatoMeta {
+            filename: sanitize_filename(&incorporato.nome),
+            content_length: Some(incorporato.contenuto.len() as u64),
+            content_type: Some(incorporato.mime.clone()),
+        });
+    }
+
+    metadata_url_via(http, &allegato.download_url(base_url), session_id, webidentity).await
+}
+
+// Come `allegato_metadata`, per l'URL di download indicato
+async fn metadata_url_via(http: &Http, url: &str, session_id: &str, webidentity: &str) -> Result<AllegatoMeta, SpaggiariError> {
+    let cookie = cookie_header(session_id, webidentity);
+    let header = |response: &Response, nome: &str| response.headers().get(nome).and_then(|v| v.to_str().ok()).map(str::to_string);
+
+    let response = http.send(|client| client.head(url).header("Cookie", &cookie)).await?;
+    if response.status().is_success() {
+        return Ok(AllegatoMeta {
+            filename: response_filename(&response),
+            content_length: header(&response, "content-length").and_then(|v| v.trim().parse().ok()),
+            content_type: header(&response, "content-type"),
+        });
+    }
+
+    // HEAD non supportato: chiede solo il primo byte e legge la dimensione totale da Content-Range
+    debug!(url = %url, status = response.status().as_u16(), "HEAD non supportato, uso una richiesta Range");
+    let response = http.send(|client| client.get(url).header("Cookie", &cookie).header("Range", "bytes=0-0")).await?;
+    if !response.status().is_success() {
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
+        return Err(SpaggiariError::ParseError {
+            details: format!("Download fallito: {}", response.status()),
+        });
+    }
+    let content_length = match header(&response, "content-range") {
+        Some(range) => range.rsplit('/').next().and_then(|totale| totale.trim().parse().ok()),
+        None => header(&response, "content-length").and_then(|v| v.trim().parse().ok()),
+    };
+    Ok(AllegatoMeta {
+        filename: response_filename(&response),
+        content_length,
+        content_type: header(&response, "content-type"),
+    })
+}
+
+/// Stima lo spazio necessario per scaricare gli allegati indicati, senza scaricarli.
+///
+/// Somma le dimensioni ottenute con [`allegato_metadata`], eseguendo al massimo
+/// `DEFAULT_CONCURRENCY` richieste alla volta come i download. Gli allegati di cui il server
+/// non indica la dimensione non vengono conteggiati.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Gli allegati da scaricare.
+///
+/// # Restituisce
+///
+/// * `Ok(u64)` con la dimensione totale in byte.
+pub async fn estimate_download_size(client: &Client, session_id: &str, webidentity: &str, allegati: &[Allegato]) -> Result<u64, SpaggiariError> {
+    estimate_download_size_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, DEFAULT_CONCURRENCY).await
+}
+
+// Come `estimate_download_size`, usando l'indirizzo base e la concorrenza indicati
+pub(crate) async fn estimate_download_size_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, allegati: &[Allegato], concurrency: usize) -> Result<u64, SpaggiariError> {
+    let dimensioni = dimensioni_allegati_at(http, base_url, session_id, webidentity, allegati, concurrency).await?;
+
+    let sconosciute = dimensioni.iter().filter(|d| d.is_none()).count();
+    if sconosciute > 0 {
+        debug!(sconosciute, allegati = allegati.len(), "dimensione non indicata dal server per alcuni allegati");
+    }
+    Ok(dimensioni.into_iter().flatten().sum())
+}
+
+// Legge la dimensione di ogni allegato con `allegato_metadata_at`, `None` se il server non la indica
+async fn dimensioni_allegati_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, allegati: &[Allegato], concurrency: usize) -> Result<Vec<Option<u64>>, SpaggiariError> {
+    stream::iter(allegati)
+        .map(|allegato| async move { allegato_metadata_at(http, base_url, session_id, webidentity, allegato).await.map(|meta| meta.content_length) })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await
+}
+
+/// Scarica un allegato passando il contenuto a `sink` man mano che arriva.
+///
+/// Per gli allegati incorporati il contenuto viene passato a `sink` in un unico blocco.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegato` - L'allegato da scaricare.
+/// * `sink` - Funzione richiamata per ogni blocco di byte ricevuto.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` contenente il nome del file.
+pub async fn download_allegato_stream<F, E>(client: &Client, session_id: &str, webidentity: &str, allegato: &Allegato, sink: F) -> Result<String, SpaggiariError>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    download_allegato_stream_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegato, sink).await
+}
+
+// Come `download_allegato_stream`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegato_stream_at<F, E>(http: &Http, base_url: &str, session_id: &str, webidentity: &str, allegato: &Allegato, mut sink: F) -> Result<String, SpaggiariError>
+where
+    F: FnMut(&[u8]) -> Result<(), E>,
+    E: std::fmt::Display,
+{
+    if let Some(incorporato) = &allegato.incorporato {
+        sink(&incorporato.contenuto).map_err(|e| SpaggiariError::Generic(format!("Errore nella scrittura di {}: {}", incorporato.nome, e)))?;
+        return Ok(sanitize_filename(&incorporato.nome));
+    }
+
+    download_file_stream_via(http, &allegato.download_url(base_url), session_id, webidentity, sink).await
+}
+
+/// Scarica tutti gli allegati specificati e restituisce il loro contenuto in memoria.
+///
+/// Gli allegati vengono scaricati in parallelo, al massimo `DEFAULT_CONCURRENCY` alla volta,
+/// e il primo download fallito viene restituito come errore.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Un vettore di `Allegato` da scaricare.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<(String, Vec<u8>)>)` contenente coppie di (nome file, contenuto), nello stesso ordine di `allegati`.
+pub async fn download_allegati_bytes(client: &Client, session_id: &str, webidentity: &str, allegati: Vec<Allegato>) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
+    download_allegati_bytes_concurrent(client, session_id, webidentity, allegati, DEFAULT_CONCURRENCY).await
+}
+
+/// Come `download_allegati_bytes`, con al massimo `concurrency` download in parallelo.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Un vettore di `Allegato` da scaricare.
+/// * `concurrency` - Il numero massimo di download contemporanei (almeno 1).
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<(String, Vec<u8>)>)` contenente coppie di (nome file, contenuto), nello stesso ordine di `allegati`.
+pub async fn download_allegati_bytes_concurrent(client: &Client, session_id: &str, webidentity: &str, allegati: Vec<Allegato>, concurrency: usize) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
+    download_allegati_bytes_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, concurrency).await
+}
+
+// Come `download_allegati_bytes_concurrent`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_bytes_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: Vec<Allegato>,
+    concurrency: usize,
+) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
+    let mut results: Vec<(usize, (String, Vec<u8>))> = stream::iter(allegati.into_iter().enumerate())
+        .map(|(i, allegato)| async move {
+            if let Some(incorporato) = allegato.incorporato {
+                return Ok((i, (sanitize_filename(&incorporato.nome), incorporato.contenuto)));
+            }
+            let download_url = allegato.download_url(base_url);
+            match download_file_bytes_via(http, &download_url, session_id, webidentity).await {
+                Ok(file) => Ok((i, file)),
+                Err(e) => {
+                    debug!(allegato_id = %allegato.allegato_id, errore = %e, "download dell'allegato fallito");
+                    Err(e)
+                }
+            }
+        })
+        .buffer_unordered(concurrency.max(1))
+        .try_collect()
+        .await?;
+
+    // I download terminano in ordine sparso: ripristina l'ordine degli allegati
+    results.sort_unstable_by_key(|(i, _)| *i);
+    Ok(results.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Scarica in parallelo tutti gli allegati specificati, riportando l'avanzamento complessivo.
+///
+/// La dimensione totale è letta prima con richieste HEAD, come in `estimate_download_size`, poi
+/// i file vengono scaricati al massimo `DEFAULT_CONCURRENCY` alla volta. `callback` riceve i byte
+/// scaricati finora (su tutti i file) e il totale, `None` se il server non indica la dimensione
+/// di almeno un file. Come in `download_allegati_bytes`, il primo errore interrompe il download.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Un vettore di `Allegato` da scaricare.
+/// * `callback` - Funzione richiamata con (byte scaricati, byte totali) a ogni blocco ricevuto.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<(String, Vec<u8>)>)` contenente coppie di (nome file, contenuto) nello stesso ordine di `allegati`.
+pub async fn download_allegati_bytes_with_progress<F>(client: &Client, session_id: &str, webidentity: &str, allegati: Vec<Allegato>, callback: F) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError>
+where
+    F: Fn(u64, Option<u64>),
+{
+    download_allegati_bytes_with_progress_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, callback).await
+}
+
+// Come `download_allegati_bytes_with_progress`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_bytes_with_progress_at<F>(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: Vec<Allegato>,
+    callback: F,
+) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError>
+where
+    F: Fn(u64, Option<u64>),
+{
+    // 1) Calcola il totale con richieste HEAD, senza tenere aperte le risposte dei download
+    let total = dimensioni_allegati_at(http, base_url, session_id, webidentity, &allegati, DEFAULT_CONCURRENCY)
+        .await?
+        .into_iter()
+        .sum::<Option<u64>>();
+    let downloaded = AtomicU64::new(0);
+    callback(0, total);
+
+    // 2) Scarica i contenuti: ogni download occupa il suo posto nel limite finché il corpo non è letto
+    let avanza = |bytes: usize| {
+        let now = downloaded.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+        callback(now, total);
+    };
+    let avanza = &avanza;
+    stream::iter(allegati)
+        .map(|allegato| async move {
+            if let Some(incorporato) = allegato.incorporato {
+                avanza(incorporato.contenuto.len());
+                return Ok((sanitize_filename(&incorporato.nome), incorporato.contenuto));
+            }
+            download_url_bytes_with_progress(http, &allegato.download_url(base_url), session_id, webidentity, avanza).await
+        })
+        .buffered(DEFAULT_CONCURRENCY)
+        .try_collect()
+        .await
+}
+
+// Scarica in memoria un URL, passando ad `avanza` la dimensione di ogni blocco ricevuto
+async fn download_url_bytes_with_progress(http: &Http, url: &str, session_id: &str, webidentity: &str, avanza: &impl Fn(usize)) -> Result<(String, Vec<u8>), SpaggiariError> {
+    let mut response = http.send(|client| client.get(url).header("Cookie", cookie_header(session_id, webidentity))).await?;
+    if !response.status().is_success() {
+        debug!(url = %url, status = response.status().as_u16(), "download fallito");
+        return Err(SpaggiariError::ParseError {
+            details: format!("Download fallito: {}", response.status()),
+        });
+    }
+
+    let filename = response_filename(&response);
+    let mut content = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        content.extend_from_slice(&chunk);
+        avanza(chunk.len());
+    }
+    debug!(filename = %filename, bytes = content.len(), "file scaricato in memoria");
+    Ok((filename, content))
+}
+
+/// Recupera la bacheca personale dell'utente.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+///
+/// # Restituisce
+///
+/// * `Ok(Bacheca)` contenente le circolari lette e nuove.
+pub async fn get_backeca(client: &Client, session_id: &str, webidentity: &str) -> Result<Bacheca, SpaggiariError> {
+    get_backeca_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await
+}
+
+// Come `get_backeca`, usando l'indirizzo base indicato
+pub(crate) async fn get_backeca_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<Bacheca, SpaggiariError> {
+    let text = fetch_bacheca_at(http, base_url, session_id, webidentity).await?;
+    parse_bacheca(&text)
+}
+
+/// Recupera la bacheca personale scartando le circolari che non è possibile interpretare.
+///
+/// A differenza di `get_backeca`, un singolo elemento malformato non fa fallire
+/// l'intera richiesta: l'errore viene registrato e restituito insieme alle circolari valide.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+///
+/// # Restituisce
+///
+/// * `Ok((Bacheca, Vec<String>))` con le circolari valide e i messaggi di errore di quelle scartate.
+pub async fn get_backeca_lenient(client: &Client, session_id: &str, webidentity: &str) -> Result<(Bacheca, Vec<String>), SpaggiariError> {
+    let text = fetch_bacheca_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await?;
+    parse_bacheca_lenient(&text)
+}
+
+/// Recupera le circolari della bacheca pubblicate nell'intervallo di date indicato.
+///
+/// A differenza di `get_backeca`, che scarica l'intera bacheca, passa al portale
+/// il filtro sulle date (`data_start` e `data_stop`, estremi inclusi): utile per gli
+/// account con molti anni di circolari.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `from` - Prima data dell'intervallo.
+/// * `to` - Ultima data dell'intervallo.
+///
+/// # Restituisce
+///
+/// * `Ok(Bacheca)` contenente le circolari lette e nuove dell'intervallo.
+pub async fn get_backeca_range(client: &Client, session_id: &str, webidentity: &str, from: NaiveDate, to: NaiveDate) -> Result<Bacheca, SpaggiariError> {
+    let text = fetch_bacheca_range_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, Some((from, to))).await?;
+    parse_bacheca(&text)
+}
+
+// Scarica il JSON grezzo della bacheca personale
+pub(crate) async fn fetch_bacheca_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<String, SpaggiariError> {
+    fetch_bacheca_range_at(http, base_url, session_id, webidentity, None).await
+}
+
+// Come `fetch_bacheca_at`, limitando facoltativamente la bacheca a un intervallo di date
+pub(crate) async fn fetch_bacheca_range_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, periodo: Option<(NaiveDate, NaiveDate)>) -> Result<String, SpaggiariError> {
+    let mut query = vec![("action", "get_comunicazioni".to_string()), ("ncna", "1".to_string())];
+    if let Some((from, to)) = periodo {
+        query.push(("data_start", from.format("%Y-%m-%d").to_string()));
+        query.push(("data_stop", to.format("%Y-%m-%d").to_string()));
+    }
+
+    let response = http
+        .send(|client| {
+            client
+                .get(format!("{}{}", base_url, PATH_BACHECA))
+                .query(&query) // Aggiunti i form data come query parameters
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+
+    trace!(status = status.as_u16(), "risposta bacheca ricevuta");
+
+    if status.is_success() {
+        Ok(response.text().await?)
+    } else {
+        debug!(status = status.as_u16(), "token non valido o scaduto");
+        Err(SpaggiariError::ParseError {
+            details: "Il token non sembra funzionare".to_string(),
+        })
+    }
+}
+
+// Interpreta il JSON della bacheca
+pub(crate) fn parse_bacheca(text: &str) -> Result<Bacheca, SpaggiariError> {
+    if let Some(e) = classifica_pagina_html(text) {
+        debug!(errore = %e, "la bacheca ha restituito una pagina HTML");
+        return Err(e);
+    }
+
+    match parse_json_body::<Bacheca>(text) {
+        Ok(bacheca) => {
+            segnala_campi_sconosciuti(&bacheca);
+            Ok(bacheca)
+        }
+        Err(e) => {
+            debug!(errore = %e, "bacheca non interpretabile");
+            Err(e)
+        }
+    }
+}
+
+// Interpreta il JSON della bacheca deserializzando le circolari una alla volta
+pub(crate) fn parse_bacheca_lenient(text: &str) -> Result<(Bacheca, Vec<String>), SpaggiariError> {
+    if let Some(e) = classifica_pagina_html(text) {
+        debug!(errore = %e, "la bacheca ha restituito una pagina HTML");
+        return Err(e);
+    }
+
+    #[derive(Deserialize)]
+    struct RawBacheca {
+        read: Vec<serde_json::Value>,
+        msg_new: Option<Vec<serde_json::Value>>,
+        #[serde(flatten)]
+        extra: HashMap<String, serde_json::Value>,
+    }
+
+    let raw: RawBacheca = parse_json_body(text)?;
+    let mut errori = Vec::new();
+    let mut parse_lista = |nome: &str, valori: Vec<serde_json::Value>| -> Vec<Circolare> {
+        let mut circolari = Vec::new();
+        for (i, valore) in valori.into_iter().enumerate() {
+            match serde_json::from_value::<Circolare>(valore) {
+                Ok(circolare) => circolari.push(circolare),
+                Err(e) => {
+                    debug!(lista = %nome, indice = i, errore = %e, "circolare scartata");
+                    errori.push(format!("{}[{}]: {}", nome, i, e));
+                }
+            }
+        }
+        circolari
+    };
+
+    let read = parse_lista("read", raw.read);
+    let msg_new = raw.msg_new.map(|valori| parse_lista("msg_new", valori));
+
+    let bacheca = Bacheca { read, msg_new, extra: raw.extra };
+    segnala_campi_sconosciuti(&bacheca);
+    Ok((bacheca, errori))
+}
+
+// Avvisa quando la risposta contiene campi che la libreria non conosce
+fn segnala_campi_sconosciuti(bacheca: &Bacheca) {
+    let campi = bacheca.campi_sconosciuti();
+    if !campi.is_empty() {
+        warn!(campi = %campi.join(", "), "campi sconosciuti nella bacheca, il formato potrebbe essere cambiato");
+    }
+}
+
+/// Estrae il testo di una comunicazione dall'HTML.
+///
+/// La struttura del testo viene mantenuta: i paragrafi sono separati da una riga vuota,
+/// `<br>`, `<div>` e le voci degli elenchi (precedute da `- `) iniziano una nuova riga.
+/// Gli spazi superflui vengono rimossi.
+///
+/// # Argomenti
+///
+/// * `html` - Il contenuto HTML della pagina della comunicazione.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` contenente il testo della comunicazione.
+pub fn extract_testo_comunicazione(html: &str) -> Result<String, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("div.comunicazione_testo").map_err(|e| SpaggiariError::ParseError {
+        details: format!("Errore nel parsing del selettore: {}", e),
+    })?;
+
+    if let Some(element) = document.select(&selector).next() {
+        let mut testo = String::new();
+        accoda_testo(element, &mut testo);
+        Ok(pulisci_righe(&testo))
+    } else {
+        Ok("".to_string()) // Se non trovato, restituisci stringa vuota
+    }
+}
+
+// Accoda il testo del nodo, andando a capo in corrispondenza degli elementi di blocco
+fn accoda_testo(elemento: ElementRef, out: &mut String) {
+    for nodo in elemento.children() {
+        if let Node::Text(testo) = nodo.value() {
+            out.push_str(testo);
+        }
+        if let Some(figlio) = ElementRef::wrap(nodo) {
+            match figlio.value().name() {
+                "br" => out.push('\n'),
+                "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" => {
+                    out.push_str("\n\n");
+                    accoda_testo(figlio, out);
+                    out.push_str("\n\n");
+                }
+                "li" => {
+                    out.push_str("\n- ");
+                    accoda_testo(figlio, out);
+                }
+                "div" | "ul" | "ol" | "tr" | "table" => {
+                    out.push('\n');
+                    accoda_testo(figlio, out);
+                    out.push('\n');
+                }
+                _ => accoda_testo(figlio, out),
+            }
+        }
+    }
+}
+
+/// Converte in Markdown il testo di una comunicazione.
+///
+/// Mantiene titoli, elenchi (puntati e numerati), link, grassetto e corsivo.
+/// Disponibile con la feature `markdown`.
+///
+/// # Argomenti
+///
+/// * `html` - Il contenuto HTML della pagina della comunicazione.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` con il testo in Markdown (vuoto se la pagina non contiene il testo).
+#[cfg(feature = "markdown")]
+pub fn extract_markdown_comunicazione(html: &str) -> Result<String, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("div.comunicazione_testo").map_err(|e| SpaggiariError::ParseError {
+        details: format!("Errore nel parsing del selettore: {}", e),
+    })?;
+
+    let mut markdown = String::new();
+    if let Some(element) = document.select(&selector).next() {
+        accoda_markdown(element, &mut markdown);
+    }
+    Ok(pulisci_righe(&markdown))
+}
+
+// Come `accoda_testo`, convertendo la formattazione in Markdown
+#[cfg(feature = "markdown")]
+fn accoda_markdown(elemento: ElementRef, out: &mut String) {
+    for nodo in elemento.children() {
+        if let Node::Text(testo) = nodo.value() {
+            out.push_str(testo);
+        }
+        if let Some(figlio) = ElementRef::wrap(nodo) {
+            match figlio.value().name() {
+                "br" => out.push('\n'),
+                "p" | "blockquote" => {
+                    out.push_str("\n\n");
+                    accoda_markdown(figlio, out);
+                    out.push_str("\n\n");
+                }
+                "div" | "table" | "tr" => {
+                    out.push('\n');
+                    accoda_markdown(figlio, out);
+                    out.push('\n');
+                }
+                titolo @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                    let livello = titolo[1..].parse().unwrap_or(1);
+                    out.push_str(&format!("\n\n{} ", "#".repeat(livello)));
+                    accoda_markdown(figlio, out);
+                    out.push_str("\n\n");
+                }
+                elenco @ ("ul" | "ol") => {
+                    out.push('\n');
+                    let voci = figlio.children().filter_map(ElementRef::wrap).filter(|e| e.value().name() == "li");
+                    for (i, voce) in voci.enumerate() {
+                        if elenco == "ol" {
+                            out.push_str(&format!("\n{}. ", i + 1));
+                        } else {
+                            out.push_str("\n- ");
+                        }
+                        accoda_markdown(voce, out);
+                    }
+                    out.push_str("\n\n");
+                }
+                "li" => {
+                    out.push_str("\n- ");
+                    accoda_markdown(figlio, out);
+                }
+                "strong" | "b" => accoda_enfasi(figlio, "**", out),
+                "em" | "i" => accoda_enfasi(figlio, "*", out),
+                "code" => accoda_enfasi(figlio, "`", out),
+                "a" => match figlio.value().attr("href") {
+                    Some(href) if !href.starts_with('#') && !href.starts_with("javascript:") => {
+                        let mut testo = String::new();
+                        accoda_markdown(figlio, &mut testo);
+                        let testo = testo.split_whitespace().collect::<Vec<_>>().join(" ");
+                        out.push_str(&format!("[{}]({})", if testo.is_empty() { href } else { &testo }, href));
+                    }
+                    _ => accoda_markdown(figlio, out),
+                },
+                _ => accoda_markdown(figlio, out),
+            }
+        }
+    }
+}
+
+// Racchiude il testo dell'elemento tra i marcatori, lasciando fuori gli spazi iniziali e finali
+#[cfg(feature = "markdown")]
+fn accoda_enfasi(elemento: ElementRef, marcatore: &str, out: &mut String) {
+    let mut interno = String::new();
+    accoda_markdown(elemento, &mut interno);
+    let testo = interno.trim();
+    if testo.is_empty() {
+        out.push_str(&interno);
+        return;
+    }
+    let inizio = &interno[..interno.len() - interno.trim_start().len()];
+    let fine = &interno[interno.trim_end().len()..];
+    out.push_str(&format!("{}{}{}{}{}", inizio, marcatore, testo, marcatore, fine));
+}
+
+// Compatta gli spazi di ogni riga e lascia al più una riga vuota tra i blocchi
+fn pulisci_righe(testo: &str) -> String {
+    let mut righe: Vec<String> = Vec::new();
+    for riga in testo.split('\n') {
+        let riga = riga.split_whitespace().collect::<Vec<_>>().join(" ");
+        if riga.is_empty() && righe.last().is_none_or(|r| r.is_empty()) {
+            continue;
+        }
+        righe.push(riga);
+    }
+    while righe.last().is_some_and(|r| r.is_empty()) {
+        righe.pop();
+    }
+    righe.join("\n")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Allegato {
+    pub comunicazione_id: String,
+    pub allegato_id: String,
+    /// Nome originale del file, se indicato nella pagina della comunicazione
+    pub nome: Option<String>,
+    /// Presente quando l'allegato è incorporato nell'HTML come `data:` URI anziché scaricabile
+    pub incorporato: Option<AllegatoIncorporato>,
+}
+
+impl Allegato {
+    /// `true` se il contenuto dell'allegato è già disponibile in memoria
+    pub fn is_incorporato(&self) -> bool {
+        self.incorporato.is_some()
+    }
+
+    /// URL da cui scaricare l'allegato
+    ///
+    /// # Argomenti
+    ///
+    /// * `base_url` - L'indirizzo base del portale (es. `https://web.spaggiari.eu`)
+    pub fn download_url(&self, base_url: &str) -> String {
+        format!("{}{}?action=file_download&com_id={}", base_url, PATH_BACHECA, self.allegato_id)
+    }
+}
+
+/// Allegato incorporato nella pagina della comunicazione come `data:` URI base64
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllegatoIncorporato {
+    pub nome: String,
+    pub mime: String,
+    pub contenuto: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Comunicazione {
+    pub testo: String,
+    pub allegati: Vec<Allegato>,
+}
+
+// Contenitore della pagina di consenso mostrata al posto di alcune comunicazioni
+const SELETTORE_CONSENSO: &str = "div.comunicazione_consenso";
+
+// `true` se la pagina contiene il contenitore del testo di una comunicazione (anche vuoto)
+fn ha_testo_comunicazione(html: &str) -> Result<bool, SpaggiariError> {
+    let testo = selettore("div.comunicazione_testo")?;
+    Ok(Html::parse_document(html).select(&testo).next().is_some())
+}
+
+// `true` se la pagina chiede un consenso invece di mostrare il testo della comunicazione
+fn richiede_consenso(html: &str) -> Result<bool, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let consenso = selettore(SELETTORE_CONSENSO)?;
+    let testo = selettore("div.comunicazione_testo")?;
+    Ok(document.select(&consenso).next().is_some() && document.select(&testo).next().is_none())
+}
+
+// Estrae testo e allegati (scaricabili e incorporati) dalla pagina di una comunicazione
+pub(crate) fn parse_comunicazione(html: &str, comm_id: &str) -> Result<Comunicazione, SpaggiariError> {
+    if sessione_sostituita(html) {
+        debug!(comm_id, "sessione aperta su un altro dispositivo");
+        return Err(SpaggiariError::SessionSuperseded);
+    }
+
+    if richiede_consenso(html)? {
+        debug!(comm_id, "la comunicazione richiede un consenso");
+        return Err(SpaggiariError::ConsensoRichiesto(comm_id.to_string()));
+    }
+
+    // Estrai gli allegati dal body HTML
+    let mut allegati = extract_allegati(html)?;
+
+    for (i, incorporato) in extract_allegati_incorporati(html)?.into_iter().enumerate() {
+        allegati.push(Allegato {
+            comunicazione_id: comm_id.to_string(),
+            allegato_id: format!("incorporato_{}", i + 1),
+            nome: Some(incorporato.nome.clone()),
+            incorporato: Some(incorporato),
+        });
+    }
+
+    // Senza testo né allegati: pagina di accesso se la sessione è scaduta,
+    // altrimenti il portale non ha riconosciuto l'ID richiesto
+    if allegati.is_empty() && !ha_testo_comunicazione(html)? {
+        if pagina_di_accesso(html) {
+            return Err(SpaggiariError::InvalidSessionToken);
+        }
+        debug!(comm_id, "la pagina della comunicazione non contiene né testo né allegati");
+        return Err(SpaggiariError::ComunicazioneNotFound(comm_id.to_string()));
+    }
+
+    // Estrai il testo della comunicazione
+    let testo = extract_testo_comunicazione(html)?;
+    trace!(testo = %testo, "testo della comunicazione estratto");
+
+    Ok(Comunicazione { testo, allegati })
+}
+
+/// Recupera i dettagli di una specifica comunicazione.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `comm_id` - L'ID della comunicazione.
+/// * `webidentity` - L'identità web dell'utente (il codice usato al login), inviata nel
+///   cookie `webidentity` insieme a `PHPSESSID`.
+///
+/// # Restituisce
+///
+/// * `Ok(Comunicazione)` contenente il testo e gli allegati della comunicazione.
+/// * `Err(SpaggiariError::ComunicazioneNotFound)` se la pagina non contiene né testo né allegati
+///   (ID non riconosciuto dal portale).
+///
+/// # Example
+///
+/// ```no_run
+/// use spaggiari_rs::{create_client, get_comunicazioni};
+///
+/// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+/// let client = create_client()?;
+/// let comunicazione = get_comunicazioni(&client, "phpsessid", "123", "G1234567X").await?;
+/// println!("{}", comunicazione.testo);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn get_comunicazioni(client: &Client, session_id: &str, comm_id: &str, webidentity: &str) -> Result<Comunicazione, SpaggiariError> {
+    get_comunicazioni_at(&Http::new(client.clone()), BASE_URL, session_id, comm_id, webidentity).await
+}
+
+// Come `get_comunicazioni`, usando l'indirizzo base indicato
+pub(crate) async fn get_comunicazioni_at(http: &Http, base_url: &str, session_id: &str, comm_id: &str, webidentity: &str) -> Result<Comunicazione, SpaggiariError> {
+    let text = fetch_comunicazione_at(http, base_url, session_id, comm_id, webidentity).await?;
+    parse_comunicazione(&text, comm_id)
+}
+
+// Scarica la pagina HTML grezza di una comunicazione
+pub(crate) async fn fetch_comunicazione_at(http: &Http, base_url: &str, session_id: &str, comm_id: &str, webidentity: &str) -> Result<String, SpaggiariError> {
+    let response = http
+        .send(|client| {
+            client
+                .get(format!("{}{}", base_url, PATH_COMUNICAZIONI))
+                .query(&[("action", "risposta_com"), ("com_id", comm_id)]) // Aggiunti i form data come query parameters
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+
+    trace!(status = status.as_u16(), "risposta comunicazione ricevuta");
+
+    if status.is_success() {
+        Ok(response.text().await?)
+    } else {
+        debug!(status = status.as_u16(), "token non valido o scaduto");
+        Err(SpaggiariError::ParseError {
+            details: "Il token non sembra funzionare".to_string(),
+        })
+    }
+}
+
+// Invia il consenso richiesto per visualizzare una comunicazione
+pub(crate) async fn invia_consenso_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, comm_id: &str) -> Result<(), SpaggiariError> {
+    let response = http
+        .send_once(|client| {
+            client
+                .post(format!("{}{}", base_url, PATH_COMUNICAZIONI))
+                .form(&[("action", "conferma_consenso"), ("com_id", comm_id)])
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta consenso ricevuta");
+
+    if status.is_success() {
+        Ok(())
+    } else {
+        debug!(comm_id, status = status.as_u16(), "invio del consenso fallito");
+        Err(SpaggiariError::ApiError {
+            message: format!("Invio del consenso fallito: {}", status),
+        })
+    }
+}
+
+/// Invia la conferma di lettura di una circolare.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `circolare` - La circolare di cui confermare la lettura.
+pub async fn conferma_lettura(client: &Client, session_id: &str, webidentity: &str, circolare: &Circolare) -> Result<(), SpaggiariError> {
+    conferma_lettura_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, circolare).await
+}
+
+// Come `conferma_lettura`, usando l'indirizzo base indicato
+pub(crate) async fn conferma_lettura_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, circolare: &Circolare) -> Result<(), SpaggiariError> {
+    let response = http
+        .send_once(|client| {
+            client
+                .post(format!("{}{}", base_url, PATH_COMUNICAZIONI))
+                .form(&[("action", "conferma_lettura"), ("com_id", circolare.id.as_str()), ("id_relazione", circolare.id_relazione.as_str())])
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta conferma lettura ricevuta");
+
+    if status.is_success() {
+        debug!(circolare_id = %circolare.id, "lettura confermata");
+        Ok(())
+    } else {
+        debug!(circolare_id = %circolare.id, status = status.as_u16(), "conferma di lettura fallita");
+        Err(SpaggiariError::ApiError {
+            message: format!("Conferma di lettura fallita: {}", status),
+        })
+    }
+}
+
+// Esito restituito dal portale per le azioni sulle comunicazioni (risposta, accettazione)
+#[derive(Debug, Deserialize)]
+struct EsitoAzione {
+    #[serde(default)]
+    error: Vec<String>,
+}
+
+// Invia un'azione sulla comunicazione e interpreta il JSON di conferma del portale
+async fn invia_azione_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, form: &[(&str, &str)]) -> Result<(), SpaggiariError> {
+    let response = http
+        .send_once(|client| {
+            client
+                .post(format!("{}{}", base_url, PATH_COMUNICAZIONI))
+                .form(form)
+                .header("Cookie", cookie_header(session_id, webidentity))
+        })
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta azione ricevuta");
+
+    if !status.is_success() {
+        debug!(status = status.as_u16(), "azione rifiutata dal portale");
+        return Err(SpaggiariError::ApiError {
+            message: format!("Azione rifiutata dal portale: {}", status),
+        });
+    }
+
+    let esito: EsitoAzione = parse_json_body(&response.text().await?)?;
+    if esito.error.is_empty() {
+        Ok(())
+    } else {
+        debug!(errori = ?esito.error, "il portale ha segnalato degli errori");
+        Err(SpaggiariError::ApiError { message: esito.error.join("; ") })
+    }
+}
+
+/// Invia una risposta testuale a una circolare che la richiede (`flag_risp`).
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `circolare` - La circolare a cui rispondere.
+/// * `testo` - Il testo della risposta.
+///
+/// # Restituisce
+///
+/// * `Ok(())` se il portale ha registrato la risposta.
+/// * `Err(SpaggiariError::AzioneNonPrevista)` se la circolare non prevede una risposta.
+pub async fn rispondi_comunicazione(client: &Client, session_id: &str, webidentity: &str, circolare: &Circolare, testo: &str) -> Result<(), SpaggiariError> {
+    rispondi_comunicazione_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, circolare, testo).await
+}
+
+// Come `rispondi_comunicazione`, usando l'indirizzo base indicato
+pub(crate) async fn rispondi_comunicazione_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, circolare: &Circolare, testo: &str) -> Result<(), SpaggiariError> {
+    if !circolare.richiede_risposta() {
+        return Err(SpaggiariError::AzioneNonPrevista {
+            circolare_id: circolare.id.clone(),
+            azione: "risposta".to_string(),
+        });
+    }
+
+    let form = [
+        ("action", "risposta_testo"),
+        ("com_id", circolare.id.as_str()),
+        ("id_relazione", circolare.id_relazione.as_str()),
+        ("testo", testo),
+    ];
+    invia_azione_at(http, base_url, session_id, webidentity, &form).await?;
+    debug!(circolare_id = %circolare.id, "risposta inviata");
+    Ok(())
+}
+
+/// Accetta o rifiuta una circolare che richiede una decisione esplicita (`flag_accettazione`).
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `circolare` - La circolare da accettare o rifiutare.
+/// * `accept` - `true` per accettare, `false` per rifiutare.
+///
+/// # Restituisce
+///
+/// * `Ok(())` se il portale ha registrato la decisione.
+/// * `Err(SpaggiariError::AzioneNonPrevista)` se la circolare non prevede l'accettazione.
+pub async fn accetta_comunicazione(client: &Client, session_id: &str, webidentity: &str, circolare: &Circolare, accept: bool) -> Result<(), SpaggiariError> {
+    accetta_comunicazione_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, circolare, accept).await
+}
+
+// Come `accetta_comunicazione`, usando l'indirizzo base indicato
+pub(crate) async fn accetta_comunicazione_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str, circolare: &Circolare, accept: bool) -> Result<(), SpaggiariError> {
+    if !circolare.richiede_accettazione() {
+        return Err(SpaggiariError::AzioneNonPrevista {
+            circolare_id: circolare.id.clone(),
+            azione: "accettazione".to_string(),
+        });
+    }
+
+    let action = if accept { "accetta_com" } else { "rifiuta_com" };
+    let form = [("action", action), ("com_id", circolare.id.as_str()), ("id_relazione", circolare.id_relazione.as_str())];
+    invia_azione_at(http, base_url, session_id, webidentity, &form).await?;
+    debug!(circolare_id = %circolare.id, action, "decisione inviata");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::circolare;
+    use wiremock::matchers::{body_string_contains, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn mock_file_server(body: impl Into<Vec<u8>>) -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/file"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"circolare.pdf\"")
+                    .set_body_bytes(body),
+            )
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_download_file_appends_server_filename_to_directory() {
+        let server = mock_file_server(b"%PDF-1.4").await;
+        let dir = tempfile::tempdir().unwrap();
+        let client = Client::new();
+
+        let filepath = download_file(&client, &format!("{}/file", server.uri()), "sess", "G1234567X", dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(filepath, format!("{}/circolare.pdf", dir.path().display()));
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"%PDF-1.4");
+    }
+
+    #[tokio::test]
+    async fn test_download_file_checked_reports_bytes() {
+        let server = mock_file_server(b"%PDF-1.4").await;
+        let dir = tempfile::tempdir().unwrap();
+
+        let result = download_file_checked(&Client::new(), &format!("{}/file", server.uri()), "sess", "G1234567X", dir.path().to_str().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(result.path, dir.path().join("circolare.pdf"));
+        assert_eq!(result.bytes, 8);
+    }
+
+    #[tokio::test]
+    async fn test_download_file_checked_rejects_truncated_body() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            // Il server dichiara 100 byte ma chiude la connessione dopo 8
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Disposition: attachment; filename=\"circolare.pdf\"\r\nContent-Length: 100\r\nConnection: close\r\n\r\n%PDF-1.4")
+                .await
+                .unwrap();
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let result = download_file_checked(&Client::new(), &url, "sess", "G1234567X", dir.path().to_str().unwrap()).await;
+
+        assert!(matches!(result, Err(SpaggiariError::NetworkError(_))), "{:?}", result);
+        assert!(!dir.path().join("circolare.pdf").exists());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_download_file_bytes_ignores_huge_content_length() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            // 10 GB dichiarati: l'allocazione anticipata non deve seguire questo valore
+            socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10000000000\r\nConnection: close\r\n\r\n%PDF-1.4").await.unwrap();
+        });
+
+        let result = download_file_bytes(&Client::new(), &url, "sess", "G1234567X").await;
+
+        assert!(result.is_err(), "il body è troncato rispetto a Content-Length");
+        server.await.unwrap();
+    }
+
+    fn allegato(id: &str) -> Allegato {
+        Allegato {
+            comunicazione_id: "101".to_string(),
+            allegato_id: id.to_string(),
+            nome: None,
+            incorporato: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_bytes_concurrent_keeps_order() {
+        let server = MockServer::start().await;
+        for i in 1..=6u64 {
+            // I primi allegati rispondono più lentamente, così terminano per ultimi
+            Mock::given(method("GET"))
+                .and(query_param("com_id", i.to_string()))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", format!("attachment; filename=\"file{}.txt\"", i))
+                        .set_body_string(format!("contenuto {}", i))
+                        .set_delay(std::time::Duration::from_millis(20 * (7 - i))),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let allegati: Vec<Allegato> = (1..=6).map(|i| allegato(&i.to_string())).collect();
+        let files = download_allegati_bytes_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", allegati, 3).await.unwrap();
+
+        let nomi: Vec<&str> = files.iter().map(|(nome, _)| nome.as_str()).collect();
+        assert_eq!(nomi, ["file1.txt", "file2.txt", "file3.txt", "file4.txt", "file5.txt", "file6.txt"]);
+        assert_eq!(files[3].1, b"contenuto 4");
+    }
+
+    #[tokio::test]
+    async fn test_download_file_bytes_with_progress_reports_total() {
+        let server = MockServer::start().await;
+        let body = vec![7u8; 64 * 1024];
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone())).mount(&server).await;
+
+        let avanzamento = std::sync::Mutex::new(Vec::new());
+        let (_, content) = download_file_bytes_with_progress(&Client::new(), &format!("{}/file", server.uri()), "sess", "G1234567X", |scaricati, totale| {
+            avanzamento.lock().unwrap().push((scaricati, totale));
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(content, body);
+        let avanzamento = avanzamento.into_inner().unwrap();
+        assert_eq!(avanzamento.last(), Some(&(body.len() as u64, Some(body.len() as u64))));
+        assert!(avanzamento.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_download_file_streaming_writes_all_chunks() {
+        let server = MockServer::start().await;
+        // Corpo di più blocchi con contenuto non ripetitivo
+        let body: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", r#"attachment; filename="grande.bin""#)
+                    .set_body_bytes(body.clone()),
+            )
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("sub");
+        let path = download_file_streaming(&Client::new(), &format!("{}/file", server.uri()), "sess", "G1234567X", dest.to_str().unwrap())
+            .await
+            .unwrap();
+
+        assert!(path.ends_with("/grande.bin"));
+        assert_eq!(std::fs::read(&path).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_concurrent_surfaces_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).and(query_param("com_id", "2")).respond_with(ResponseTemplate::new(500)).mount(&server).await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Content-Disposition", "attachment; filename=ok.txt").set_body_string("ok"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let allegati: Vec<Allegato> = (1..=4).map(|i| allegato(&i.to_string())).collect();
+        let result = download_allegati_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &allegati, dir.path().to_str().unwrap(), 2).await;
+        assert!(matches!(result, Err(SpaggiariError::ParseError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_flat_avoids_collisions() {
+        let server = MockServer::start().await;
+        for (com_id, body) in [("11", "primo"), ("22", "secondo")] {
+            Mock::given(method("GET"))
+                .and(path(PATH_BACHECA))
+                .and(query_param("com_id", com_id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", "attachment; filename=\"circolare.pdf\"")
+                        .set_body_string(body),
+                )
+                .mount(&server)
+                .await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().to_str().unwrap();
+        let client = Client::new();
+        let allegato = |id: &str| Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: id.to_string(),
+            nome: None,
+            incorporato: None,
+        };
+
+        let primo = download_allegati_flat_at(&Http::new(client.clone()), &server.uri(), "sess", "G1234567X", 5, &[allegato("11")], dest, DEFAULT_NAME_TEMPLATE)
+            .await
+            .unwrap();
+        let secondo = download_allegati_flat_at(&Http::new(client.clone()), &server.uri(), "sess", "G1234567X", 6, &[allegato("22")], dest, DEFAULT_NAME_TEMPLATE)
+            .await
+            .unwrap();
+
+        assert_eq!(primo, [format!("{}/5_11_circolare.pdf", dest)]);
+        assert_eq!(secondo, [format!("{}/6_22_circolare.pdf", dest)]);
+        assert_eq!(std::fs::read_to_string(&primo[0]).unwrap(), "primo");
+        assert_eq!(std::fs::read_to_string(&secondo[0]).unwrap(), "secondo");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_by_type_only_pdf() {
+        let server = MockServer::start().await;
+        // Il PDF risponde alla HEAD, il PNG no e viene riconosciuto dai primi byte
+        Mock::given(method("HEAD"))
+            .and(query_param("com_id", "1"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Content-Type", "application/pdf"))
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(query_param("com_id", "2"))
+            .respond_with(ResponseTemplate::new(405))
+            .mount(&server)
+            .await;
+        for (com_id, nome, body) in [("1", "circolare.pdf", b"%PDF-1.4".to_vec()), ("2", "foto", vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A])] {
+            Mock::given(method("GET"))
+                .and(query_param("com_id", com_id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", format!("attachment; filename=\"{}\"", nome).as_str())
+                        .set_body_bytes(body),
+                )
+                .mount(&server)
+                .await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().to_str().unwrap();
+        let allegato = |id: &str| Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: id.to_string(),
+            nome: None,
+            incorporato: None,
+        };
+
+        let paths = download_allegati_by_type_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &[allegato("1"), allegato("2")], &[FileType::Pdf], dest)
+            .await
+            .unwrap();
+
+        assert_eq!(paths, [format!("{}/circolare.pdf", dest)]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+        let requests = server.received_requests().await.unwrap();
+        let range = requests.iter().find(|r| r.headers.contains_key("range")).unwrap();
+        assert_eq!(range.url.query(), Some("action=file_download&com_id=2"));
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_sorted_by_type() {
+        let server = MockServer::start().await;
+        for (com_id, nome, body) in [("1", "circolare.pdf", b"%PDF-1.4".to_vec()), ("2", "foto.png", vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A])] {
+            Mock::given(method("GET"))
+                .and(query_param("com_id", com_id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", format!("attachment; filename=\"{}\"", nome).as_str())
+                        .set_body_bytes(body),
+                )
+                .mount(&server)
+                .await;
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let allegato = |id: &str| Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: id.to_string(),
+            nome: None,
+            incorporato: None,
+        };
+
+        let report = download_allegati_sorted_at(
+            &Http::new(Client::new()),
+            &server.uri(),
+            "sess",
+            "G1234567X",
+            &[allegato("1"), allegato("2")],
+            dir.path().to_str().unwrap(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(report, BTreeMap::from([("images".to_string(), 1), ("pdf".to_string(), 1)]));
+        assert!(dir.path().join("pdf/circolare.pdf").is_file());
+        assert!(dir.path().join("images/foto.png").is_file());
+    }
+
+    // Archivio ZIP in memoria con i file indicati
+    #[cfg(feature = "zip")]
+    fn zip_bytes(files: &[(&str, &str)]) -> Vec<u8> {
+        use std::io::Write;
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (nome, contenuto) in files {
+            writer.start_file(*nome, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(contenuto.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "zip")]
+    #[tokio::test]
+    async fn test_download_allegati_extracts_zip() {
+        let server = MockServer::start().await;
+        // Nome senza estensione: l'archivio è riconosciuto dai magic bytes
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"moduli\"")
+                    .set_body_bytes(zip_bytes(&[("autorizzazione.txt", "firma qui"), ("info/orari.txt", "8:00")])),
+            )
+            .mount(&server)
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let allegato = Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: "5".to_string(),
+            nome: None,
+            incorporato: None,
+        };
+
+        let paths = download_allegati_extract_zip_at(
+            &Http::new(Client::new()),
+            &server.uri(),
+            "sess",
+            "G1234567X",
+            std::slice::from_ref(&allegato),
+            dir.path().to_str().unwrap(),
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(std::fs::read_to_string(dir.path().join("moduli/autorizzazione.txt")).unwrap(), "firma qui");
+        assert_eq!(std::fs::read_to_string(dir.path().join("moduli/info/orari.txt")).unwrap(), "8:00");
+        assert!(!dir.path().join("moduli.zip").exists());
+
+        let paths = download_allegati_extract_zip_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &[allegato], dir.path().to_str().unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(paths.last(), Some(&format!("{}/moduli.zip", dir.path().display())));
+        assert!(dir.path().join("moduli.zip").is_file());
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_is_archivio_zip_ignores_office_documents() {
+        let zip = zip_bytes(&[("a.txt", "a")]);
+        assert!(is_archivio_zip("allegati.zip", &zip));
+        assert!(!is_archivio_zip("verbale.docx", &zip));
+        assert!(!is_archivio_zip("finto.zip", b"%PDF-1.4"));
+    }
+
+    #[tokio::test]
+    async fn test_download_file_to_exact_path() {
+        let server = mock_file_server(b"%PDF-1.4").await;
+        let dir = tempfile::tempdir().unwrap();
+        let exact = dir.path().join("sub/mio_nome.pdf");
+        let client = Client::new();
+
+        let filepath = download_file_to(&client, &format!("{}/file", server.uri()), "sess", "G1234567X", exact.to_str().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(filepath, exact.to_str().unwrap());
+        assert_eq!(std::fs::read(&exact).unwrap(), b"%PDF-1.4");
+        assert!(!dir.path().join("sub/circolare.pdf").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_file_stream_feeds_all_chunks() {
+        let body: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+        let server = mock_file_server(body.clone()).await;
+        let client = Client::new();
+
+        let mut buffer = Vec::new();
+        let mut chunks = 0;
+        let filename = download_file_stream(&client, &format!("{}/file", server.uri()), "sess", "G1234567X", |chunk: &[u8]| {
+            chunks += 1;
+            buffer.extend_from_slice(chunk);
+            Ok::<(), std::io::Error>(())
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(filename, "circolare.pdf");
+        assert_eq!(buffer, body);
+        assert!(chunks > 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_file_stream_propagates_sink_error() {
+        let server = mock_file_server(b"%PDF-1.4").await;
+        let client = Client::new();
+
+        let result = download_file_stream(&client, &format!("{}/file", server.uri()), "sess", "G1234567X", |_: &[u8]| Err("upload fallito")).await;
+
+        assert!(matches!(result, Err(SpaggiariError::Generic(msg)) if msg.contains("upload fallito")));
+    }
+
+    #[tokio::test]
+    async fn test_download_with_progress_reports_cumulative_total() {
+        let server = MockServer::start().await;
+        // Senza vincolo sul metodo: la stessa risposta serve sia HEAD sia GET
+        for (com_id, size) in [("1", 70_000usize), ("2", 130_000usize)] {
+            Mock::given(query_param("com_id", com_id))
+                .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![7u8; size]))
+                .mount(&server)
+                .await;
+        }
+        let allegati: Vec<Allegato> = ["1", "2"]
+            .into_iter()
+            .map(|com_id| Allegato {
+                comunicazione_id: com_id.to_string(),
+                allegato_id: com_id.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+
+        let progress = std::sync::Mutex::new(Vec::new());
+        let results = download_allegati_bytes_with_progress_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", allegati, |downloaded, total| {
+            progress.lock().unwrap().push((downloaded, total))
+        })
+        .await
+        .unwrap();
+
+        let progress = progress.into_inner().unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].1.len(), 70_000);
+        assert_eq!(results[1].1.len(), 130_000);
+        assert_eq!(progress.first(), Some(&(0, Some(200_000))));
+        assert_eq!(progress.last(), Some(&(200_000, Some(200_000))));
+        assert!(progress.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    #[tokio::test]
+    async fn test_download_with_progress_keeps_input_order() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("com_id", "9"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"orario.pdf\"")
+                    .set_body_bytes(b"%PDF-1.4".to_vec()),
+            )
+            .mount(&server)
+            .await;
+        let scaricato = |allegato_id: &str| Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: allegato_id.to_string(),
+            nome: None,
+            incorporato: None,
+        };
+        let comunicazione = parse_comunicazione(include_str!("../tests/fixtures/comunicazione_con_allegati_incorporati.html"), "555").unwrap();
+        let mut incorporati = comunicazione.allegati.into_iter().filter(Allegato::is_incorporato);
+        let allegati = vec![incorporati.next().unwrap(), scaricato("9"), incorporati.next().unwrap()];
+
+        let results = download_allegati_bytes_with_progress_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", allegati, |_, _| {})
+            .await
+            .unwrap();
+
+        let nomi: Vec<&str> = results.iter().map(|(nome, _)| nome.as_str()).collect();
+        assert_eq!(nomi, ["modulo.pdf", "orario.pdf", "profile"]);
+        assert_eq!(results[1].1, b"%PDF-1.4");
+    }
+
+    #[test]
+    fn test_parse_comunicazione_with_embedded_pdf() {
+        let comunicazione = parse_comunicazione(include_str!("../tests/fixtures/comunicazione_con_allegati_incorporati.html"), "555").unwrap();
+
+        assert_eq!(comunicazione.allegati.len(), 3);
+        assert!(!comunicazione.allegati[0].is_incorporato());
+        assert_eq!(comunicazione.allegati[0].allegato_id, "777");
+
+        let incorporato = comunicazione.allegati[1].incorporato.as_ref().unwrap();
+        assert_eq!(comunicazione.allegati[1].comunicazione_id, "555");
+        assert_eq!(incorporato.nome, "modulo.pdf");
+        assert_eq!(incorporato.mime, "application/pdf");
+        assert!(incorporato.contenuto.starts_with(b"%PDF-1.4"));
+    }
+
+    #[tokio::test]
+    async fn test_download_embedded_attachments_stay_in_destination() {
+        let comunicazione = parse_comunicazione(include_str!("../tests/fixtures/comunicazione_con_allegati_incorporati.html"), "555").unwrap();
+        let incorporati: Vec<Allegato> = comunicazione.allegati.into_iter().filter(Allegato::is_incorporato).collect();
+        let radice = tempfile::tempdir().unwrap();
+        let cartella = radice.path().join("a").join("b");
+
+        download_allegati(&Client::new(), "sess", "G1234567X", &incorporati, cartella.to_str().unwrap()).await.unwrap();
+
+        assert_eq!(std::fs::read(cartella.join("profile")).unwrap(), b"echo ciao");
+        assert!(cartella.join("modulo.pdf").is_file());
+        assert!(!radice.path().join(".profile").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_skip_existing_stays_in_destination() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH_BACHECA))
+            .and(query_param("com_id", "9"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename*=UTF-8''%2F..%2F..%2Forario.pdf")
+                    .set_body_bytes(b"%PDF-orario".to_vec()),
+            )
+            .mount(&server)
+            .await;
+
+        let comunicazione = parse_comunicazione(include_str!("../tests/fixtures/comunicazione_con_allegati_incorporati.html"), "555").unwrap();
+        let mut allegati: Vec<Allegato> = comunicazione.allegati.into_iter().filter(Allegato::is_incorporato).collect();
+        allegati.push(Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: "9".to_string(),
+            nome: None,
+            incorporato: None,
+        });
+        let radice = tempfile::tempdir().unwrap();
+        let destinazione = radice.path().join("a").join("b").to_string_lossy().to_string();
+
+        let esiti = download_allegati_skip_existing_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &allegati, &destinazione, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            esiti,
+            [
+                DownloadStatus::Scaricato(format!("{}/modulo.pdf", destinazione)),
+                DownloadStatus::Scaricato(format!("{}/profile", destinazione)),
+                DownloadStatus::Scaricato(format!("{}/orario.pdf", destinazione)),
+            ]
+        );
+        assert!(!radice.path().join("orario.pdf").exists() && !radice.path().join(".profile").exists());
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_skip_existing_second_run_writes_nothing() {
+        let server = MockServer::start().await;
+        // Una HEAD per ogni esecuzione, una sola GET: la seconda trova il file già presente
+        for (metodo, richieste) in [("HEAD", 2), ("GET", 1)] {
+            Mock::given(method(metodo))
+                .and(path(PATH_BACHECA))
+                .and(query_param("com_id", "9"))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", "attachment; filename=\"orario.pdf\"")
+                        .set_body_bytes(b"%PDF-orario".to_vec()),
+                )
+                .expect(richieste)
+                .mount(&server)
+                .await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let destinazione = dir.path().to_string_lossy().to_string();
+        let allegati = [Allegato {
+            comunicazione_id: "1".to_string(),
+            allegato_id: "9".to_string(),
+            nome: None,
+            incorporato: None,
+        }];
+        let client = Client::new();
+
+        let primo = download_allegati_skip_existing_at(&Http::new(client.clone()), &server.uri(), "sess", "G1234567X", &allegati, &destinazione, 2)
+            .await
+            .unwrap();
+        assert_eq!(primo, [DownloadStatus::Scaricato(format!("{}/orario.pdf", destinazione))]);
+
+        // Stessa dimensione, contenuto diverso: se il file venisse riscritto tornerebbe quello del server
+        std::fs::write(dir.path().join("orario.pdf"), b"gia_salvato").unwrap();
+        let secondo = download_allegati_skip_existing_at(&Http::new(client.clone()), &server.uri(), "sess", "G1234567X", &allegati, &destinazione, 2)
+            .await
+            .unwrap();
+        assert!(secondo[0].is_skipped());
+        assert_eq!(std::fs::read(dir.path().join("orario.pdf")).unwrap(), b"gia_salvato");
+    }
+
+    #[test]
+    fn test_allegato_download_url() {
+        let allegato = Allegato {
+            comunicazione_id: "555".to_string(),
+            allegato_id: "777".to_string(),
+            nome: None,
+            incorporato: None,
+        };
+
+        assert_eq!(
+            allegato.download_url(BASE_URL),
+            "https://web.spaggiari.eu/sif/app/default/bacheca_personale.php?action=file_download&com_id=777"
+        );
+        assert_eq!(
+            allegato.download_url("http://127.0.0.1:8080"),
+            "http://127.0.0.1:8080/sif/app/default/bacheca_personale.php?action=file_download&com_id=777"
+        );
+    }
+
+    #[test]
+    fn test_extract_allegati_incorporati_skips_invalid_uri() {
+        let html = r#"<a href="data:application/pdf;base64,@@@">rotto</a><a href="data:text/plain,ciao">non base64</a><embed src="data:image/png;base64,iVBORw0KGgo=">"#;
+
+        let allegati = extract_allegati_incorporati(html).unwrap();
+
+        assert_eq!(allegati.len(), 1);
+        assert_eq!(allegati[0].nome, "allegato_incorporato_1.png");
+        assert_eq!(allegati[0].contenuto, [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]);
+    }
+
+    #[test]
+    fn test_filter_by_tipo() {
+        let mut circ = circolare("1", 1, "Circolari");
+        circ.tipo_com_filtro = "CIR".to_string();
+        let mut pagamento = circolare("2", 2, "Pagamenti");
+        pagamento.tipo_com = "P".to_string();
+        pagamento.tipo_com_filtro = "PAG".to_string();
+        let mut circ_nuova = circolare("3", 3, "Circolari");
+        circ_nuova.tipo_com_filtro = "CIR".to_string();
+
+        let bacheca = Bacheca {
+            read: vec![circ, pagamento],
+            msg_new: Some(vec![circ_nuova]),
+            extra: HashMap::new(),
+        };
+
+        let ids = |circolari: Vec<&Circolare>| circolari.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(bacheca.all().collect()), ["1", "2", "3"]);
+        assert_eq!(ids(bacheca.filter_by_tipo("cir")), ["1", "3"]);
+        assert_eq!(ids(bacheca.filter_by_tipo("P")), ["2"]);
+        assert!(bacheca.filter_by_tipo("ALT").is_empty());
+    }
+
+    #[test]
+    fn test_conteggio_mensile() {
+        let mut settembre = circolare("1", 1, "Circolari");
+        settembre.data_start = "2024-09-12".to_string();
+        let mut settembre_bis = circolare("2", 2, "Circolari");
+        settembre_bis.data_start = "30-09-2024 08:00:00".to_string();
+        let mut ottobre = circolare("3", 3, "Avvisi");
+        ottobre.data_start = "2024-10-01".to_string();
+        let mut senza_data = circolare("4", 4, "Avvisi");
+        senza_data.data_start = String::new();
+
+        let bacheca = Bacheca {
+            read: vec![settembre, ottobre],
+            msg_new: Some(vec![settembre_bis, senza_data]),
+            extra: HashMap::new(),
+        };
+
+        let conteggio = bacheca.conteggio_mensile();
+        assert_eq!(conteggio.len(), 2);
+        assert_eq!(conteggio[&(2024, 9)], 2);
+        assert_eq!(conteggio[&(2024, 10)], 1);
+        assert_eq!(bacheca.date_non_valide(), 1);
+    }
+
+    #[test]
+    fn test_circolare_display() {
+        let mut c = circolare("1", 52, "Circolari");
+        c.titolo = "Attività di orientamento: novità per l'a.s. 2024/25 ".to_string();
+
+        assert_eq!(c.to_string(), "[52] Attività di orientamento: novità per l'a.s. 2024/25 (Circolari, 2024-09-12)");
+        assert_eq!(c.summary(), c.to_string());
+    }
+
+    #[test]
+    fn test_circolare_date_parsed() {
+        let mut c = circolare("1", 1, "Circolari");
+        c.data_start = "2024-09-12".to_string();
+        c.data_stop = "30-09-2024".to_string();
+        c.modificato = "2024-09-13 10:15:00".to_string();
+
+        assert_eq!(c.data_start_parsed().unwrap(), NaiveDate::from_ymd_opt(2024, 9, 12));
+        assert_eq!(c.data_stop_parsed().unwrap(), NaiveDate::from_ymd_opt(2024, 9, 30));
+        assert_eq!(c.modificato_parsed().unwrap(), NaiveDate::from_ymd_opt(2024, 9, 13));
+        assert_eq!(c.evento_data_parsed().unwrap(), None);
+
+        c.data_start = "12/09/2024".to_string();
+        assert!(matches!(c.data_start_parsed(), Err(SpaggiariError::ParseError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_rispondi_comunicazione() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path(PATH_COMUNICAZIONI))
+            .and(body_string_contains("action=risposta_testo"))
+            .and(body_string_contains("com_id=101"))
+            .and(body_string_contains("testo=Parteciper%C3%B2"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"error":[]}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut da_rispondere = circolare("101", 1, "Circolari");
+        da_rispondere.flag_risp = "1".to_string();
+        rispondi_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &da_rispondere, "Parteciperò")
+            .await
+            .unwrap();
+
+        let result = rispondi_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &circolare("102", 2, "Circolari"), "Ok").await;
+        assert!(matches!(result, Err(SpaggiariError::AzioneNonPrevista { azione, .. }) if azione == "risposta"));
+    }
+
+    #[tokio::test]
+    async fn test_accetta_comunicazione_actions() {
+        let server = MockServer::start().await;
+        for action in ["accetta_com", "rifiuta_com"] {
+            Mock::given(method("POST"))
+                .and(path(PATH_COMUNICAZIONI))
+                .and(body_string_contains(format!("action={}", action)))
+                .and(body_string_contains("com_id=101"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"error":[]}"#))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let mut da_accettare = circolare("101", 1, "Circolari");
+        da_accettare.flag_accettazione = Some("1".to_string());
+        accetta_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &da_accettare, true)
+            .await
+            .unwrap();
+        accetta_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &da_accettare, false)
+            .await
+            .unwrap();
+
+        for flag in [None, Some("0".to_string()), Some(String::new())] {
+            let mut senza_accettazione = circolare("102", 2, "Circolari");
+            senza_accettazione.flag_accettazione = flag;
+            let result = accetta_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &senza_accettazione, true).await;
+            assert!(matches!(result, Err(SpaggiariError::AzioneNonPrevista { azione, .. }) if azione == "accettazione"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rispondi_comunicazione_portal_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"error":["Termine scaduto"]}"#))
+            .mount(&server)
+            .await;
+
+        let mut da_rispondere = circolare("101", 1, "Circolari");
+        da_rispondere.flag_risp = "1".to_string();
+        let result = rispondi_comunicazione_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X", &da_rispondere, "Ok").await;
+        assert!(matches!(result, Err(SpaggiariError::ApiError { message }) if message == "Termine scaduto"));
+    }
+
+    #[test]
+    fn test_tipo_comunicazione_from_filtro() {
+        assert_eq!(TipoComunicazione::from_filtro("GEN"), TipoComunicazione::Generale);
+        assert_eq!(TipoComunicazione::from_filtro("alunno"), TipoComunicazione::Alunno);
+        assert_eq!(TipoComunicazione::from_filtro(" PAGAMENTI "), TipoComunicazione::Pagamenti);
+        assert_eq!(TipoComunicazione::from_filtro("XYZ"), TipoComunicazione::Other("XYZ".to_string()));
+        assert_eq!(crate::test_support::circolare("101", 1, "Circolari").tipo(), TipoComunicazione::Generale);
+    }
+
+    const CIRCOLARE_JSON: &str = r#"{"id":"101","codice":"12","titolo":"Uscita didattica","testo":"","data_start":"2024-09-12","data_stop":"2024-09-30","tipo_com":"C","tipo_com_filtro":"GEN","tipo_com_desc":"Circolari","nome_file":null,"richieste":null,"id_relazione":"1","conf_lettura":"0","flag_risp":"0","testo_risp":null,"file_risp":null,"flag_accettazione":null,"modificato":"","evento_data":""}"#;
+
+    #[test]
+    fn test_extract_filename_from_disposition() {
+        assert_eq!(extract_filename_from_disposition(r#"attachment; filename="circolare 12.pdf""#).as_deref(), Some("circolare 12.pdf"));
+        assert_eq!(extract_filename_from_disposition("attachment; filename=orario.pdf; size=100").as_deref(), Some("orario.pdf"));
+        assert_eq!(
+            extract_filename_from_disposition("attachment; filename*=UTF-8''attivit%C3%A0%20extra.pdf").as_deref(),
+            Some("attività extra.pdf")
+        );
+        assert_eq!(
+            extract_filename_from_disposition(r#"attachment; filename="attivita.pdf"; filename*=utf-8''attivit%C3%A0.pdf"#).as_deref(),
+            Some("attività.pdf")
+        );
+        assert_eq!(extract_filename_from_disposition("attachment; filename*=ISO-8859-1'it'attivit%E0.pdf").as_deref(), Some("attività.pdf"));
+        // Codifica non valida: si ripiega sulla forma semplice
+        assert_eq!(
+            extract_filename_from_disposition(r#"attachment; filename*=UTF-8''%ZZ; filename="fallback.pdf""#).as_deref(),
+            Some("fallback.pdf")
+        );
+        assert_eq!(extract_filename_from_disposition("inline"), None);
+    }
+
+    #[test]
+    fn test_extract_filename_from_disposition_strips_path() {
+        assert_eq!(extract_filename_from_disposition("attachment; filename*=UTF-8''%2F..%2F..%2F.bashrc").as_deref(), Some("bashrc"));
+        assert_eq!(extract_filename_from_disposition("attachment; filename*=UTF-8''..%5C..%5Cboot.ini").as_deref(), Some("boot.ini"));
+        assert_eq!(extract_filename_from_disposition(r#"attachment; filename="../../orario.pdf""#).as_deref(), Some("orario.pdf"));
+        assert_eq!(extract_filename_from_disposition("attachment; filename*=UTF-8''modulo%00.pdf").as_deref(), Some("modulo.pdf"));
+        assert_eq!(extract_filename_from_disposition("attachment; filename*=UTF-8''..%2F..").as_deref(), Some("file_sconosciuto"));
+    }
+
+    #[test]
+    fn test_cookie_header_uses_identity() {
+        let cookie = cookie_header("sess", "X9876543Y");
+        assert_eq!(cookie, "PHPSESSID=sess; webidentity=X9876543Y");
+        assert!(!cookie.contains("G13070983V"));
+    }
+
+    #[tokio::test]
+    async fn test_download_sends_identity_cookie() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=X9876543Y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("ok"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let (_, content) = download_file_bytes(&Client::new(), &format!("{}/file", server.uri()), "sess", "X9876543Y").await.unwrap();
+        assert_eq!(content, b"ok");
+    }
+
+    #[tokio::test]
+    async fn test_get_backeca_login_page_is_invalid_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH_BACHECA))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/login_page.html")))
+            .mount(&server)
+            .await;
+
+        let result = get_backeca_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X").await;
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)));
+        assert!(!crate::login::test_session_token_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X").await.unwrap());
+    }
+
+    #[test]
+    fn test_parse_comunicazione_login_page_is_invalid_token() {
+        let result = parse_comunicazione(include_str!("../tests/fixtures/login_page.html"), "123");
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazioni_unknown_id_is_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH_COMUNICAZIONI))
+            .and(query_param("com_id", "999999"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/comunicazione_inesistente.html")))
+            .mount(&server)
+            .await;
+
+        let result = get_comunicazioni_at(&Http::new(Client::new()), &server.uri(), "sess", "999999", "G1234567X").await;
+        assert!(matches!(result, Err(SpaggiariError::ComunicazioneNotFound(id)) if id == "999999"));
+    }
+
+    #[tokio::test]
+    async fn test_get_backeca_html_error_includes_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH_BACHECA))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html><body><h1>Manutenzione programmata</h1></body></html>"))
+            .mount(&server)
+            .await;
+
+        let result = get_backeca_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X").await;
+        assert!(matches!(result, Err(SpaggiariError::ParseError { details }) if details.contains("<h1>Manutenzione programmata</h1>")));
+    }
+
+    #[tokio::test]
+    async fn test_get_backeca_against_mock_server() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(PATH_BACHECA))
+            .and(query_param("action", "get_comunicazioni"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":[]}}"#, CIRCOLARE_JSON)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let bacheca = get_backeca_at(&Http::new(Client::new()), &server.uri(), "sess", "G1234567X").await.unwrap();
+
+        assert_eq!(bacheca.read.len(), 1);
+        assert_eq!(bacheca.read[0].id, "101");
+        assert_eq!(bacheca.msg_new.map(|m| m.len()), Some(0));
+    }
+
+    #[test]
+    fn test_parse_bacheca_with_bom() {
+        let json = format!("\u{feff}\r\n  {{\"read\":[{}],\"msg_new\":null}}\n", CIRCOLARE_JSON);
+
+        let bacheca = parse_bacheca(&json).unwrap();
+        assert_eq!(bacheca.read.len(), 1);
+        assert_eq!(bacheca.read[0].codice, 12);
+
+        let (bacheca, errori) = parse_bacheca_lenient(&json).unwrap();
+        assert_eq!(bacheca.read.len(), 1);
+        assert!(errori.is_empty());
+    }
+
+    #[test]
+    fn test_parse_bacheca_wrapped_in_html() {
+        let json = format!(r#"{{"read":[{}],"msg_new":null}}"#, CIRCOLARE_JSON).replace('"', "&quot;");
+        let html = format!("<html><body><pre>{}</pre></body></html>", json);
+
+        let bacheca = parse_bacheca(&html).unwrap();
+        assert_eq!(bacheca.read.len(), 1);
+        assert_eq!(bacheca.read[0].titolo, "Uscita didattica");
+    }
+
+    #[test]
+    fn test_parse_comunicazione_consent_page() {
+        let html = r#"<html><body><div class="comunicazione_consenso"><form><button>Acconsento</button></form></div></body></html>"#;
+        assert!(matches!(parse_comunicazione(html, "7"), Err(SpaggiariError::ConsensoRichiesto(id)) if id == "7"));
+
+        let html = r#"<div class="comunicazione_consenso">Consenso già dato</div><div class="comunicazione_testo">Testo</div>"#;
+        assert_eq!(parse_comunicazione(html, "7").map(|c| c.testo).ok().as_deref(), Some("Testo"));
+    }
+
+    #[test]
+    fn test_parse_bacheca_captures_unknown_fields() {
+        let json = format!(r#"{{"read":[{}],"msg_new":null,"nuovo_campo":{{"a":1}}}}"#, CIRCOLARE_JSON);
+
+        let bacheca = parse_bacheca(&json).unwrap();
+        assert_eq!(bacheca.campi_sconosciuti(), ["nuovo_campo"]);
+        assert_eq!(bacheca.extra["nuovo_campo"]["a"], 1);
+
+        let (bacheca, _) = parse_bacheca_lenient(&json).unwrap();
+        assert_eq!(bacheca.campi_sconosciuti(), ["nuovo_campo"]);
+
+        let bacheca = parse_bacheca(&format!(r#"{{"read":[{}],"msg_new":null}}"#, CIRCOLARE_JSON)).unwrap();
+        assert!(bacheca.campi_sconosciuti().is_empty());
+    }
+
+    #[test]
+    fn test_bacheca_unread() {
+        let mut bacheca = Bacheca {
+            read: vec![circolare("1", 1, "Circolari")],
+            msg_new: None,
+            extra: HashMap::new(),
+        };
+        assert!(bacheca.unread().is_empty());
+        assert_eq!(bacheca.unread_count(), 0);
+
+        bacheca.msg_new = Some(vec![circolare("2", 2, "Circolari"), circolare("3", 3, "Circolari")]);
+        assert_eq!(bacheca.unread().iter().map(|c| c.codice).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(bacheca.unread_count(), 2);
+    }
+
+    #[test]
+    fn test_bacheca_to_csv() {
+        let mut nuova = circolare("302", 8, "Circolari");
+        nuova.titolo = "Uscita, classi terze".to_string();
+        nuova.nome_file = Some("uscita.pdf".to_string());
+        let bacheca = Bacheca {
+            read: vec![],
+            msg_new: Some(vec![nuova]),
+            extra: HashMap::new(),
+        };
+
+        let mut out = Vec::new();
+        bacheca.to_csv(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "stato,codice,id,titolo,data_start,data_stop,tipo_com_desc,nome_file\nnuova,8,302,\"Uscita, classi terze\",2024-09-12,2024-09-30,Circolari,uscita.pdf\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bacheca.csv");
+        bacheca.export_csv_file(&path).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("stato,codice,id,titolo"));
+    }
+
+    #[test]
+    fn test_bacheca_serialize_round_trip() {
+        let json = format!(r#"{{"read":[{}],"msg_new":[{}],"nuovo_campo":{{"a":1}}}}"#, CIRCOLARE_JSON, CIRCOLARE_JSON);
+        let bacheca = parse_bacheca(&json).unwrap();
+
+        let serializzata = serde_json::to_string(&bacheca).unwrap();
+        assert_eq!(parse_bacheca(&serializzata).unwrap(), bacheca);
+
+        let comunicazione = Comunicazione {
+            testo: "Testo".to_string(),
+            allegati: vec![Allegato {
+                comunicazione_id: "1".to_string(),
+                allegato_id: "2".to_string(),
+                nome: Some("circolare.pdf".to_string()),
+                incorporato: None,
+            }],
+        };
+        let serializzata = serde_json::to_string(&comunicazione).unwrap();
+        assert_eq!(serde_json::from_str::<Comunicazione>(&serializzata).unwrap(), comunicazione);
+    }
+
+    #[test]
+    fn test_parse_bacheca_lenient_skips_malformed_circolare() {
+        let malformata = CIRCOLARE_JSON.replace(r#""codice":"12""#, r#""codice":"non un numero""#);
+        let json = format!(r#"{{"read":[{},{}],"msg_new":[{}]}}"#, CIRCOLARE_JSON, malformata, CIRCOLARE_JSON);
+
+        assert!(parse_bacheca(&json).is_err());
+
+        let (bacheca, errori) = parse_bacheca_lenient(&json).unwrap();
+        assert_eq!(bacheca.read.len(), 1);
+        assert_eq!(bacheca.msg_new.as_ref().map(Vec::len), Some(1));
+        assert_eq!(errori.len(), 1);
+        assert!(errori[0].starts_with("read[1]"));
+    }
+
+    #[test]
+    fn test_scadenze_imminenti() {
+        let now = NaiveDate::from_ymd_opt(2024, 9, 10).unwrap().and_hms_opt(9, 0, 0).unwrap();
+
+        let mut in_scadenza = circolare("1", 1, "Circolari");
+        in_scadenza.conf_lettura = "1".to_string();
+        in_scadenza.data_stop = "2024-09-12".to_string();
+
+        let mut lontana = circolare("2", 2, "Circolari");
+        lontana.conf_lettura = "1".to_string();
+        lontana.data_stop = "2024-12-20".to_string();
+
+        let mut confermata = circolare("3", 3, "Circolari");
+        confermata.flag_accettazione = Some("1".to_string());
+        confermata.testo_risp = Some("Accetto".to_string());
+        confermata.data_stop = "2024-09-11".to_string();
+
+        let mut senza_azione = circolare("4", 4, "Avvisi");
+        senza_azione.data_stop = "2024-09-11".to_string();
+
+        let bacheca = Bacheca {
+            read: vec![in_scadenza, lontana],
+            msg_new: Some(vec![confermata, senza_azione]),
+            extra: HashMap::new(),
+        };
+
+        let scadenze = bacheca.scadenze_imminenti(Duration::days(7), now);
+        assert_eq!(scadenze.len(), 1);
+        assert_eq!(scadenze[0].id, "1");
+    }
+
+    #[test]
+    fn test_content_hash_ignores_irrelevant_fields() {
+        let originale = circolare("1", 1, "Circolari");
+
+        let mut rimodificata = originale.clone();
+        rimodificata.modificato = "2024-09-13 10:00:00".to_string();
+        rimodificata.conf_lettura = "1".to_string();
+        assert_eq!(originale.content_hash(), rimodificata.content_hash());
+
+        let mut nuovo_testo = originale.clone();
+        nuovo_testo.testo = "Testo aggiornato".to_string();
+        assert_ne!(originale.content_hash(), nuovo_testo.content_hash());
+    }
+
+    #[test]
+    fn test_risposta_testo() {
+        let mut c = circolare("1", 1, "Circolari");
+        c.testo_risp = Some("Parteciperà".to_string());
+        c.file_risp = Some(String::new());
+
+        assert_eq!(
+            c.risposta(),
+            Some(RispostaData {
+                testo: Some("Parteciperà".to_string()),
+                file: None
+            })
+        );
+    }
+
+    #[test]
+    fn test_risposta_file() {
+        let mut c = circolare("1", 1, "Circolari");
+        c.file_risp = Some("autorizzazione_firmata.pdf".to_string());
+
+        assert_eq!(
+            c.risposta(),
+            Some(RispostaData {
+                testo: None,
+                file: Some("autorizzazione_firmata.pdf".to_string())
+            })
+        );
+    }
+
+    #[test]
+    fn test_risposta_assente() {
+        let mut c = circolare("1", 1, "Circolari");
+        assert_eq!(c.risposta(), None);
+
+        c.testo_risp = Some("  ".to_string());
+        assert_eq!(c.risposta(), None);
+    }
+
+    #[test]
+    fn test_richiedono_upload() {
+        let mut da_caricare = circolare("1", 1, "Circolari");
+        da_caricare.richieste = Some("conferma, FILE".to_string());
+        let mut caricata = circolare("2", 2, "Circolari");
+        caricata.richieste = Some("upload".to_string());
+        caricata.file_risp = Some("autorizzazione.pdf".to_string());
+        let mut senza_upload = circolare("3", 3, "Avvisi");
+        senza_upload.richieste = Some("conferma".to_string());
+
+        let bacheca = Bacheca {
+            read: vec![caricata, senza_upload],
+            msg_new: Some(vec![da_caricare]),
+            extra: HashMap::new(),
+        };
+
+        let ids: Vec<&str> = bacheca.richiedono_upload().iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["1"]);
+    }
+
+    #[test]
+    fn test_snapshot_independent_of_order() {
+        let prima = Bacheca {
+            read: vec![circolare("1", 1, "Circolari"), circolare("2", 2, "Avvisi")],
+            msg_new: Some(vec![circolare("3", 3, "Circolari"), circolare("4", 4, "Avvisi")]),
+            extra: HashMap::new(),
+        };
+        let seconda = Bacheca {
+            read: vec![circolare("2", 2, "Avvisi"), circolare("1", 1, "Circolari")],
+            msg_new: Some(vec![circolare("4", 4, "Avvisi"), circolare("3", 3, "Circolari")]),
+            extra: HashMap::new(),
+        };
+
+        assert_eq!(prima.snapshot(), seconda.snapshot());
+        assert_eq!(prima.snapshot(), "[read]\n1\t1\tCircolare 1\n2\t2\tCircolare 2\n[msg_new]\n3\t3\tCircolare 3\n4\t4\tCircolare 4\n");
+    }
+
+    #[test]
+    fn test_categorie_distinct_sorted() {
+        let bacheca = Bacheca {
+            read: vec![circolare("1", 1, "Circolari"), circolare("2", 2, "Avvisi")],
+            msg_new: Some(vec![circolare("3", 3, "Circolari"), circolare("4", 4, "")]),
+            extra: HashMap::new(),
+        };
+
+        assert_eq!(bacheca.categorie(), vec!["Avvisi".to_string(), "Circolari".to_string()]);
+    }
+}
@@ -0,0 +1,276 @@
+//! API bloccante, disponibile con la feature `blocking`
+//!
+//! Rispecchia le operazioni principali di [`crate::SpaggiariSession`] usando `reqwest::blocking`,
+//! per gli strumenti sincroni che non vogliono avviare un runtime tokio. Basta usare una
+//! delle due API: il parsing delle risposte del portale è condiviso.
+//!
+//! ```no_run
+//! use spaggiari_rs::blocking::SpaggiariSession;
+//!
+//! # fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+//! let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD")?;
+//! let bacheca = session.get_bacheca()?;
+//! println!("Comunicazioni non lette: {}", bacheca.unread_count());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::bacheca_personale::{
+    cookie_header, filename_da_headers, parse_bacheca, parse_comunicazione, sanitize_filename, Allegato, Bacheca, Comunicazione, BASE_URL, PATH_BACHECA, PATH_COMUNICAZIONI,
+};
+use crate::error::SpaggiariError;
+use crate::login::{interpreta_risposta_login, AccountInfo, LoginConfig, PATH_LOGOUT};
+use crate::DEFAULT_USER_AGENT;
+use reqwest::blocking::Client;
+use tracing::{debug, trace};
+
+/// Crea un client HTTP bloccante configurato per Spaggiari
+///
+/// # Returns
+///
+/// Un `Client` bloccante con cookies e user agent appropriati
+pub fn create_client() -> Result<Client, reqwest::Error> {
+    Client::builder().cookie_store(true).user_agent(DEFAULT_USER_AGENT).build()
+}
+
+/// Sessione Spaggiari che usa richieste bloccanti
+///
+/// Equivalente sincrono di [`crate::SpaggiariSession`].
+pub struct SpaggiariSession {
+    pub client: Client,
+    pub session_token: String,
+    identity: String,
+    base_url: String,
+    account_info: Option<AccountInfo>,
+}
+
+impl SpaggiariSession {
+    /// Crea una nuova sessione effettuando il login
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - Il codice fiscale dell'utente
+    /// * `password` - La password dell'utente
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` autenticata
+    pub fn new(username: &str, password: &str) -> Result<Self, SpaggiariError> {
+        Self::new_at(create_client()?, BASE_URL, username, password)
+    }
+
+    // Come `new`, usando il client e l'indirizzo base indicati
+    pub(crate) fn new_at(client: Client, base_url: &str, username: &str, password: &str) -> Result<Self, SpaggiariError> {
+        let login_config = LoginConfig::default();
+        login_config.check_username(username)?;
+        let login_url = login_config.url(base_url);
+        debug!(username, "login bloccante in corso");
+        let response = client.post(&login_url).form(&[("uid", username), ("pwd", password)]).send()?;
+        trace!(status = response.status().as_u16(), "risposta login ricevuta");
+
+        let headers = response.headers().clone();
+        let (session_token, account_info) = interpreta_risposta_login(&headers, &response.text()?, None)?;
+
+        Ok(SpaggiariSession {
+            client,
+            session_token,
+            identity: username.to_string(),
+            base_url: base_url.to_string(),
+            account_info,
+        })
+    }
+
+    /// Crea una sessione usando un token esistente e l'identità dell'utente a cui appartiene
+    ///
+    /// # Arguments
+    ///
+    /// * `session_token` - Il token di sessione salvato
+    /// * `identity` - Il codice fiscale dell'utente che ha effettuato il login
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` se il token è valido
+    pub fn from_token_with_identity(session_token: String, identity: String) -> Result<Self, SpaggiariError> {
+        let session = SpaggiariSession {
+            client: create_client()?,
+            session_token,
+            identity,
+            base_url: BASE_URL.to_string(),
+            account_info: None,
+        };
+        if !session.is_valid()? {
+            return Err(SpaggiariError::InvalidSessionToken);
+        }
+        Ok(session)
+    }
+
+    /// Le informazioni sull'account restituite dal login, se disponibili
+    pub fn account_info(&self) -> Option<&AccountInfo> {
+        self.account_info.as_ref()
+    }
+
+    /// Verifica se il token di sessione è ancora valido
+    pub fn is_valid(&self) -> Result<bool, SpaggiariError> {
+        match self.get_bacheca() {
+            Ok(_) => Ok(true),
+            Err(e) => {
+                debug!(errore = %e, "token scaduto o non valido");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Chiude la sessione sul portale, invalidando il token
+    pub fn logout(self) -> Result<(), SpaggiariError> {
+        let response = self
+            .client
+            .post(format!("{}{}", self.base_url, PATH_LOGOUT))
+            .header("Cookie", cookie_header(&self.session_token, &self.identity))
+            .send()?;
+
+        let status = response.status();
+        if status.is_success() || status.is_redirection() {
+            Ok(())
+        } else {
+            Err(SpaggiariError::ApiError {
+                message: format!("Logout non riuscito (status {})", status),
+            })
+        }
+    }
+
+    /// Ottiene la bacheca personale
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Bacheca` contenente tutte le comunicazioni
+    pub fn get_bacheca(&self) -> Result<Bacheca, SpaggiariError> {
+        let text = self.get_text(PATH_BACHECA, &[("action", "get_comunicazioni"), ("ncna", "1")])?;
+        parse_bacheca(&text)
+    }
+
+    /// Ottiene una comunicazione specifica
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare_id` - L'ID della circolare da ottenere
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Comunicazione` con tutti i dettagli
+    pub fn get_comunicazione(&self, circolare_id: &str) -> Result<Comunicazione, SpaggiariError> {
+        let html = self.get_text(PATH_COMUNICAZIONI, &[("action", "risposta_com"), ("com_id", circolare_id)])?;
+        parse_comunicazione(&html, circolare_id)
+    }
+
+    /// Scarica un file in memoria
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - L'URL del file da scaricare
+    ///
+    /// # Returns
+    ///
+    /// Il nome del file indicato dal server e il suo contenuto
+    pub fn download_file_bytes(&self, url: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
+        let response = self.client.get(url).header("Cookie", cookie_header(&self.session_token, &self.identity)).send()?;
+        if !response.status().is_success() {
+            debug!(url = %url, status = response.status().as_u16(), "download fallito");
+            return Err(SpaggiariError::ParseError {
+                details: format!("Download fallito: {}", response.status()),
+            });
+        }
+
+        let filename = filename_da_headers(response.headers());
+        let content = response.bytes()?.to_vec();
+        debug!(filename = %filename, bytes = content.len(), "file scaricato in memoria");
+        Ok((filename, content))
+    }
+
+    /// Scarica tutti gli allegati di una comunicazione, uno alla volta
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    ///
+    /// # Returns
+    ///
+    /// I percorsi dei file salvati
+    pub fn download_allegati(&self, allegati: &[Allegato], folder_path: &str) -> Result<Vec<String>, SpaggiariError> {
+        std::fs::create_dir_all(folder_path)?;
+        let mut paths = Vec::with_capacity(allegati.len());
+        for allegato in allegati {
+            let (filename, content) = match &allegato.incorporato {
+                Some(incorporato) => (sanitize_filename(&incorporato.nome), incorporato.contenuto.clone()),
+                None => self.download_file_bytes(&allegato.download_url(&self.base_url))?,
+            };
+            let filepath = format!("{}/{}", folder_path, filename);
+            std::fs::write(&filepath, content)?;
+            paths.push(filepath);
+        }
+        Ok(paths)
+    }
+
+    // Scarica il testo della pagina indicata con i cookie della sessione
+    fn get_text(&self, path: &str, query: &[(&str, &str)]) -> Result<String, SpaggiariError> {
+        let response = self
+            .client
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .header("Cookie", cookie_header(&self.session_token, &self.identity))
+            .send()?;
+
+        let status = response.status();
+        trace!(path, status = status.as_u16(), "risposta ricevuta");
+        if status.is_success() {
+            Ok(response.text()?)
+        } else {
+            debug!(status = status.as_u16(), "token non valido o scaduto");
+            Err(SpaggiariError::ParseError {
+                details: "Il token non sembra funzionare".to_string(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::LOGIN_OK;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_blocking_session_smoke() {
+        // Il mock server gira sul proprio thread: le richieste bloccanti restano fuori dal runtime
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime.block_on(async {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .and(path("/auth-p7/app/default/AuthApi4.php"))
+                .and(query_param("a", "aLoginPwd"))
+                .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=mock123; path=/").set_body_string(LOGIN_OK))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(PATH_BACHECA))
+                .and(header("Cookie", "PHPSESSID=mock123; webidentity=G1234567X"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":null}}"#, crate::test_support::circolare_json("301", 7))))
+                .mount(&server)
+                .await;
+            Mock::given(method("GET"))
+                .and(path(PATH_COMUNICAZIONI))
+                .and(query_param("com_id", "301"))
+                .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Testo</div>"#))
+                .mount(&server)
+                .await;
+            server
+        });
+
+        let session = SpaggiariSession::new_at(create_client().unwrap(), &server.uri(), "G1234567X", "password").unwrap();
+        assert_eq!(session.session_token, "mock123");
+        assert_eq!(session.account_info().map(|a| a.nome.as_str()), Some("Mario"));
+        assert_eq!(session.get_bacheca().unwrap().read[0].codice, 7);
+        assert_eq!(session.get_comunicazione("301").unwrap().testo, "Testo");
+    }
+}
@@ -0,0 +1,329 @@
+use crate::bacheca_personale::{download_file_bytes_via, sanitize_filename, Allegato, BASE_URL};
+use crate::error::SpaggiariError;
+use crate::http::Http;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Strategia usata quando un allegato ha lo stesso contenuto di un file già salvato
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupMode {
+    /// Crea un hardlink al file già salvato (nessuna copia fisica aggiuntiva)
+    #[default]
+    Hardlink,
+    /// Scrive un file `<nome>.ref` contenente il percorso del file già salvato
+    Reference,
+}
+
+/// Esito del salvataggio di un allegato tramite il `Deduplicator`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DedupOutcome {
+    /// Il contenuto non era mai stato visto: il file è stato scritto su disco
+    Written(PathBuf),
+    /// Il contenuto era già presente: è stato creato un collegamento al file originale
+    Linked { path: PathBuf, original: PathBuf },
+}
+
+impl DedupOutcome {
+    /// Il percorso del file (o del riferimento) creato
+    pub fn path(&self) -> &Path {
+        match self {
+            DedupOutcome::Written(path) => path,
+            DedupOutcome::Linked { path, .. } => path,
+        }
+    }
+}
+
+/// Tiene traccia degli allegati già salvati per evitare copie duplicate.
+///
+/// Gli allegati vengono riconosciuti sia per `allegato_id` sia per hash SHA-256 del contenuto,
+/// quindi lo stesso PDF pubblicato in più comunicazioni viene scritto una sola volta.
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    mode: DedupMode,
+    by_hash: HashMap<String, PathBuf>,
+    by_allegato_id: HashMap<String, PathBuf>,
+}
+
+impl Deduplicator {
+    /// Crea un nuovo `Deduplicator` con la strategia indicata
+    pub fn new(mode: DedupMode) -> Self {
+        Deduplicator { mode, ..Default::default() }
+    }
+
+    /// Restituisce il percorso già salvato per un `allegato_id`, se presente
+    pub fn known_allegato(&self, allegato_id: &str) -> Option<&Path> {
+        self.by_allegato_id.get(allegato_id).map(PathBuf::as_path)
+    }
+
+    /// Salva il contenuto nella cartella di destinazione, oppure crea un collegamento
+    /// se un file con lo stesso contenuto è già stato salvato.
+    ///
+    /// Un hardlink non sostituisce mai un file esistente: se il nome è occupato
+    /// riceve un suffisso numerico (es. `circolare_2.pdf`).
+    ///
+    /// # Argomenti
+    ///
+    /// * `destination_path` - La cartella di destinazione.
+    /// * `filename` - Il nome del file da creare.
+    /// * `content` - Il contenuto dell'allegato.
+    ///
+    /// # Restituisce
+    ///
+    /// * `Ok(DedupOutcome)` che indica se il file è stato scritto o collegato.
+    pub fn save(&mut self, destination_path: &Path, filename: &str, content: &[u8]) -> Result<DedupOutcome, SpaggiariError> {
+        std::fs::create_dir_all(destination_path)?;
+        let hash = content_hash(content);
+        let filepath = destination_path.join(filename);
+
+        if let Some(original) = self.by_hash.get(&hash) {
+            if *original == filepath {
+                return Ok(DedupOutcome::Written(filepath));
+            }
+
+            let path = match self.mode {
+                DedupMode::Hardlink => {
+                    // Un file diverso con lo stesso nome non viene sovrascritto: il collegamento prende un nome libero
+                    let path = percorso_libero(destination_path, filename);
+                    std::fs::hard_link(original, &path)?;
+                    path
+                }
+                DedupMode::Reference => {
+                    let ref_path = destination_path.join(format!("{}.ref", filename));
+                    std::fs::write(&ref_path, original.to_string_lossy().as_bytes())?;
+                    ref_path
+                }
+            };
+            debug!(path = %path.display(), original = %original.display(), "contenuto duplicato, collegato all'originale");
+            return Ok(DedupOutcome::Linked { path, original: original.clone() });
+        }
+
+        // Il file viene sovrascritto: gli hash che puntavano al vecchio contenuto non sono più validi
+        self.by_hash.retain(|_, path| *path != filepath);
+        self.by_allegato_id.retain(|_, path| *path != filepath);
+        std::fs::write(&filepath, content)?;
+        debug!(path = %filepath.display(), bytes = content.len(), "file salvato");
+        self.by_hash.insert(hash, filepath.clone());
+        Ok(DedupOutcome::Written(filepath))
+    }
+}
+
+// Percorso di `filename` nella cartella, con un suffisso numerico se esiste già un file con quel nome
+fn percorso_libero(destination_path: &Path, filename: &str) -> PathBuf {
+    let (base, estensione) = match filename.rsplit_once('.') {
+        Some((base, estensione)) if !base.is_empty() => (base, format!(".{}", estensione)),
+        _ => (filename, String::new()),
+    };
+    let mut path = destination_path.join(filename);
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = destination_path.join(format!("{}_{}{}", base, n, estensione));
+    }
+    path
+}
+
+// Calcola l'hash SHA-256 del contenuto in formato esadecimale
+pub(crate) fn content_hash(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+/// Scarica gli allegati nella cartella di destinazione evitando copie duplicate.
+///
+/// Un allegato già scaricato (stesso `allegato_id`) non viene scaricato di nuovo,
+/// mentre un allegato con contenuto identico a un file già salvato viene collegato
+/// secondo la strategia del `Deduplicator`.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP.
+/// * `session_id` - L'ID di sessione.
+/// * `webidentity` - L'identità web.
+/// * `allegati` - Una slice di `Allegato` da scaricare.
+/// * `destination_path` - Il percorso della cartella dove salvare i file.
+/// * `dedup` - Il `Deduplicator` condiviso tra le comunicazioni.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<DedupOutcome>)` con l'esito di ciascun allegato.
+pub async fn download_allegati_dedup(
+    client: &Client,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    dedup: &mut Deduplicator,
+) -> Result<Vec<DedupOutcome>, SpaggiariError> {
+    download_allegati_dedup_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity, allegati, destination_path, dedup).await
+}
+
+// Come `download_allegati_dedup`, usando l'indirizzo base indicato
+pub(crate) async fn download_allegati_dedup_at(
+    http: &Http,
+    base_url: &str,
+    session_id: &str,
+    webidentity: &str,
+    allegati: &[Allegato],
+    destination_path: &str,
+    dedup: &mut Deduplicator,
+) -> Result<Vec<DedupOutcome>, SpaggiariError> {
+    let destination = Path::new(destination_path);
+    let mut outcomes = Vec::new();
+
+    for allegato in allegati {
+        if let Some(incorporato) = &allegato.incorporato {
+            outcomes.push(dedup.save(destination, &sanitize_filename(&incorporato.nome), &incorporato.contenuto)?);
+            continue;
+        }
+
+        if let Some(original) = dedup.known_allegato(&allegato.allegato_id) {
+            let original = original.to_path_buf();
+            let filename = original.file_name().map(|f| f.to_string_lossy().to_string()).unwrap_or_else(|| allegato.allegato_id.clone());
+            let content = std::fs::read(&original)?;
+            outcomes.push(dedup.save(destination, &filename, &content)?);
+            continue;
+        }
+
+        let (filename, content) = download_file_bytes_via(http, &allegato.download_url(base_url), session_id, webidentity).await?;
+        let outcome = dedup.save(destination, &filename, &content)?;
+        let original = match &outcome {
+            DedupOutcome::Written(path) => path.clone(),
+            DedupOutcome::Linked { original, .. } => original.clone(),
+        };
+        dedup.by_allegato_id.insert(allegato.allegato_id.clone(), original);
+        outcomes.push(outcome);
+    }
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_content_written_once_with_reference() {
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = b"%PDF-1.4 stesso contenuto";
+        let mut dedup = Deduplicator::new(DedupMode::Reference);
+
+        let first = dedup.save(&dir.path().join("100"), "circolare.pdf", pdf).unwrap();
+        let second = dedup.save(&dir.path().join("200"), "allegato.pdf", pdf).unwrap();
+
+        assert_eq!(first, DedupOutcome::Written(dir.path().join("100/circolare.pdf")));
+        assert!(matches!(second, DedupOutcome::Linked { .. }));
+        assert!(!dir.path().join("200/allegato.pdf").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("200/allegato.pdf.ref")).unwrap(),
+            dir.path().join("100/circolare.pdf").to_string_lossy()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_identical_content_hardlinked() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = b"%PDF-1.4 stesso contenuto";
+        let mut dedup = Deduplicator::new(DedupMode::Hardlink);
+
+        dedup.save(&dir.path().join("100"), "circolare.pdf", pdf).unwrap();
+        dedup.save(&dir.path().join("200"), "circolare.pdf", pdf).unwrap();
+
+        let first = std::fs::metadata(dir.path().join("100/circolare.pdf")).unwrap();
+        let second = std::fs::metadata(dir.path().join("200/circolare.pdf")).unwrap();
+        assert_eq!(first.ino(), second.ino());
+        assert_eq!(first.nlink(), 2);
+    }
+
+    #[test]
+    fn test_different_content_written_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dedup = Deduplicator::default();
+
+        let first = dedup.save(dir.path(), "a.pdf", b"uno").unwrap();
+        let second = dedup.save(dir.path(), "b.pdf", b"due").unwrap();
+
+        assert!(matches!(first, DedupOutcome::Written(_)));
+        assert!(matches!(second, DedupOutcome::Written(_)));
+    }
+
+    #[test]
+    fn test_overwritten_file_no_longer_matches_old_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut dedup = Deduplicator::new(DedupMode::Reference);
+
+        dedup.save(dir.path(), "a.pdf", b"uno").unwrap();
+        dedup.save(dir.path(), "a.pdf", b"due").unwrap();
+        let terzo = dedup.save(dir.path(), "c.pdf", b"uno").unwrap();
+
+        assert_eq!(terzo, DedupOutcome::Written(dir.path().join("c.pdf")));
+        assert_eq!(std::fs::read(dir.path().join("c.pdf")).unwrap(), b"uno");
+    }
+
+    #[test]
+    fn test_hardlink_keeps_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("b.pdf"), b"altro").unwrap();
+        let mut dedup = Deduplicator::new(DedupMode::Hardlink);
+
+        dedup.save(dir.path(), "a.pdf", b"uno").unwrap();
+        let second = dedup.save(dir.path(), "b.pdf", b"uno").unwrap();
+
+        assert_eq!(
+            second,
+            DedupOutcome::Linked {
+                path: dir.path().join("b_2.pdf"),
+                original: dir.path().join("a.pdf"),
+            }
+        );
+        assert_eq!(std::fs::read(dir.path().join("b.pdf")).unwrap(), b"altro");
+        assert_eq!(std::fs::read(dir.path().join("b_2.pdf")).unwrap(), b"uno");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_download_allegati_dedup_same_bytes_in_two_communications() {
+        use std::os::unix::fs::MetadataExt;
+        use wiremock::matchers::{method, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        for allegato_id in ["11", "22"] {
+            Mock::given(method("GET"))
+                .and(query_param("com_id", allegato_id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", "attachment; filename=\"circolare.pdf\"")
+                        .set_body_bytes(b"%PDF-1.4 stesso contenuto".to_vec()),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        let allegato = |comunicazione_id: &str, allegato_id: &str| Allegato {
+            comunicazione_id: comunicazione_id.to_string(),
+            allegato_id: allegato_id.to_string(),
+            nome: None,
+            incorporato: None,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let http = Http::new(Client::new());
+        let mut dedup = Deduplicator::new(DedupMode::Hardlink);
+
+        for (comunicazione_id, allegato_id) in [("100", "11"), ("200", "22")] {
+            let cartella = dir.path().join(comunicazione_id).to_string_lossy().to_string();
+            download_allegati_dedup_at(&http, &server.uri(), "sess", "G1234567X", &[allegato(comunicazione_id, allegato_id)], &cartella, &mut dedup)
+                .await
+                .unwrap();
+        }
+
+        let primo = std::fs::metadata(dir.path().join("100/circolare.pdf")).unwrap();
+        let secondo = std::fs::metadata(dir.path().join("200/circolare.pdf")).unwrap();
+        assert_eq!(primo.ino(), secondo.ino());
+        assert_eq!(primo.nlink(), 2);
+    }
+}
@@ -7,6 +7,22 @@ pub enum SpaggiariError {
     #[error("Autenticazione fallita: credenziali non valide")]
     AuthenticationFailed,
 
+    /// Password errata (`errCod` del login)
+    #[error("Autenticazione fallita: password errata")]
+    WrongPassword,
+
+    /// Account bloccato, ad esempio dopo troppi tentativi falliti
+    #[error("Autenticazione fallita: account bloccato")]
+    AccountLocked,
+
+    /// Password scaduta: va cambiata dal portale prima di poter accedere
+    #[error("Autenticazione fallita: password scaduta")]
+    PasswordExpired,
+
+    /// Login rifiutato con codici di errore non riconosciuti dalla libreria
+    #[error("Login rifiutato (codici: {}): {}", codes.join(", "), errors.join("; "))]
+    LoginRejected { codes: Vec<String>, errors: Vec<String> },
+
     /// Token di sessione non valido o scaduto
     #[error("Token di sessione non valido o scaduto")]
     InvalidSessionToken,
@@ -15,10 +31,34 @@ pub enum SpaggiariError {
     #[error("Errore richiesta HTTP: {0}")]
     HttpError(#[from] reqwest::Error),
 
+    /// Header HTTP con caratteri non validi (es. `Set-Cookie` non UTF-8)
+    #[error("Header HTTP non leggibile: {0}")]
+    HeaderToStr(#[from] reqwest::header::ToStrError),
+
+    /// Valore non utilizzabile come header HTTP
+    #[error("Valore di header HTTP non valido: {0}")]
+    InvalidHeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+
     /// Errore nella deserializzazione JSON
     #[error("Errore deserializzazione JSON: {0}")]
     JsonError(#[from] serde_json::Error),
 
+    /// La sessione è stata invalidata da un accesso su un altro dispositivo
+    #[error("Sessione chiusa: è stato effettuato un accesso da un altro dispositivo")]
+    SessionSuperseded,
+
+    /// Il nuovo login appartiene a un account diverso da quello della sessione
+    #[error("Il login appartiene all'account '{got}' invece che a '{expected}'")]
+    IdentityMismatch { expected: String, got: String },
+
+    /// Operazione non disponibile per il tipo di account della sessione (es. i voti per un docente)
+    #[error("Operazione non consentita per questo account: {0}")]
+    Forbidden(String),
+
+    /// Il portale richiede un passaggio di verifica aggiuntivo prima di completare il login
+    #[error("Il login richiede un'azione aggiuntiva: {}", hints.join("; "))]
+    ActionRequired { hints: Vec<String> },
+
     /// Errore API - risposta inaspettata dal server
     #[error("Errore API: {message}")]
     ApiError { message: String },
@@ -35,6 +75,14 @@ pub enum SpaggiariError {
     #[error("Comunicazione con ID '{0}' non trovata")]
     ComunicazioneNotFound(String),
 
+    /// La comunicazione richiede un consenso prima di mostrare il testo
+    #[error("La comunicazione '{0}' richiede un consenso prima di essere visualizzata")]
+    ConsensoRichiesto(String),
+
+    /// La circolare non prevede l'azione richiesta (es. risposta non abilitata da `flag_risp`)
+    #[error("La comunicazione '{circolare_id}' non prevede l'azione '{azione}'")]
+    AzioneNonPrevista { circolare_id: String, azione: String },
+
     /// Allegato non trovato
     #[error("Allegato '{0}' non trovato")]
     AllegatoNotFound(String),
@@ -85,6 +133,16 @@ mod tests {
         assert_eq!(err.to_string(), "Comunicazione con ID '123' non trovata");
     }
 
+    #[test]
+    fn test_header_error_display() {
+        let err: SpaggiariError = reqwest::header::HeaderValue::from_bytes(b"PHPSESSID=\xff").unwrap().to_str().unwrap_err().into();
+        assert!(matches!(err, SpaggiariError::HeaderToStr(_)));
+        assert_eq!(err.to_string(), "Header HTTP non leggibile: failed to convert header to a str");
+
+        let err: SpaggiariError = reqwest::header::HeaderValue::from_str("a\nb").unwrap_err().into();
+        assert_eq!(err.to_string(), "Valore di header HTTP non valido: failed to parse header value");
+    }
+
     #[test]
     fn test_error_from_string() {
         let err: SpaggiariError = "test error".into();
@@ -0,0 +1,258 @@
+use std::fmt;
+
+/// Tipo di file riconosciuto dai primi byte del contenuto (magic bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Pdf,
+    Png,
+    Jpeg,
+    Gif,
+    Mp4,
+    /// Archivi ZIP e formati basati su ZIP (docx, xlsx, odt, ...)
+    Zip,
+    /// Testo semplice (UTF-8 senza caratteri di controllo)
+    Text,
+    Unknown,
+}
+
+/// Riconosce il tipo di file a partire dai primi byte del contenuto.
+///
+/// Equivale a `FileType::from_bytes`.
+///
+/// # Example
+///
+/// ```
+/// use spaggiari_rs::{detect_file_type, FileType};
+///
+/// let tipo = detect_file_type(b"%PDF-1.7");
+/// assert_eq!(tipo, FileType::Pdf);
+/// assert_eq!(tipo.extension(), "pdf");
+/// ```
+pub fn detect_file_type(content: &[u8]) -> FileType {
+    FileType::from_bytes(content)
+}
+
+impl FileType {
+    /// Riconosce il tipo di file a partire dal contenuto.
+    ///
+    /// # Argomenti
+    ///
+    /// * `content` - I byte del file (bastano i primi 12).
+    ///
+    /// # Restituisce
+    ///
+    /// * Il `FileType` riconosciuto, oppure `FileType::Unknown`.
+    pub fn from_bytes(content: &[u8]) -> FileType {
+        if content.starts_with(b"%PDF") {
+            FileType::Pdf
+        } else if content.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+            FileType::Png
+        } else if content.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            FileType::Jpeg
+        } else if content.starts_with(b"GIF87a") || content.starts_with(b"GIF89a") {
+            FileType::Gif
+        } else if content.len() >= 8 && &content[4..8] == b"ftyp" {
+            FileType::Mp4
+        } else if content.starts_with(b"PK\x03\x04") || content.starts_with(b"PK\x05\x06") || content.starts_with(b"PK\x07\x08") {
+            FileType::Zip
+        } else if is_text(content) {
+            FileType::Text
+        } else {
+            FileType::Unknown
+        }
+    }
+
+    /// Riconosce il tipo di file a partire dal MIME type (ad esempio l'header `Content-Type`).
+    ///
+    /// Eventuali parametri (`; charset=...`) vengono ignorati.
+    pub fn from_mime(mime: &str) -> FileType {
+        let mime = mime.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "application/pdf" => FileType::Pdf,
+            "image/png" => FileType::Png,
+            "image/jpeg" | "image/jpg" => FileType::Jpeg,
+            "image/gif" => FileType::Gif,
+            "video/mp4" => FileType::Mp4,
+            "application/zip" | "application/x-zip-compressed" => FileType::Zip,
+            "text/plain" => FileType::Text,
+            m if m.starts_with("application/vnd.openxmlformats-officedocument.") || m.starts_with("application/vnd.oasis.opendocument.") => FileType::Zip,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// Riconosce il tipo di file a partire dall'estensione del nome del file.
+    pub fn from_filename(filename: &str) -> FileType {
+        let extension = match filename.rsplit_once('.') {
+            Some((_, extension)) => extension.to_ascii_lowercase(),
+            None => return FileType::Unknown,
+        };
+        match extension.as_str() {
+            "pdf" => FileType::Pdf,
+            "png" => FileType::Png,
+            "jpg" | "jpeg" => FileType::Jpeg,
+            "gif" => FileType::Gif,
+            "mp4" | "m4v" => FileType::Mp4,
+            "zip" | "docx" | "xlsx" | "pptx" | "odt" | "ods" | "odp" => FileType::Zip,
+            "txt" => FileType::Text,
+            _ => FileType::Unknown,
+        }
+    }
+
+    /// Sottocartella in cui `download_allegati_sorted` salva i file di questo tipo
+    /// (`pdf`, `images`, `docs` oppure `other`)
+    pub fn sottocartella(&self) -> &'static str {
+        match self {
+            FileType::Pdf => "pdf",
+            FileType::Png | FileType::Jpeg | FileType::Gif => "images",
+            FileType::Zip | FileType::Text => "docs",
+            FileType::Mp4 | FileType::Unknown => "other",
+        }
+    }
+
+    /// Il MIME type associato al tipo di file
+    pub fn mime(&self) -> &'static str {
+        match self {
+            FileType::Pdf => "application/pdf",
+            FileType::Png => "image/png",
+            FileType::Jpeg => "image/jpeg",
+            FileType::Gif => "image/gif",
+            FileType::Mp4 => "video/mp4",
+            FileType::Zip => "application/zip",
+            FileType::Text => "text/plain",
+            FileType::Unknown => "application/octet-stream",
+        }
+    }
+
+    /// L'estensione (senza punto) usata per i file di questo tipo
+    pub fn extension(&self) -> &'static str {
+        match self {
+            FileType::Pdf => "pdf",
+            FileType::Png => "png",
+            FileType::Jpeg => "jpg",
+            FileType::Gif => "gif",
+            FileType::Mp4 => "mp4",
+            FileType::Zip => "zip",
+            FileType::Text => "txt",
+            FileType::Unknown => "bin",
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nome = match self {
+            FileType::Pdf => "PDF",
+            FileType::Png => "PNG",
+            FileType::Jpeg => "JPEG",
+            FileType::Gif => "GIF",
+            FileType::Mp4 => "MP4",
+            FileType::Zip => "ZIP",
+            FileType::Text => "Testo",
+            FileType::Unknown => "Sconosciuto",
+        };
+        f.write_str(nome)
+    }
+}
+
+// Il contenuto è testo se è UTF-8 valido (tollerando un carattere troncato alla fine)
+// e non contiene caratteri di controllo diversi da tabulazioni e a capo
+fn is_text(content: &[u8]) -> bool {
+    let testo = match std::str::from_utf8(content) {
+        Ok(testo) => testo,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&content[..e.valid_up_to()]).unwrap_or_default(),
+        Err(_) => return false,
+    };
+    !testo.is_empty() && !testo.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pdf() {
+        assert_eq!(FileType::from_bytes(b"%PDF-1.7\n..."), FileType::Pdf);
+        assert_eq!(FileType::Pdf.mime(), "application/pdf");
+    }
+
+    #[test]
+    fn test_png() {
+        assert_eq!(FileType::from_bytes(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00]), FileType::Png);
+        assert_eq!(FileType::Png.mime(), "image/png");
+    }
+
+    #[test]
+    fn test_jpeg() {
+        assert_eq!(FileType::from_bytes(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10]), FileType::Jpeg);
+        assert_eq!(FileType::Jpeg.mime(), "image/jpeg");
+    }
+
+    #[test]
+    fn test_gif() {
+        assert_eq!(FileType::from_bytes(b"GIF87a...."), FileType::Gif);
+        assert_eq!(FileType::from_bytes(b"GIF89a...."), FileType::Gif);
+        assert_eq!(FileType::Gif.mime(), "image/gif");
+    }
+
+    #[test]
+    fn test_mp4() {
+        assert_eq!(FileType::from_bytes(b"\x00\x00\x00\x18ftypmp42"), FileType::Mp4);
+        assert_eq!(FileType::Mp4.mime(), "video/mp4");
+    }
+
+    #[test]
+    fn test_zip() {
+        assert_eq!(FileType::from_bytes(b"PK\x03\x04\x14\x00"), FileType::Zip);
+        assert_eq!(FileType::from_bytes(b"PK\x05\x06\x00\x00"), FileType::Zip);
+        assert_eq!(FileType::Zip.mime(), "application/zip");
+    }
+
+    #[test]
+    fn test_from_mime() {
+        assert_eq!(FileType::from_mime("application/pdf"), FileType::Pdf);
+        assert_eq!(FileType::from_mime("Image/PNG; charset=binary"), FileType::Png);
+        assert_eq!(FileType::from_mime("application/vnd.openxmlformats-officedocument.wordprocessingml.document"), FileType::Zip);
+        assert_eq!(FileType::from_mime("application/octet-stream"), FileType::Unknown);
+    }
+
+    #[test]
+    fn test_from_filename() {
+        assert_eq!(FileType::from_filename("circolare.PDF"), FileType::Pdf);
+        assert_eq!(FileType::from_filename("foto.jpeg"), FileType::Jpeg);
+        assert_eq!(FileType::from_filename("modulo.docx"), FileType::Zip);
+        assert_eq!(FileType::from_filename("senza_estensione"), FileType::Unknown);
+    }
+
+    #[test]
+    fn test_sottocartella() {
+        assert_eq!(FileType::Pdf.sottocartella(), "pdf");
+        assert_eq!(FileType::Jpeg.sottocartella(), "images");
+        assert_eq!(FileType::Zip.sottocartella(), "docs");
+        assert_eq!(FileType::Unknown.sottocartella(), "other");
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(FileType::from_bytes(b"Gentili famiglie,\r\n\tsi comunica"), FileType::Text);
+        // Carattere multibyte troncato al limite dei byte letti
+        assert_eq!(FileType::from_bytes(&"perché".as_bytes()[..6]), FileType::Text);
+        assert_eq!(FileType::from_filename("note.txt"), FileType::Text);
+        assert_eq!(FileType::Text.mime(), "text/plain");
+    }
+
+    #[test]
+    fn test_extension_and_display() {
+        assert_eq!(detect_file_type(b"GIF89a....").extension(), "gif");
+        assert_eq!(FileType::Jpeg.extension(), "jpg");
+        assert_eq!(FileType::Unknown.extension(), "bin");
+        assert_eq!(FileType::Pdf.to_string(), "PDF");
+        assert_eq!(FileType::Text.to_string(), "Testo");
+    }
+
+    #[test]
+    fn test_unknown() {
+        assert_eq!(FileType::from_bytes(&[0x00, 0x01, 0xFE, 0x42]), FileType::Unknown);
+        assert_eq!(FileType::from_bytes(&[]), FileType::Unknown);
+        assert_eq!(FileType::Unknown.mime(), "application/octet-stream");
+    }
+}
@@ -0,0 +1,217 @@
+//! Percorso comune per l'invio delle richieste al portale.
+//!
+//! Bacheca, comunicazioni, download, richieste HEAD e login passano tutti da [`Http::send`],
+//! che applica la [`RetryPolicy`] e il limite di richieste contemporanee della sessione.
+
+use futures::{Stream, StreamExt};
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderMap, HeaderValue, COOKIE};
+use reqwest::{Client, RequestBuilder, Response};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::activity::ActivityLog;
+use crate::error::SpaggiariError;
+use crate::retry::{send_with_retry, RetryPolicy};
+
+// Client HTTP insieme al limite di richieste contemporanee condiviso dalla sessione
+#[derive(Debug)]
+pub(crate) struct Http {
+    client: Client,
+    limite: Option<Arc<Semaphore>>,
+    // Cookie jar del client, i cui cookie vengono aggiunti all'header `Cookie` esplicito
+    jar: Option<Arc<Jar>>,
+    // Status dell'ultima risposta ricevuta, 0 finché non ne arriva una
+    status: AtomicU16,
+    // Log delle attività della sessione, per le operazioni che registrano ogni file scaricato
+    attivita: Option<Arc<ActivityLog>>,
+}
+
+impl Http {
+    // Senza limite: usato dalle funzioni pubbliche che ricevono solo un `Client`
+    pub(crate) fn new(client: Client) -> Self {
+        Http::con_limite(client, None)
+    }
+
+    // Con il limite indicato, condiviso da tutte le copie che ricevono lo stesso semaforo
+    pub(crate) fn con_limite(client: Client, limite: Option<Arc<Semaphore>>) -> Self {
+        Http {
+            client,
+            limite,
+            jar: None,
+            status: AtomicU16::new(0),
+            attivita: None,
+        }
+    }
+
+    // Con il cookie jar del client, così i suoi cookie (consenso, scuola, ...) accompagnano
+    // anche le richieste che impostano `PHPSESSID` e `webidentity` a mano
+    pub(crate) fn con_jar(mut self, jar: Option<Arc<Jar>>) -> Self {
+        self.jar = jar;
+        self
+    }
+
+    // Con il log delle attività della sessione: i download di più allegati vi registrano ogni file
+    pub(crate) fn con_attivita(mut self, attivita: Arc<ActivityLog>) -> Self {
+        self.attivita = Some(attivita);
+        self
+    }
+
+    // Registra l'esito del download di `url` nel log delle attività, se presente
+    pub(crate) fn registra<T>(&self, url: &str, result: &Result<T, SpaggiariError>, bytes: impl FnOnce(&T) -> usize) {
+        if let Some(attivita) = &self.attivita {
+            attivita.record(url, self.status(), result, bytes);
+        }
+    }
+
+    // Status HTTP dell'ultima risposta ricevuta, `None` se nessuna richiesta ha avuto risposta
+    pub(crate) fn status(&self) -> Option<u16> {
+        Some(self.status.load(Ordering::Relaxed)).filter(|&status| status != 0)
+    }
+
+    // Invia la richiesta costruita da `build` con la `RetryPolicy` predefinita
+    pub(crate) async fn send<F>(&self, build: F) -> Result<Risposta, SpaggiariError>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        self.send_with(&RetryPolicy::default(), build).await
+    }
+
+    // Invia la richiesta una sola volta: per le azioni che modificano lo stato sul portale,
+    // dove una ripetizione potrebbe registrarle due volte
+    pub(crate) async fn send_once<F>(&self, build: F) -> Result<Risposta, SpaggiariError>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        self.send_with(&RetryPolicy::disabled(), build).await
+    }
+
+    // Invia la richiesta con la `RetryPolicy` indicata, dopo aver ottenuto un posto nel limite.
+    // Il posto resta occupato finché la `Risposta` (o il suo body) non viene rilasciata
+    pub(crate) async fn send_with<F>(&self, policy: &RetryPolicy, build: F) -> Result<Risposta, SpaggiariError>
+    where
+        F: Fn(&Client) -> RequestBuilder,
+    {
+        let permesso = self.permesso().await?;
+        let response = send_with_retry(policy, || match &self.jar {
+            Some(jar) => con_cookie_del_jar(build(&self.client), jar),
+            None => build(&self.client),
+        })
+        .await?;
+        self.status.store(response.status().as_u16(), Ordering::Relaxed);
+        Ok(Risposta { response, permesso })
+    }
+
+    async fn permesso(&self) -> Result<Option<OwnedSemaphorePermit>, SpaggiariError> {
+        match &self.limite {
+            Some(limite) => limite
+                .clone()
+                .acquire_owned()
+                .await
+                .map(Some)
+                .map_err(|e| SpaggiariError::Generic(format!("Limite delle richieste non disponibile: {}", e))),
+            None => Ok(None),
+        }
+    }
+}
+
+// reqwest non aggiunge i cookie del jar alle richieste che hanno già un header `Cookie`:
+// li accoda a quello esplicito, che ha la precedenza per i cookie con lo stesso nome
+fn con_cookie_del_jar(builder: RequestBuilder, jar: &Jar) -> RequestBuilder {
+    let Some(Ok(request)) = builder.try_clone().map(RequestBuilder::build) else {
+        return builder;
+    };
+    let (Some(esplicito), Some(dal_jar)) = (request.headers().get(COOKIE).and_then(|v| v.to_str().ok()), jar.cookies(request.url())) else {
+        return builder;
+    };
+
+    let nome = |cookie: &str| cookie.split_once('=').map(|(nome, _)| nome.trim().to_string());
+    let presenti: Vec<String> = esplicito.split(';').filter_map(nome).collect();
+    let aggiuntivi: Vec<&str> = dal_jar
+        .to_str()
+        .unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|cookie| nome(cookie).is_some_and(|n| !presenti.contains(&n)))
+        .collect();
+    if aggiuntivi.is_empty() {
+        return builder;
+    }
+    match HeaderValue::from_str(&format!("{}; {}", esplicito, aggiuntivi.join("; "))) {
+        Ok(valore) => builder.headers(HeaderMap::from_iter([(COOKIE, valore)])),
+        Err(_) => builder,
+    }
+}
+
+// Risposta del portale che tiene occupato il posto nel limite finché il body non è stato letto
+#[derive(Debug)]
+pub(crate) struct Risposta {
+    response: Response,
+    permesso: Option<OwnedSemaphorePermit>,
+}
+
+impl Risposta {
+    pub(crate) async fn text(self) -> reqwest::Result<String> {
+        self.response.text().await
+    }
+
+    // Body a blocchi; il posto nel limite viene rilasciato insieme allo stream
+    pub(crate) fn bytes_stream(self) -> impl Stream<Item = reqwest::Result<impl Deref<Target = [u8]>>> {
+        let permesso = self.permesso;
+        self.response.bytes_stream().map(move |chunk| {
+            let _ = &permesso;
+            chunk
+        })
+    }
+}
+
+impl Deref for Risposta {
+    type Target = Response;
+
+    fn deref(&self) -> &Response {
+        &self.response
+    }
+}
+
+impl DerefMut for Risposta {
+    fn deref_mut(&mut self) -> &mut Response {
+        &mut self.response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{server_contatore, BACHECA_VUOTA};
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_limit_caps_requests_in_flight() {
+        let (indirizzo, massimo) = server_contatore(Duration::from_millis(100)).await;
+        let http = Http::con_limite(Client::new(), Some(Arc::new(Semaphore::new(2))));
+
+        let richieste = (0..5).map(|_| async {
+            let risposta = http.send(|c| c.get(&indirizzo)).await.unwrap();
+            risposta.text().await.unwrap()
+        });
+        let corpi = futures::future::join_all(richieste).await;
+
+        assert_eq!(corpi, vec![BACHECA_VUOTA; 5]);
+        assert!((1..=2).contains(&massimo.load(Ordering::SeqCst)), "{:?}", massimo);
+    }
+
+    #[tokio::test]
+    async fn test_permit_released_with_body_stream() {
+        let (indirizzo, _) = server_contatore(Duration::ZERO).await;
+        let limite = Arc::new(Semaphore::new(1));
+        let http = Http::con_limite(Client::new(), Some(limite.clone()));
+
+        let body = http.send(|c| c.get(&indirizzo)).await.unwrap().bytes_stream();
+        assert_eq!(limite.available_permits(), 0);
+        drop(body);
+        assert_eq!(limite.available_permits(), 1);
+    }
+}
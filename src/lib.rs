@@ -6,20 +6,74 @@
 //! - Effettuare il login al portale Spaggiari
 //! - Ottenere la bacheca personale
 //! - Scaricare comunicazioni e allegati
+//! - Leggere i voti dello studente
+//! - Consultare assenze, ritardi e uscite anticipate
+//! - Leggere compiti e verifiche dall'agenda
 //! - Gestire i token di sessione
 
+pub mod activity;
+pub mod agenda;
+pub mod assenze;
 pub mod bacheca_personale;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod dedup;
 pub mod error;
+pub mod file_type;
+mod http;
 pub mod login;
+pub mod manifest;
+pub mod retry;
+pub mod token_store;
+pub mod utils;
+pub mod voti;
 
+#[cfg(test)]
+mod test_support;
+
+use activity::ActivityLog;
+use agenda::{fetch_agenda_at, parse_agenda, PATH_AGENDA};
+use assenze::{fetch_assenze_at, parse_assenze, PATH_ASSENZE};
+#[cfg(feature = "zip")]
+use bacheca_personale::download_allegati_extract_zip_at;
+use bacheca_personale::{
+    accetta_comunicazione_at, conferma_lettura_at, download_allegati_by_type_at, download_allegati_flat_at, download_allegati_sorted_at, fetch_bacheca_at, fetch_bacheca_range_at,
+    fetch_comunicazione_at, invia_consenso_at, parse_bacheca, parse_bacheca_lenient, parse_comunicazione, rispondi_comunicazione_at, BASE_URL, PATH_BACHECA, PATH_COMUNICAZIONI,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
+use http::Http;
 use reqwest::cookie::Jar;
 use reqwest::Client;
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use voti::{fetch_voti_at, parse_voti, PATH_VOTI};
 
 // Re-export delle strutture principali
-pub use bacheca_personale::{download_allegati, download_allegati_bytes, download_file, download_file_bytes, get_backeca, get_comunicazioni, Allegato, Bacheca, Circolare, Comunicazione};
+pub use activity::ActivityEntry;
+pub use agenda::{get_agenda, AgendaEvent, TipoEvento};
+pub use assenze::{get_assenze, Assenza, TipoAssenza};
+pub use bacheca_personale::{
+    accetta_comunicazione, allegato_metadata, download_allegati, download_allegati_by_type, download_allegati_bytes, download_allegati_bytes_concurrent, download_allegati_bytes_with_progress,
+    download_allegati_concurrent, download_allegati_flat, download_allegati_skip_existing, download_allegati_sorted, download_allegato_stream, download_file, download_file_bytes,
+    download_file_bytes_with_progress, download_file_checked, download_file_skip_existing, download_file_stream, download_file_streaming, download_file_to, estimate_download_size, get_backeca,
+    get_backeca_lenient, get_backeca_range, get_comunicazioni, nome_allegato, rispondi_comunicazione, Allegato, AllegatoIncorporato, AllegatoMeta, Bacheca, Circolare, Comunicazione, DownloadResult,
+    DownloadStatus, DownloadSummary, RispostaData, TipoComunicazione, DEFAULT_CONCURRENCY, DEFAULT_NAME_TEMPLATE,
+};
+#[cfg(feature = "zip")]
+pub use bacheca_personale::{download_allegati_extract_zip, download_allegati_zip};
+pub use dedup::{download_allegati_dedup, DedupMode, DedupOutcome, Deduplicator};
 pub use error::SpaggiariError;
-pub use login::{login, test_session_token, AccountInfo, Auth, LoginResponse};
+pub use file_type::{detect_file_type, FileType};
+pub use login::{list_students, login, login_full, login_with_config, login_with_retry, logout, test_session_token, AccountInfo, AccountType, Auth, LoginConfig, LoginResponse, StudentRef};
+pub use manifest::{diff_manifest, Manifest, ManifestDiff, ManifestEntry};
+pub use retry::RetryPolicy;
+pub use token_store::{FileTokenStore, NoopTokenStore, PersistedSession, TokenStore};
+pub use utils::format_bytes;
+pub use voti::{get_voti, Voto};
 
 /// Crea un client HTTP configurato per Spaggiari
 ///
@@ -35,10 +89,75 @@ pub use login::{login, test_session_token, AccountInfo, Auth, LoginResponse};
 /// let client = create_client().unwrap();
 /// ```
 pub fn create_client() -> Result<Client, reqwest::Error> {
-    let jar = Jar::default();
-    let jar = Arc::new(jar);
+    create_client_with_config(&SpaggiariClientConfig::default())
+}
+
+const DEFAULT_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64)";
+
+/// Configurazione del client HTTP usato per contattare il portale
+///
+/// I campi lasciati a `None` mantengono il comportamento di `create_client`:
+/// user agent predefinito, nessun timeout, nessun proxy esplicito e il portale ufficiale.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpaggiariClientConfig {
+    /// User agent inviato al portale
+    pub user_agent: Option<String>,
+    /// Tempo massimo per completare una richiesta
+    pub timeout: Option<Duration>,
+    /// URL del proxy da usare per tutte le richieste (es. `http://proxy.local:3128`)
+    pub proxy: Option<String>,
+    /// Tempo massimo per stabilire la connessione
+    pub connect_timeout: Option<Duration>,
+    /// Indirizzo del portale usato dalla sessione (default `https://web.spaggiari.eu`),
+    /// ad esempio un mirror o un server di test
+    pub base_url: Option<String>,
+    /// Se `true` il nome utente non viene verificato prima del login (scuole con login numerici)
+    pub skip_username_validation: bool,
+    /// Numero massimo di richieste contemporanee della sessione verso il portale (nessun limite se `None`)
+    pub max_concurrent_requests: Option<usize>,
+}
+
+/// Crea un client HTTP per Spaggiari con la configurazione indicata
+///
+/// # Arguments
+///
+/// * `config` - User agent, timeout e proxy da applicare al client
+///
+/// # Returns
+///
+/// Un `Client` reqwest con i cookie abilitati, oppure un errore se il proxy non è valido
+///
+/// # Example
+///
+/// ```
+/// use spaggiari_rs::{create_client_with_config, SpaggiariClientConfig};
+/// use std::time::Duration;
+///
+/// let config = SpaggiariClientConfig {
+///     timeout: Some(Duration::from_secs(30)),
+///     ..Default::default()
+/// };
+/// let client = create_client_with_config(&config).unwrap();
+/// ```
+pub fn create_client_with_config(config: &SpaggiariClientConfig) -> Result<Client, reqwest::Error> {
+    Ok(create_client_and_jar(config)?.0)
+}
 
-    Client::builder().cookie_provider(jar).user_agent("Mozilla/5.0 (Windows NT 10.0; Win64; x64)").build()
+// Come `create_client_with_config`, restituendo anche il cookie jar del client
+pub(crate) fn create_client_and_jar(config: &SpaggiariClientConfig) -> Result<(Client, Arc<Jar>), reqwest::Error> {
+    let jar = Arc::new(Jar::default());
+
+    let mut builder = Client::builder().cookie_provider(jar.clone()).user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+    if let Some(timeout) = config.timeout {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(connect_timeout) = config.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    Ok((builder.build()?, jar))
 }
 
 /// Struttura per gestire una sessione Spaggiari
@@ -49,6 +168,20 @@ pub struct SpaggiariSession {
     pub client: Client,
     pub session_token: String,
     identity: String,
+    base_url: String,
+    activity: Arc<ActivityLog>,
+    token_store: Arc<dyn TokenStore>,
+    account_info: Option<AccountInfo>,
+    created_at: DateTime<Utc>,
+    // Limite delle richieste contemporanee, condiviso con le sessioni create da `for_student`
+    limite: Option<Arc<Semaphore>>,
+    // Cookie jar del client, se creato dalla libreria: usato per leggere il PHPSESSID del relogin
+    jar: Option<Arc<Jar>>,
+}
+
+// Semaforo per il limite di richieste contemporanee (almeno 1)
+fn semaforo(max_concurrent_requests: usize) -> Arc<Semaphore> {
+    Arc::new(Semaphore::new(max_concurrent_requests.clamp(1, Semaphore::MAX_PERMITS)))
 }
 
 impl SpaggiariSession {
@@ -65,19 +198,67 @@ impl SpaggiariSession {
     ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
     /// use spaggiari_rs::SpaggiariSession;
     ///
-    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").unwrap();
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn new(username: &str, password: &str) -> Result<Self, SpaggiariError> {
-        let client = create_client()?;
-        let session_token = login(&client, username, password).await?;
+        Self::new_with_config(username, password, &SpaggiariClientConfig::default()).await
+    }
+
+    /// Crea una nuova sessione effettuando il login con un client configurato
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - Il codice fiscale dell'utente
+    /// * `password` - La password dell'utente
+    /// * `config` - La configurazione del client HTTP (timeout, proxy, user agent)
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` autenticata
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::{SpaggiariClientConfig, SpaggiariSession};
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let config = SpaggiariClientConfig {
+    ///     proxy: Some("http://proxy.local:3128".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let session = SpaggiariSession::new_with_config("CODICE_FISCALE", "PASSWORD", &config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn new_with_config(username: &str, password: &str, config: &SpaggiariClientConfig) -> Result<Self, SpaggiariError> {
+        let (client, jar) = create_client_and_jar(config)?;
+        let base_url = config.base_url.as_deref().unwrap_or(BASE_URL);
+        let login_config = LoginConfig {
+            validate_username: !config.skip_username_validation,
+            ..LoginConfig::default()
+        };
+        login_config.check_username(username)?;
+        let limite = config.max_concurrent_requests.map(semaforo);
+        let http = Http::con_limite(client.clone(), limite.clone()).con_jar(Some(jar.clone()));
+        let (session_token, account_info) = login::login_response_with_jar_at(&http, Some(&jar), &login_config.url(base_url), username, password, &RetryPolicy::default()).await?;
 
         Ok(SpaggiariSession {
             client,
             session_token,
             identity: username.to_string(),
+            base_url: base_url.to_string(),
+            activity: Arc::default(),
+            token_store: Arc::new(NoopTokenStore),
+            account_info,
+            created_at: Utc::now(),
+            limite,
+            jar: Some(jar),
         })
     }
 
@@ -93,126 +274,2548 @@ impl SpaggiariSession {
     ///
     /// # Example
     ///
-    /// ```
+    /// ```no_run
     /// use spaggiari_rs::SpaggiariSession;
     ///
-    /// let session = SpaggiariSession::from_token("token_esistente").unwrap();
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::from_token("token_esistente".to_string()).await?;
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn from_token(session_token: String) -> Result<Self, SpaggiariError> {
-        let client = create_client()?;
         let username = std::env::var("SPAGGIARI_USERNAME")?;
+        Self::from_token_with_identity(session_token, username).await
+    }
+
+    /// Crea una sessione usando un token esistente e l'identità dell'utente a cui appartiene
+    ///
+    /// A differenza di `from_token` non legge `SPAGGIARI_USERNAME`: è pensato per i server
+    /// che ripristinano le sessioni di più utenti.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_token` - Il token di sessione salvato
+    /// * `identity` - Il codice fiscale dell'utente che ha effettuato il login
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` se il token è valido
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::from_token_with_identity("token_esistente".to_string(), "CODICE_FISCALE".to_string()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_token_with_identity(session_token: String, identity: String) -> Result<Self, SpaggiariError> {
+        Self::from_token_at(create_client()?, BASE_URL, session_token, identity).await
+    }
+
+    /// Crea una sessione da un token esistente con un client configurato
+    ///
+    /// Come `from_token_with_identity`, ma il client e l'indirizzo del portale
+    /// (`base_url`) sono presi dalla configurazione indicata.
+    ///
+    /// # Arguments
+    ///
+    /// * `session_token` - Il token di sessione salvato
+    /// * `identity` - Il codice fiscale dell'utente che ha effettuato il login
+    /// * `config` - La configurazione del client HTTP
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` se il token è valido, altrimenti `SpaggiariError::InvalidSessionToken`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::{SpaggiariClientConfig, SpaggiariSession};
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let config = SpaggiariClientConfig {
+    ///     base_url: Some("http://localhost:8080".to_string()),
+    ///     ..Default::default()
+    /// };
+    /// let session = SpaggiariSession::from_token_with_config("token_esistente".to_string(), "CODICE_FISCALE".to_string(), &config).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_token_with_config(session_token: String, identity: String, config: &SpaggiariClientConfig) -> Result<Self, SpaggiariError> {
+        let base_url = config.base_url.as_deref().unwrap_or(BASE_URL);
+        let (client, jar) = create_client_and_jar(config)?;
+        let mut session = Self::from_token_at(client, base_url, session_token, identity).await?;
+        session.limite = config.max_concurrent_requests.map(semaforo);
+        session.jar = Some(jar);
+        Ok(session)
+    }
+
+    /// Crea una sessione da un token esistente riutilizzando un client HTTP già configurato
+    ///
+    /// Utile per le applicazioni che hanno già un `Client` (pool di connessioni, TLS, proxy)
+    /// e non vogliono crearne un secondo. Il token viene verificato come in `from_token`.
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Il client HTTP da usare per tutte le richieste della sessione
+    /// * `session_token` - Il token di sessione salvato
+    /// * `identity` - Il codice fiscale dell'utente che ha effettuato il login
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` se il token è valido, altrimenti `SpaggiariError::InvalidSessionToken`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = reqwest::Client::builder().pool_max_idle_per_host(4).build()?;
+    /// let session = SpaggiariSession::from_client_and_token(client, "token_esistente".to_string(), "CODICE_FISCALE".to_string()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_client_and_token(client: Client, session_token: String, identity: String) -> Result<Self, SpaggiariError> {
+        Self::from_client_and_token_with_base_url(client, BASE_URL, session_token, identity).await
+    }
+
+    /// Come `from_client_and_token`, usando l'indirizzo del portale indicato (es. un mirror o un server di test)
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - Il client HTTP da usare per tutte le richieste della sessione
+    /// * `base_url` - L'indirizzo del portale, senza `/` finale (es. `https://web.spaggiari.eu`)
+    /// * `session_token` - Il token di sessione salvato
+    /// * `identity` - Il codice fiscale dell'utente che ha effettuato il login
+    ///
+    /// # Returns
+    ///
+    /// Una `SpaggiariSession` se il token è valido, altrimenti `SpaggiariError::InvalidSessionToken`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), Box<dyn std::error::Error>> {
+    /// let client = reqwest::Client::new();
+    /// let session = SpaggiariSession::from_client_and_token_with_base_url(client, "http://localhost:8080", "token_esistente".to_string(), "CODICE_FISCALE".to_string()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_client_and_token_with_base_url(client: Client, base_url: &str, session_token: String, identity: String) -> Result<Self, SpaggiariError> {
+        Self::from_token_at(client, base_url, session_token, identity).await
+    }
+
+    // Come `from_token_with_identity`, usando il client e l'indirizzo base indicati
+    pub(crate) async fn from_token_at(client: Client, base_url: &str, session_token: String, identity: String) -> Result<Self, SpaggiariError> {
         // Verifica che il token sia valido
-        if !test_session_token(&client, &session_token, &username).await? {
+        if !login::test_session_token_at(&Http::new(client.clone()), base_url, &session_token, &identity).await? {
             return Err(SpaggiariError::InvalidSessionToken);
         }
 
         Ok(SpaggiariSession {
             client,
             session_token,
-            identity: username,
+            identity,
+            base_url: base_url.to_string(),
+            activity: Arc::default(),
+            token_store: Arc::new(NoopTokenStore),
+            account_info: None,
+            created_at: Utc::now(),
+            limite: None,
+            jar: None,
         })
     }
 
-    /// Verifica se il token di sessione è ancora valido
+    /// Crea una sessione usando il token salvato nell'archivio indicato
+    ///
+    /// L'archivio resta associato alla sessione, come con `with_token_store`.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - L'archivio da cui leggere il token
+    /// * `identity` - Il codice fiscale dell'utente a cui appartiene il token
     ///
     /// # Returns
     ///
-    /// `true` se il token è valido, `false` altrimenti
-    pub async fn is_valid(&self) -> Result<bool, SpaggiariError> {
-        test_session_token(&self.client, &self.session_token, &self.identity).await
+    /// Una `SpaggiariSession` se l'archivio contiene un token valido,
+    /// altrimenti `SpaggiariError::InvalidSessionToken`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::{FileTokenStore, SpaggiariSession};
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let store = FileTokenStore::new("mario.token");
+    /// let session = SpaggiariSession::from_token_store(store, "CODICE_FISCALE".to_string()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_token_store(store: impl TokenStore + 'static, identity: String) -> Result<Self, SpaggiariError> {
+        let session_token = store.load()?.ok_or(SpaggiariError::InvalidSessionToken)?;
+        let mut session = Self::from_token_with_identity(session_token, identity).await?;
+        session.token_store = Arc::new(store);
+        Ok(session)
     }
 
-    /// Ottiene la bacheca personale
+    /// Crea una sessione da una `PersistedSession` salvata in precedenza
+    ///
+    /// Il token viene verificato come in `from_token_with_identity`; la data di creazione
+    /// salvata viene conservata.
+    ///
+    /// # Arguments
+    ///
+    /// * `persisted` - La sessione salvata con `to_persisted`
     ///
     /// # Returns
     ///
-    /// La struttura `Bacheca` contenente tutte le comunicazioni
+    /// Una `SpaggiariSession` se il token è ancora valido, altrimenti `SpaggiariError::InvalidSessionToken`
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// use spaggiari_rs::{PersistedSession, SpaggiariSession};
+    ///
+    /// # async fn esempio() -> Result<(), Box<dyn std::error::Error>> {
+    /// let persisted: PersistedSession = serde_json::from_str(&std::fs::read_to_string("sessione.json")?)?;
+    /// let session = SpaggiariSession::from_persisted(persisted).await?;
+    /// # Ok(())
+    /// # }
     /// ```
-    /// let session = SpaggiariSession::new("username", "password").unwrap();
-    /// let bacheca = session.get_bacheca().unwrap();
-    /// println!("Comunicazioni lette: {}", bacheca.read.len());
+    pub async fn from_persisted(persisted: PersistedSession) -> Result<Self, SpaggiariError> {
+        Self::from_persisted_at(create_client()?, BASE_URL, persisted).await
+    }
+
+    // Come `from_persisted`, usando il client e l'indirizzo base indicati
+    pub(crate) async fn from_persisted_at(client: Client, base_url: &str, persisted: PersistedSession) -> Result<Self, SpaggiariError> {
+        let mut session = Self::from_token_at(client, base_url, persisted.token, persisted.identity).await?;
+        session.created_at = persisted.created_at;
+        Ok(session)
+    }
+
+    /// Restituisce i dati della sessione da salvare (token, utente e momento del login)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), Box<dyn std::error::Error>> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// std::fs::write("sessione.json", serde_json::to_string(&session.to_persisted())?)?;
+    /// # Ok(())
+    /// # }
     /// ```
-    pub async fn get_bacheca(&self) -> Result<Bacheca, SpaggiariError> {
-        Ok(get_backeca(&self.client, &self.session_token, &self.identity).await?)
+    pub fn to_persisted(&self) -> PersistedSession {
+        PersistedSession {
+            token: self.session_token.clone(),
+            identity: self.identity.clone(),
+            created_at: self.created_at,
+        }
     }
 
-    /// Ottiene una comunicazione specifica
+    /// Associa alla sessione un archivio per il token e vi salva subito il token corrente
+    ///
+    /// Per default la sessione usa `NoopTokenStore`, che non salva nulla.
     ///
     /// # Arguments
     ///
-    /// * `circolare_id` - L'ID della circolare da ottenere
+    /// * `store` - L'archivio in cui salvare il token (file, database, ...)
     ///
     /// # Returns
     ///
-    /// La struttura `Comunicazione` con tutti i dettagli
-    pub async fn get_comunicazione(&self, circolare_id: &str) -> Result<Comunicazione, SpaggiariError> {
-        Ok(get_comunicazioni(&self.client, &self.session_token, circolare_id, "").await?)
+    /// La sessione con l'archivio associato, oppure l'errore restituito da `TokenStore::save`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::{FileTokenStore, SpaggiariSession};
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?.with_token_store(FileTokenStore::new("mario.token"))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_token_store(mut self, store: impl TokenStore + 'static) -> Result<Self, SpaggiariError> {
+        store.save(&self.session_token)?;
+        self.token_store = Arc::new(store);
+        Ok(self)
     }
 
-    /// Scarica tutti gli allegati di una comunicazione
+    /// Limita il numero di richieste contemporanee che la sessione invia al portale
+    ///
+    /// Il limite vale per tutte le richieste della sessione (bacheca, comunicazioni, download, relogin)
+    /// ed è condiviso con le sessioni create da `for_student`.
     ///
     /// # Arguments
     ///
-    /// * `allegati` - Lista degli allegati da scaricare
-    /// * `folder_path` - Percorso della cartella dove salvare i file
-    pub async fn download_allegati(&self, allegati: &[Allegato], folder_path: &str) -> Result<(), SpaggiariError> {
-        Ok(download_allegati(&self.client, &self.session_token, allegati, folder_path).await?)
+    /// * `max_concurrent_requests` - Il numero massimo di richieste in corso (almeno 1)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?.with_max_concurrent_requests(2);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_max_concurrent_requests(mut self, max_concurrent_requests: usize) -> Self {
+        self.limite = Some(semaforo(max_concurrent_requests));
+        self
     }
 
-    /// Scarica un file e ritorna il contenuto binario
+    // Percorso di invio delle richieste della sessione, con il suo limite di richieste contemporanee
+    fn http(&self) -> Http {
+        Http::con_limite(self.client.clone(), self.limite.clone()).con_jar(self.jar.clone())
+    }
+
+    // Come `http`, registrando nel log delle attività ogni file scaricato dalle operazioni su più allegati
+    fn http_registrato(&self) -> Http {
+        self.http().con_attivita(self.activity.clone())
+    }
+
+    /// Le informazioni sull'account restituite dal login
+    ///
+    /// Disponibili solo per le sessioni create con `new` o `new_with_config`:
+    /// le sessioni ripristinate da un token restituiscono `None`.
+    pub fn account_info(&self) -> Option<&AccountInfo> {
+        self.account_info.as_ref()
+    }
+
+    // Voti e assenze esistono solo per studenti e genitori: con un account docente noto
+    // la richiesta viene rifiutata senza contattare il portale. Se le informazioni dell'account
+    // non sono disponibili (es. sessione ripristinata) il tipo si ricava dal prefisso dell'identità
+    fn richiede_studente(&self, operazione: &str) -> Result<(), SpaggiariError> {
+        let account_type = match &self.account_info {
+            Some(account) => Some(account.account_type.clone()),
+            None => self.identity.get(..1).map(AccountType::from_code),
+        };
+        match account_type {
+            Some(AccountType::Teacher) => Err(SpaggiariError::Forbidden(format!("{} è disponibile solo per gli account studente e genitore", operazione))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Elenca gli studenti collegati all'account (es. i figli di un genitore)
+    ///
+    /// # Returns
+    ///
+    /// Gli studenti da passare a `for_student`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// for studente in session.list_students().await? {
+    ///     let bacheca = session.for_student(&studente).get_bacheca().await?;
+    ///     println!("{} {}: {} comunicazioni", studente.nome, studente.cognome, bacheca.read.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn list_students(&self) -> Result<Vec<StudentRef>, SpaggiariError> {
+        let http = self.http();
+        let result = login::list_students_at(&http, &self.base_url, &self.session_token, &self.identity).await;
+        self.activity.record(login::PATH_ACCOUNTS, http.status(), &result, |_| 0);
+        result
+    }
+
+    /// Crea una sessione che condivide token e client ma opera sullo studente indicato
+    ///
+    /// Le chiamate successive (bacheca, comunicazioni, allegati) usano lo studente
+    /// come `webidentity`. Il log delle attività della nuova sessione parte vuoto e disattivato.
     ///
     /// # Arguments
     ///
-    /// * `url` - URL del file da scaricare
+    /// * `student` - Uno degli studenti restituiti da `list_students`
+    pub fn for_student(&self, student: &StudentRef) -> SpaggiariSession {
+        SpaggiariSession {
+            client: self.client.clone(),
+            session_token: self.session_token.clone(),
+            identity: student.id.clone(),
+            base_url: self.base_url.clone(),
+            activity: Arc::default(),
+            token_store: self.token_store.clone(),
+            account_info: self.account_info.clone(),
+            created_at: self.created_at,
+            limite: self.limite.clone(),
+            jar: self.jar.clone(),
+        }
+    }
+
+    /// L'indirizzo del portale usato dalla sessione
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// L'archivio del token associato alla sessione
+    pub fn token_store(&self) -> &dyn TokenStore {
+        self.token_store.as_ref()
+    }
+
+    /// Abilita la registrazione delle operazioni eseguite dalla sessione
+    ///
+    /// Finché non viene chiamato, `activity_log` restituisce sempre un elenco vuoto.
+    /// Vengono registrate le richieste di bacheca, comunicazioni e conferme di lettura,
+    /// `download_file_bytes` e, per i download di più allegati (`download_allegati`,
+    /// `download_all`, ...), ogni file scaricato con il suo URL e i byte ricevuti.
+    pub fn enable_activity_log(&self) {
+        self.activity.enable();
+    }
+
+    /// Restituisce le operazioni registrate dopo `enable_activity_log`
     ///
     /// # Returns
     ///
-    /// Una tupla contenente il nome del file e il contenuto binario
+    /// Le voci del log, dalla più vecchia alla più recente
     ///
     /// # Example
     ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// session.enable_activity_log();
+    /// session.get_bacheca().await?;
+    /// for entry in session.activity_log() {
+    ///     println!("{} {} {} bytes", entry.timestamp, entry.endpoint, entry.bytes);
+    /// }
+    /// # Ok(())
+    /// # }
     /// ```
-    /// let (filename, content) = session.download_file_bytes("https://...").await?;
-    /// println!("Scaricato {} ({} bytes)", filename, content.len());
+    pub fn activity_log(&self) -> Vec<ActivityEntry> {
+        self.activity.entries()
+    }
+
+    /// Verifica se il token di sessione è ancora valido
+    ///
+    /// # Returns
+    ///
+    /// `true` se il token è valido, `false` altrimenti
+    pub async fn is_valid(&self) -> Result<bool, SpaggiariError> {
+        login::test_session_token_at(&self.http(), &self.base_url, &self.session_token, &self.identity).await
+    }
+
+    /// Mantiene attiva la sessione sul portale
+    ///
+    /// Il portale chiude le sessioni dopo un periodo di inattività: questa chiamata
+    /// richiede la bacheca limitata al giorno corrente, una richiesta leggera che
+    /// azzera il timeout lato server. Un intervallo di 5 minuti è ampiamente sufficiente.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` se la sessione è ancora attiva, un errore (es. `InvalidSessionToken`) se è scaduta
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// use std::time::Duration;
+    ///
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// loop {
+    ///     tokio::time::sleep(Duration::from_secs(300)).await;
+    ///     session.keepalive().await?;
+    /// }
+    /// # }
     /// ```
-    pub async fn download_file_bytes(&self, url: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
-        Ok(download_file_bytes(&self.client, url, &self.session_token).await?)
+    pub async fn keepalive(&self) -> Result<(), SpaggiariError> {
+        self.keepalive_at(chrono::Local::now().date_naive()).await
     }
 
-    /// Scarica tutti gli allegati in memoria e ritorna un vettore di risultati
+    // Come `keepalive`, limitando la bacheca al giorno indicato
+    pub(crate) async fn keepalive_at(&self, oggi: NaiveDate) -> Result<(), SpaggiariError> {
+        let http = self.http();
+        let result = fetch_bacheca_range_at(&http, &self.base_url, &self.session_token, &self.identity, Some((oggi, oggi))).await;
+        self.activity.record(PATH_BACHECA, http.status(), &result, String::len);
+        parse_bacheca(&result?).map(|_| ())
+    }
+
+    /// Verifica il token e, se non è più valido, effettua di nuovo il login
+    ///
+    /// Pensato per i processi di lunga durata: il nuovo token sostituisce quello
+    /// scaduto e viene salvato nell'archivio associato alla sessione. Il login viene ripetuto
+    /// solo se il portale segnala la sessione come scaduta (`InvalidSessionToken`) o chiusa da un
+    /// altro accesso (`SessionSuperseded`): gli altri errori, ad esempio di rete, vengono restituiti
+    /// senza toccare la sessione. L'identità della sessione (anche quella scelta con `for_student`)
+    /// non cambia.
+    ///
+    /// Se le credenziali appartengono a un account diverso da quello della sessione (il `cid` di
+    /// `account_info`, oppure l'identità per le sessioni ripristinate da un token) viene restituito
+    /// `SpaggiariError::IdentityMismatch` e la sessione resta invariata.
     ///
     /// # Arguments
     ///
-    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `username` - Il codice fiscale dell'utente
+    /// * `password` - La password dell'utente
     ///
     /// # Returns
     ///
-    /// Un vettore di tuple contenenti il nome del file e il contenuto binario
+    /// `true` se è stato necessario un nuovo login, `false` se il token era ancora valido
     ///
     /// # Example
     ///
-    /// ```
-    /// let comunicazione = session.get_comunicazione("123").await?;
-    /// let files = session.download_allegati_bytes(&comunicazione.allegati).await?;
-    /// for (filename, content) in files {
-    ///     println!("Scaricato {} ({} bytes)", filename, content.len());
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let mut session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// loop {
+    ///     session.ensure_valid_with("CODICE_FISCALE", "PASSWORD").await?;
+    ///     let bacheca = session.get_bacheca().await?;
+    ///     // ...
+    ///     # break;
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
-    pub async fn download_allegati_bytes(&self, allegati: Vec<Allegato>) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
-        Ok(download_allegati_bytes(&self.client, &self.session_token, allegati).await?)
+    pub async fn ensure_valid_with(&mut self, username: &str, password: &str) -> Result<bool, SpaggiariError> {
+        let http = self.http();
+        match bacheca_personale::get_backeca_at(&http, &self.base_url, &self.session_token, &self.identity).await {
+            Ok(_) => return Ok(false),
+            Err(SpaggiariError::InvalidSessionToken | SpaggiariError::SessionSuperseded) => {}
+            Err(e) => return Err(e),
+        }
+
+        let login_url = LoginConfig::default().url(&self.base_url);
+        let (session_token, account_info) = login::login_response_with_jar_at(&http, self.jar.as_deref(), &login_url, username, password, &RetryPolicy::default()).await?;
+        let atteso = self.account_info.as_ref().map_or(self.identity.as_str(), |account| account.cid.as_str());
+        if let Some(nuovo) = account_info.as_ref().filter(|account| !account.cid.eq_ignore_ascii_case(atteso)) {
+            return Err(SpaggiariError::IdentityMismatch {
+                expected: atteso.to_string(),
+                got: nuovo.cid.clone(),
+            });
+        }
+        self.token_store.save(&session_token)?;
+        self.session_token = session_token;
+        self.created_at = Utc::now();
+        if account_info.is_some() {
+            self.account_info = account_info;
+        }
+        Ok(true)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Chiude la sessione sul portale
+    ///
+    /// Consuma la sessione, così il token invalidato non può essere riutilizzato.
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` se il portale ha accettato il logout (anche con un redirect)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// session.logout().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn logout(mut self) -> Result<(), SpaggiariError> {
+        let http = self.http();
+        let result = login::logout_at(&http, &self.base_url, &self.session_token, &self.identity).await;
+        self.activity.record(login::PATH_LOGOUT, http.status(), &result, |_| 0);
+        self.session_token.clear();
+        result
+    }
 
-    #[test]
-    fn test_create_client() {
-        let client = create_client();
+    /// Attende che il token di sessione diventi non valido, controllandolo periodicamente
+    ///
+    /// Pensato per i test di applicazioni che gestiscono la scadenza della sessione
+    /// (ad esempio il re-login automatico). Disponibile con la feature `testing`.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - Il tempo massimo di attesa
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` non appena `is_valid` restituisce `false`, un errore se il token è ancora valido allo scadere del `timeout`
+    #[cfg(feature = "testing")]
+    pub async fn wait_until_invalid(&self, timeout: Duration) -> Result<(), SpaggiariError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+        let attesa = async {
+            while self.is_valid().await? {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+            Ok(())
+        };
+        tokio::time::timeout(timeout, attesa)
+            .await
+            .map_err(|_| SpaggiariError::Generic(format!("Il token è ancora valido dopo {:?}", timeout)))?
+    }
+
+    /// Ottiene la bacheca personale
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Bacheca` contenente tutte le comunicazioni
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("username", "password").await?;
+    /// let bacheca = session.get_bacheca().await?;
+    /// println!("Comunicazioni lette: {}", bacheca.read.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_bacheca(&self) -> Result<Bacheca, SpaggiariError> {
+        parse_bacheca(&self.fetch_bacheca().await?)
+    }
+
+    /// Elenca le circolari della bacheca come coppie `(id, titolo)`
+    ///
+    /// Richiede solo la bacheca, senza scaricare il testo delle comunicazioni.
+    ///
+    /// # Returns
+    ///
+    /// Le coppie `(id, titolo)` delle circolari lette seguite da quelle nuove
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// for (id, titolo) in session.list_titles().await? {
+    ///     println!("{} - {}", id, titolo);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn list_titles(&self) -> Result<Vec<(String, String)>, SpaggiariError> {
+        let bacheca = self.get_bacheca().await?;
+        Ok(bacheca.all().map(|circolare| (circolare.id.clone(), circolare.titolo.clone())).collect())
+    }
+
+    /// Ottiene le circolari della bacheca pubblicate tra due date (estremi inclusi)
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Prima data dell'intervallo
+    /// * `to` - Ultima data dell'intervallo
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Bacheca` con le sole comunicazioni dell'intervallo
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// use chrono::NaiveDate;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("username", "password").await?;
+    /// let settembre = session.get_bacheca_range(NaiveDate::from_ymd_opt(2024, 9, 1).unwrap(), NaiveDate::from_ymd_opt(2024, 9, 30).unwrap()).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_bacheca_range(&self, from: NaiveDate, to: NaiveDate) -> Result<Bacheca, SpaggiariError> {
+        let http = self.http();
+        let result = fetch_bacheca_range_at(&http, &self.base_url, &self.session_token, &self.identity, Some((from, to))).await;
+        self.activity.record(PATH_BACHECA, http.status(), &result, String::len);
+        parse_bacheca(&result?)
+    }
+
+    /// Ottiene la bacheca personale scartando le circolari malformate
+    ///
+    /// # Returns
+    ///
+    /// La `Bacheca` con le sole circolari valide e i messaggi di errore di quelle scartate
+    pub async fn get_bacheca_lenient(&self) -> Result<(Bacheca, Vec<String>), SpaggiariError> {
+        parse_bacheca_lenient(&self.fetch_bacheca().await?)
+    }
+
+    /// Ottiene i voti dello studente
+    ///
+    /// # Returns
+    ///
+    /// I voti presenti nella pagina dei voti, con materia, valore, data e tipo di prova.
+    /// `SpaggiariError::Forbidden` se la sessione appartiene a un account docente
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// for voto in session.get_voti().await? {
+    ///     println!("{} {}: {}", voto.data, voto.materia, voto.valore);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_voti(&self) -> Result<Vec<Voto>, SpaggiariError> {
+        self.richiede_studente("Il registro dei voti")?;
+        let http = self.http();
+        let result = fetch_voti_at(&http, &self.base_url, &self.session_token, &self.identity).await;
+        self.activity.record(PATH_VOTI, http.status(), &result, String::len);
+        parse_voti(&result?)
+    }
+
+    /// Ottiene assenze, ritardi e uscite anticipate dello studente
+    ///
+    /// # Returns
+    ///
+    /// Gli eventi della pagina delle assenze, con data, tipo, giustificazione e motivo.
+    /// `SpaggiariError::Forbidden` se la sessione appartiene a un account docente
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let da_giustificare = session.get_assenze().await?.into_iter().filter(|a| !a.giustificata).count();
+    /// println!("Eventi da giustificare: {}", da_giustificare);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_assenze(&self) -> Result<Vec<Assenza>, SpaggiariError> {
+        self.richiede_studente("Il registro delle assenze")?;
+        let http = self.http();
+        let result = fetch_assenze_at(&http, &self.base_url, &self.session_token, &self.identity).await;
+        self.activity.record(PATH_ASSENZE, http.status(), &result, String::len);
+        parse_assenze(&result?)
+    }
+
+    /// Ottiene gli eventi dell'agenda (compiti, verifiche, annotazioni) tra due date (estremi inclusi)
+    ///
+    /// # Arguments
+    ///
+    /// * `from` - Prima data dell'intervallo
+    /// * `to` - Ultima data dell'intervallo
+    ///
+    /// # Returns
+    ///
+    /// Gli eventi dell'intervallo con materia, descrizione, data e tipo
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// use chrono::{Duration, Local};
+    ///
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let oggi = Local::now().date_naive();
+    /// for evento in session.get_agenda(oggi, oggi + Duration::days(7)).await? {
+    ///     println!("{} {:?}: {}", evento.data, evento.tipo, evento.descrizione);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_agenda(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<AgendaEvent>, SpaggiariError> {
+        let http = self.http();
+        let result = fetch_agenda_at(&http, &self.base_url, &self.session_token, &self.identity, from, to).await;
+        self.activity.record(PATH_AGENDA, http.status(), &result, String::len);
+        parse_agenda(&result?)
+    }
+
+    // Scarica il JSON grezzo della bacheca, registrando l'operazione nel log delle attività
+    async fn fetch_bacheca(&self) -> Result<String, SpaggiariError> {
+        let http = self.http();
+        let result = fetch_bacheca_at(&http, &self.base_url, &self.session_token, &self.identity).await;
+        self.activity.record(PATH_BACHECA, http.status(), &result, String::len);
+        result
+    }
+
+    // Scarica e interpreta una comunicazione, registrando l'operazione nel log delle attività
+    async fn fetch_comunicazione(&self, circolare_id: &str) -> Result<Comunicazione, SpaggiariError> {
+        let http = self.http();
+        let result = fetch_comunicazione_at(&http, &self.base_url, &self.session_token, circolare_id, &self.identity).await;
+        self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, String::len);
+        parse_comunicazione(&result?, circolare_id)
+    }
+
+    /// Ottiene una comunicazione specifica
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare_id` - L'ID della circolare da ottenere
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Comunicazione` con tutti i dettagli
+    pub async fn get_comunicazione(&self, circolare_id: &str) -> Result<Comunicazione, SpaggiariError> {
+        self.fetch_comunicazione(circolare_id).await
+    }
+
+    /// Ottiene una comunicazione che può richiedere un consenso prima di mostrare il testo
+    ///
+    /// Alcune comunicazioni mostrano una pagina di consenso al posto del testo: in questo caso
+    /// `get_comunicazione` restituisce `SpaggiariError::ConsensoRichiesto`.
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare_id` - L'ID della circolare da ottenere
+    /// * `auto_consent` - Se `true`, invia il consenso e scarica di nuovo la comunicazione
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Comunicazione`, oppure `SpaggiariError::ConsensoRichiesto`
+    /// se serve il consenso e `auto_consent` è `false`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione_with_consent("123", true).await?;
+    /// println!("{}", comunicazione.testo);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_comunicazione_with_consent(&self, circolare_id: &str, auto_consent: bool) -> Result<Comunicazione, SpaggiariError> {
+        match self.fetch_comunicazione(circolare_id).await {
+            Err(SpaggiariError::ConsensoRichiesto(_)) if auto_consent => {
+                let http = self.http();
+                let result = invia_consenso_at(&http, &self.base_url, &self.session_token, &self.identity, circolare_id).await;
+                self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, |_| 0);
+                result?;
+                self.fetch_comunicazione(circolare_id).await
+            }
+            result => result,
+        }
+    }
+
+    /// Ottiene una comunicazione a partire dal suo `codice` (il numero visibile in bacheca)
+    ///
+    /// Il codice viene risolto nell'`id` interno consultando la bacheca.
+    ///
+    /// # Arguments
+    ///
+    /// * `codice` - Il codice della circolare
+    ///
+    /// # Returns
+    ///
+    /// La struttura `Comunicazione`, oppure `SpaggiariError::ComunicazioneNotFound`
+    /// se nessuna circolare in bacheca ha quel codice
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione_by_codice(42).await?;
+    /// println!("{}", comunicazione.testo);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn get_comunicazione_by_codice(&self, codice: i32) -> Result<Comunicazione, SpaggiariError> {
+        let bacheca = self.get_bacheca().await?;
+        let circolare = bacheca.find_by_codice(codice).ok_or_else(|| SpaggiariError::ComunicazioneNotFound(codice.to_string()))?;
+        self.fetch_comunicazione(&circolare.id).await
+    }
+
+    /// Ottiene una comunicazione insieme alla pagina HTML da cui è stata estratta
+    ///
+    /// Utile per confrontare l'HTML con il risultato del parsing quando l'estrazione
+    /// non è corretta. Disponibile con la feature `debug-api`.
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare_id` - L'ID della circolare da ottenere
+    ///
+    /// # Returns
+    ///
+    /// L'HTML grezzo della pagina e la `Comunicazione` estratta
+    #[cfg(feature = "debug-api")]
+    pub async fn get_comunicazione_debug(&self, circolare_id: &str) -> Result<(String, Comunicazione), SpaggiariError> {
+        let html = bacheca_personale::fetch_comunicazione_at(&self.http(), &self.base_url, &self.session_token, circolare_id, &self.identity).await?;
+        let comunicazione = bacheca_personale::parse_comunicazione(&html, circolare_id)?;
+        Ok((html, comunicazione))
+    }
+
+    /// Ottiene il testo di una comunicazione convertito in Markdown
+    ///
+    /// A differenza di `get_comunicazione` mantiene link, elenchi e formattazione,
+    /// utile per archiviare le circolari. Disponibile con la feature `markdown`.
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare_id` - L'ID della circolare da ottenere
+    ///
+    /// # Returns
+    ///
+    /// Il testo della comunicazione in Markdown
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// let markdown = session.get_comunicazione_markdown("12345").await?;
+    /// std::fs::write("circolare.md", markdown)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "markdown")]
+    pub async fn get_comunicazione_markdown(&self, circolare_id: &str) -> Result<String, SpaggiariError> {
+        let http = self.http();
+        let result = fetch_comunicazione_at(&http, &self.base_url, &self.session_token, circolare_id, &self.identity).await;
+        self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, String::len);
+        let html = result?;
+        // Stessi controlli di `get_comunicazione` (sessione sostituita, consenso richiesto)
+        parse_comunicazione(&html, circolare_id)?;
+        bacheca_personale::extract_markdown_comunicazione(&html)
+    }
+
+    /// Ottiene una comunicazione e, se la circolare lo richiede, ne conferma la lettura
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare` - La circolare da aprire
+    ///
+    /// # Returns
+    ///
+    /// La `Comunicazione` e `true` se è stata inviata la conferma di lettura
+    pub async fn get_comunicazione_and_confirm(&self, circolare: &Circolare) -> Result<(Comunicazione, bool), SpaggiariError> {
+        let comunicazione = self.fetch_comunicazione(&circolare.id).await?;
+
+        if !circolare.richiede_conferma() {
+            return Ok((comunicazione, false));
+        }
+
+        self.conferma_lettura(circolare).await?;
+        Ok((comunicazione, true))
+    }
+
+    /// Conferma la lettura di una circolare
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare` - La circolare di cui confermare la lettura
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` se il portale ha registrato la conferma, `SpaggiariError::ApiError`
+    /// se la circolare non richiede la conferma di lettura (`conf_lettura`)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let bacheca = session.get_bacheca().await?;
+    /// for circolare in bacheca.all().filter(|c| c.richiede_conferma()) {
+    ///     session.conferma_lettura(circolare).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn conferma_lettura(&self, circolare: &Circolare) -> Result<(), SpaggiariError> {
+        if !circolare.richiede_conferma() {
+            return Err(SpaggiariError::ApiError {
+                message: format!("La circolare {} non richiede la conferma di lettura", circolare.id),
+            });
+        }
+
+        let http = self.http();
+        let result = conferma_lettura_at(&http, &self.base_url, &self.session_token, &self.identity, circolare).await;
+        self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, |_| 0);
+        result
+    }
+
+    /// Risponde con un testo a una circolare che lo richiede (`flag_risp`)
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare` - La circolare a cui rispondere
+    /// * `testo` - Il testo della risposta
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` se il portale ha registrato la risposta, `SpaggiariError::AzioneNonPrevista`
+    /// se la circolare non prevede una risposta
+    pub async fn rispondi_comunicazione(&self, circolare: &Circolare, testo: &str) -> Result<(), SpaggiariError> {
+        let http = self.http();
+        let result = rispondi_comunicazione_at(&http, &self.base_url, &self.session_token, &self.identity, circolare, testo).await;
+        self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, |_| 0);
+        result
+    }
+
+    /// Accetta o rifiuta una circolare che richiede una decisione (`flag_accettazione`)
+    ///
+    /// # Arguments
+    ///
+    /// * `circolare` - La circolare da accettare o rifiutare
+    /// * `accept` - `true` per accettare, `false` per rifiutare
+    ///
+    /// # Returns
+    ///
+    /// `Ok(())` se il portale ha registrato la decisione, `SpaggiariError::AzioneNonPrevista`
+    /// se la circolare non prevede l'accettazione
+    pub async fn accetta_comunicazione(&self, circolare: &Circolare, accept: bool) -> Result<(), SpaggiariError> {
+        let http = self.http();
+        let result = accetta_comunicazione_at(&http, &self.base_url, &self.session_token, &self.identity, circolare, accept).await;
+        self.activity.record(PATH_COMUNICAZIONI, http.status(), &result, |_| 0);
+        result
+    }
+
+    /// Scarica tutte le comunicazioni della bacheca, lette e nuove
+    ///
+    /// Per ogni circolare crea la cartella `dest_root/<codice>/` con il testo della
+    /// comunicazione in `README.txt` e i relativi allegati.
+    ///
+    /// # Arguments
+    ///
+    /// * `dest_root` - Cartella in cui creare le sottocartelle delle circolari
+    ///
+    /// # Returns
+    ///
+    /// Il numero di comunicazioni salvate e di file scritti
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::SpaggiariSession;
+    /// use std::path::Path;
+    ///
+    /// # async fn esempio() -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let session = SpaggiariSession::new("CODICE_FISCALE", "PASSWORD").await?;
+    /// let riepilogo = session.download_all(Path::new("download")).await?;
+    /// println!("{} comunicazioni, {} file", riepilogo.comunicazioni, riepilogo.file);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_all(&self, dest_root: &Path) -> Result<DownloadSummary, SpaggiariError> {
+        let bacheca = self.get_bacheca().await?;
+        let mut summary = DownloadSummary::default();
+
+        for circolare in bacheca.all() {
+            let comunicazione = self.fetch_comunicazione(&circolare.id).await?;
+
+            let subfolder = dest_root.join(circolare.codice.to_string());
+            std::fs::create_dir_all(&subfolder)?;
+            std::fs::write(subfolder.join("README.txt"), comunicazione.testo.as_bytes())?;
+
+            self.download_allegati(&comunicazione.allegati, &subfolder.to_string_lossy()).await?;
+            summary.comunicazioni += 1;
+            summary.file += 1 + comunicazione.allegati.len();
+        }
+
+        Ok(summary)
+    }
+
+    /// Restituisce uno stream delle comunicazioni complete di tutte le circolari della bacheca
+    ///
+    /// Lo stream è pigro: la bacheca viene scaricata alla prima lettura e ogni comunicazione
+    /// solo quando viene richiesta, così le bacheche molto grandi possono essere elaborate
+    /// man mano. Se la bacheca non è disponibile lo stream restituisce un solo errore.
+    ///
+    /// # Returns
+    ///
+    /// Uno stream con una `Comunicazione` per ogni circolare (prima le lette, poi le nuove)
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// use futures::StreamExt;
+    ///
+    /// # async fn esempio(session: SpaggiariSession) {
+    /// session
+    ///     .stream_comunicazioni()
+    ///     .for_each_concurrent(4, |comunicazione| async move {
+    ///         if let Ok(comunicazione) = comunicazione {
+    ///             println!("{}", comunicazione.testo);
+    ///         }
+    ///     })
+    ///     .await;
+    /// # }
+    /// ```
+    pub fn stream_comunicazioni(&self) -> impl Stream<Item = Result<Comunicazione, SpaggiariError>> + '_ {
+        stream::once(self.get_bacheca())
+            .map_ok(|bacheca| stream::iter(bacheca.all().map(|circolare| circolare.id.clone()).collect::<Vec<_>>()).map(Ok))
+            .try_flatten()
+            .and_then(move |circolare_id| async move { self.fetch_comunicazione(&circolare_id).await })
+    }
+
+    /// Scarica tutti gli allegati di una comunicazione
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    pub async fn download_allegati(&self, allegati: &[Allegato], folder_path: &str) -> Result<(), SpaggiariError> {
+        bacheca_personale::download_allegati_at(&self.http_registrato(), &self.base_url, &self.session_token, &self.identity, allegati, folder_path, DEFAULT_CONCURRENCY).await
+    }
+
+    /// Scarica gli allegati di una comunicazione, saltando quelli già presenti nella cartella
+    ///
+    /// Un allegato è già presente se esiste un file con lo stesso nome e la dimensione indicata
+    /// dal server: ripetere il download dopo un'interruzione scarica solo i file mancanti.
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    ///
+    /// # Returns
+    ///
+    /// L'esito di ciascun allegato, scaricato o già presente
+    pub async fn download_allegati_skip_existing(&self, allegati: &[Allegato], folder_path: &str) -> Result<Vec<DownloadStatus>, SpaggiariError> {
+        bacheca_personale::download_allegati_skip_existing_at(&self.http_registrato(), &self.base_url, &self.session_token, &self.identity, allegati, folder_path, DEFAULT_CONCURRENCY).await
+    }
+
+    /// Scarica solo gli allegati del tipo richiesto, riconoscendolo senza scaricare i file per intero
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati tra cui scegliere
+    /// * `types` - I tipi di file da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    ///
+    /// # Returns
+    ///
+    /// I percorsi dei file salvati
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::{FileType, SpaggiariSession};
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione("123").await?;
+    /// let pdf = session.download_allegati_by_type(&comunicazione.allegati, &[FileType::Pdf], "download/123").await?;
+    /// println!("Scaricati {} PDF", pdf.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_allegati_by_type(&self, allegati: &[Allegato], types: &[FileType], folder_path: &str) -> Result<Vec<String>, SpaggiariError> {
+        download_allegati_by_type_at(&self.http_registrato(), &self.base_url, &self.session_token, &self.identity, allegati, types, folder_path).await
+    }
+
+    /// Scarica gli allegati suddividendoli nelle sottocartelle `pdf/`, `images/`, `docs/` e `other/`
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella in cui creare le sottocartelle
+    ///
+    /// # Returns
+    ///
+    /// Il numero di file salvati in ciascuna sottocartella
+    pub async fn download_allegati_sorted(&self, allegati: &[Allegato], folder_path: &str) -> Result<BTreeMap<String, usize>, SpaggiariError> {
+        download_allegati_sorted_at(&self.http_registrato(), &self.base_url, &self.session_token, &self.identity, allegati, folder_path).await
+    }
+
+    /// Scarica gli allegati di una circolare in una cartella condivisa, nominandoli secondo un modello
+    ///
+    /// # Arguments
+    ///
+    /// * `codice` - Il codice della circolare a cui appartengono gli allegati
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella condivisa
+    /// * `template` - Il modello del nome dei file, ad esempio `DEFAULT_NAME_TEMPLATE`
+    ///
+    /// # Returns
+    ///
+    /// I percorsi dei file salvati
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::{SpaggiariSession, DEFAULT_NAME_TEMPLATE};
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let bacheca = session.get_bacheca().await?;
+    /// for circolare in &bacheca.read {
+    ///     let comunicazione = session.get_comunicazione(&circolare.id).await?;
+    ///     session.download_allegati_flat(circolare.codice, &comunicazione.allegati, "allegati", DEFAULT_NAME_TEMPLATE).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_allegati_flat(&self, codice: i32, allegati: &[Allegato], folder_path: &str, template: &str) -> Result<Vec<String>, SpaggiariError> {
+        download_allegati_flat_at(&self.http(), &self.base_url, &self.session_token, &self.identity, codice, allegati, folder_path, template).await
+    }
+
+    /// Scarica tutti gli allegati estraendo automaticamente gli archivi ZIP, disponibile con la feature `zip`
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    /// * `keep_archive` - Se `true` conserva anche il file `.zip` originale
+    ///
+    /// # Returns
+    ///
+    /// I percorsi dei file salvati, compresi quelli estratti
+    #[cfg(feature = "zip")]
+    pub async fn download_allegati_extract_zip(&self, allegati: &[Allegato], folder_path: &str, keep_archive: bool) -> Result<Vec<String>, SpaggiariError> {
+        download_allegati_extract_zip_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegati, folder_path, keep_archive).await
+    }
+
+    /// Scarica gli allegati in un unico archivio ZIP, disponibile con la feature `zip`
+    ///
+    /// I nomi ripetuti ricevono un suffisso numerico (`modulo.pdf`, `modulo_2.pdf`, ...).
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `out` - Destinazione dell'archivio
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione("123").await?;
+    /// let file = std::fs::File::create("circolare_123.zip")?;
+    /// session.download_allegati_zip(&comunicazione.allegati, file).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "zip")]
+    pub async fn download_allegati_zip(&self, allegati: &[Allegato], out: impl std::io::Write) -> Result<(), SpaggiariError> {
+        bacheca_personale::download_allegati_zip_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegati, out).await
+    }
+
+    /// Scarica gli allegati evitando copie duplicate tra comunicazioni diverse
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `folder_path` - Percorso della cartella dove salvare i file
+    /// * `dedup` - Il `Deduplicator` da riutilizzare per tutte le comunicazioni archiviate
+    ///
+    /// # Returns
+    ///
+    /// L'esito del salvataggio di ciascun allegato
+    pub async fn download_allegati_dedup(&self, allegati: &[Allegato], folder_path: &str, dedup: &mut Deduplicator) -> Result<Vec<DedupOutcome>, SpaggiariError> {
+        dedup::download_allegati_dedup_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegati, folder_path, dedup).await
+    }
+
+    /// Scarica un file e lo salva esattamente nel percorso indicato
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL del file da scaricare
+    /// * `exact_path` - Percorso completo del file da creare
+    ///
+    /// # Returns
+    ///
+    /// Il percorso del file salvato
+    pub async fn download_file_to(&self, url: &str, exact_path: &str) -> Result<String, SpaggiariError> {
+        bacheca_personale::download_file_to_via(&self.http(), url, &self.session_token, &self.identity, exact_path).await
+    }
+
+    /// Scarica un file e ritorna il contenuto binario
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - URL del file da scaricare
+    ///
+    /// # Returns
+    ///
+    /// Una tupla contenente il nome del file e il contenuto binario
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let (filename, content) = session.download_file_bytes("https://...").await?;
+    /// println!("Scaricato {} ({} bytes)", filename, content.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_file_bytes(&self, url: &str) -> Result<(String, Vec<u8>), SpaggiariError> {
+        let http = self.http();
+        let result = bacheca_personale::download_file_bytes_via(&http, url, &self.session_token, &self.identity).await;
+        self.activity.record(url, http.status(), &result, |(_, content)| content.len());
+        result
+    }
+
+    /// Scarica un allegato passando il contenuto a `sink` man mano che arriva
+    ///
+    /// # Arguments
+    ///
+    /// * `allegato` - L'allegato da scaricare
+    /// * `sink` - Funzione richiamata per ogni blocco di byte ricevuto
+    ///
+    /// # Returns
+    ///
+    /// Il nome del file
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::{Allegato, SpaggiariSession};
+    /// # async fn esempio(session: SpaggiariSession, allegato: Allegato) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let mut totale = 0;
+    /// let filename = session
+    ///     .download_allegato_stream(&allegato, |chunk| {
+    ///         totale += chunk.len();
+    ///         Ok::<(), std::io::Error>(())
+    ///     })
+    ///     .await?;
+    /// println!("Inoltrato {} ({} bytes)", filename, totale);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_allegato_stream<F, E>(&self, allegato: &Allegato, sink: F) -> Result<String, SpaggiariError>
+    where
+        F: FnMut(&[u8]) -> Result<(), E>,
+        E: std::fmt::Display,
+    {
+        bacheca_personale::download_allegato_stream_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegato, sink).await
+    }
+
+    /// Legge nome, dimensione e tipo di un allegato senza scaricarlo
+    ///
+    /// # Arguments
+    ///
+    /// * `allegato` - L'allegato di cui leggere le informazioni
+    ///
+    /// # Returns
+    ///
+    /// Un `AllegatoMeta` con nome del file, dimensione e tipo MIME
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use spaggiari_rs::{format_bytes, Allegato, SpaggiariSession};
+    ///
+    /// # async fn esempio(session: SpaggiariSession, allegato: Allegato) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let meta = session.allegato_metadata(&allegato).await?;
+    /// println!("{} ({})", meta.filename, meta.content_length.map(format_bytes).unwrap_or_default());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn allegato_metadata(&self, allegato: &Allegato) -> Result<AllegatoMeta, SpaggiariError> {
+        let http = self.http();
+        let result = bacheca_personale::allegato_metadata_at(&http, &self.base_url, &self.session_token, &self.identity, allegato).await;
+        self.activity.record(&allegato.download_url(&self.base_url), http.status(), &result, |_| 0);
+        result
+    }
+
+    /// Stima lo spazio necessario per scaricare gli allegati, senza scaricarli
+    ///
+    /// Le dimensioni sono lette con richieste HEAD, al massimo `DEFAULT_CONCURRENCY` alla volta;
+    /// gli allegati di cui il server non indica la dimensione non vengono conteggiati.
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Gli allegati da scaricare
+    ///
+    /// # Returns
+    ///
+    /// La dimensione totale in byte
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::{format_bytes, SpaggiariSession};
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione("123").await?;
+    /// let totale = session.estimate_download_size(&comunicazione.allegati).await?;
+    /// println!("Verranno scaricati {}", format_bytes(totale));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn estimate_download_size(&self, allegati: &[Allegato]) -> Result<u64, SpaggiariError> {
+        let http = self.http();
+        let result = bacheca_personale::estimate_download_size_at(&http, &self.base_url, &self.session_token, &self.identity, allegati, DEFAULT_CONCURRENCY).await;
+        // Una voce per ogni allegato interrogato; quelli incorporati non richiedono il portale
+        for allegato in allegati.iter().filter(|allegato| allegato.incorporato.is_none()) {
+            self.activity.record(&allegato.download_url(&self.base_url), http.status(), &result, |_| 0);
+        }
+        result
+    }
+
+    /// Scarica tutti gli allegati in memoria e ritorna un vettore di risultati
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    ///
+    /// # Returns
+    ///
+    /// Un vettore di tuple contenenti il nome del file e il contenuto binario
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use spaggiari_rs::SpaggiariSession;
+    /// # async fn esempio(session: SpaggiariSession) -> Result<(), spaggiari_rs::SpaggiariError> {
+    /// let comunicazione = session.get_comunicazione("123").await?;
+    /// let files = session.download_allegati_bytes(comunicazione.allegati).await?;
+    /// for (filename, content) in files {
+    ///     println!("Scaricato {} ({} bytes)", filename, content.len());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn download_allegati_bytes(&self, allegati: Vec<Allegato>) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError> {
+        bacheca_personale::download_allegati_bytes_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegati, DEFAULT_CONCURRENCY).await
+    }
+
+    /// Scarica in parallelo tutti gli allegati in memoria, riportando l'avanzamento complessivo
+    ///
+    /// # Arguments
+    ///
+    /// * `allegati` - Lista degli allegati da scaricare
+    /// * `callback` - Funzione richiamata con (byte scaricati, byte totali) su tutti i file
+    ///
+    /// # Returns
+    ///
+    /// Un vettore di tuple contenenti il nome del file e il contenuto binario
+    pub async fn download_allegati_bytes_with_progress<F>(&self, allegati: Vec<Allegato>, callback: F) -> Result<Vec<(String, Vec<u8>)>, SpaggiariError>
+    where
+        F: Fn(u64, Option<u64>),
+    {
+        bacheca_personale::download_allegati_bytes_with_progress_at(&self.http(), &self.base_url, &self.session_token, &self.identity, allegati, callback).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_support::{circolare, circolare_json, session, LOGIN_OK};
+    use wiremock::matchers::{body_string_contains, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_create_client() {
+        let client = create_client();
         assert!(client.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_client_config_timeout_applied() {
+        // Il server accetta la connessione ma non risponde mai
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let _server = tokio::spawn(async move {
+            let (_socket, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let config = SpaggiariClientConfig {
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        };
+        let client = create_client_with_config(&config).unwrap();
+        let err = client.get(&url).send().await.unwrap_err();
+        assert!(err.is_timeout(), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_session_reads_phpsessid_from_cookie_jar() {
+        let server = MockServer::start().await;
+        // Il cookie arriva sul redirect: la risposta finale non ha header Set-Cookie
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .respond_with(
+                ResponseTemplate::new(302)
+                    .insert_header("Set-Cookie", "PHPSESSID=dal_jar; path=/")
+                    .insert_header("Location", format!("{}/auth-p7/app/default/esito.php", server.uri()).as_str()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/auth-p7/app/default/esito.php"))
+            .and(header("Cookie", "PHPSESSID=dal_jar"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(LOGIN_OK))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config = SpaggiariClientConfig {
+            base_url: Some(server.uri()),
+            ..Default::default()
+        };
+        let session = SpaggiariSession::new_with_config("G1234567X", "password", &config).await.unwrap();
+
+        assert_eq!(session.session_token, "dal_jar");
+    }
+
+    #[tokio::test]
+    async fn test_session_against_custom_base_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .and(query_param("a", "aLoginPwd"))
+            .and(body_string_contains("uid=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=mock123; path=/").set_body_string(LOGIN_OK))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=mock123; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":null}}"#, circolare_json("301", 7))))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let config = SpaggiariClientConfig {
+            base_url: Some(server.uri()),
+            ..Default::default()
+        };
+        let session = SpaggiariSession::new_with_config("G1234567X", "password", &config).await.unwrap();
+
+        assert_eq!(session.session_token, "mock123");
+        assert_eq!(session.account_info().map(|a| a.nome.as_str()), Some("Mario"));
+        assert_eq!(session.base_url(), server.uri());
+        assert_eq!(session.get_bacheca().await.unwrap().read[0].codice, 7);
+    }
+
+    #[test]
+    fn test_client_config_invalid_proxy() {
+        let config = SpaggiariClientConfig {
+            proxy: Some("not a url".to_string()),
+            ..Default::default()
+        };
+        assert!(create_client_with_config(&config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_conferma_lettura_sends_form_fields() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .and(body_string_contains("action=conferma_lettura"))
+            .and(body_string_contains("com_id=101"))
+            .and(body_string_contains("id_relazione=55"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut da_confermare = circolare("101", 1, "Circolari");
+        da_confermare.conf_lettura = "1".to_string();
+        da_confermare.id_relazione = "55".to_string();
+        session(&server.uri()).conferma_lettura(&da_confermare).await.unwrap();
+
+        // Una circolare senza conferma richiesta non genera richieste
+        let result = session(&server.uri()).conferma_lettura(&circolare("102", 2, "Circolari")).await;
+        assert!(matches!(result, Err(SpaggiariError::ApiError { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazione_and_confirm_posts_after_get() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(query_param("action", "risposta_com"))
+            .and(query_param("com_id", "101"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Da confermare</div>"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(body_string_contains("action=conferma_lettura"))
+            .and(body_string_contains("com_id=101"))
+            .and(body_string_contains("id_relazione=55"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut da_confermare = circolare("101", 1, "Circolari");
+        da_confermare.conf_lettura = "1".to_string();
+        da_confermare.id_relazione = "55".to_string();
+
+        let (comunicazione, confermata) = session(&server.uri()).get_comunicazione_and_confirm(&da_confermare).await.unwrap();
+
+        assert_eq!(comunicazione.testo, "Da confermare");
+        assert!(confermata);
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method.as_str(), "GET");
+        assert_eq!(requests[1].method.as_str(), "POST");
+    }
+
+    #[tokio::test]
+    async fn test_from_token_with_identity_uses_given_identity() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=tok; webidentity=X9876543Y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // L'identità arriva solo dal parametro: nessuna lettura di SPAGGIARI_USERNAME
+        let session = SpaggiariSession::from_token_at(Client::new(), &server.uri(), "tok".to_string(), "X9876543Y".to_string()).await.unwrap();
+        assert_eq!(session.identity, "X9876543Y");
+        assert_eq!(session.session_token, "tok");
+    }
+
+    #[tokio::test]
+    async fn test_persisted_session_round_trip() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .mount(&server)
+            .await;
+
+        let mut originale = session(&server.uri());
+        originale.created_at = "2024-10-14T07:30:00Z".parse().unwrap();
+        let json = serde_json::to_string(&originale.to_persisted()).unwrap();
+        assert_eq!(json, r#"{"token":"sess","identity":"G1234567X","created_at":"2024-10-14T07:30:00Z"}"#);
+
+        let ripristinata = SpaggiariSession::from_persisted_at(Client::new(), &server.uri(), serde_json::from_str(&json).unwrap()).await.unwrap();
+        assert_eq!(ripristinata.to_persisted(), originale.to_persisted());
+    }
+
+    #[test]
+    fn test_with_token_store_saves_current_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileTokenStore::new(dir.path().join("phpsessid.token"));
+
+        let session = session("http://localhost").with_token_store(store.clone()).unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("sess"));
+        assert_eq!(session.token_store().load().unwrap().as_deref(), Some("sess"));
+    }
+
+    #[tokio::test]
+    async fn test_session_uses_caller_provided_client() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("User-Agent", "app-esterna/1.0"))
+            .and(header("Cookie", "PHPSESSID=tok; webidentity=X9876543Y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = Client::builder().user_agent("app-esterna/1.0").build().unwrap();
+        let session = SpaggiariSession::from_client_and_token_with_base_url(client, &server.uri(), "tok".to_string(), "X9876543Y".to_string())
+            .await
+            .unwrap();
+        session.get_bacheca().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_from_token_with_identity_rejects_invalid_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(401)).mount(&server).await;
+
+        let result = SpaggiariSession::from_token_at(Client::new(), &server.uri(), "tok".to_string(), "X9876543Y".to_string()).await;
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)));
+    }
+
+    #[tokio::test]
+    async fn test_logout_follows_redirect_to_login_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/logout.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(302).insert_header("Location", "/home/"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET")).and(path("/home/")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        session(&server.uri()).logout().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_sends_single_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("data_start", "2024-10-14"))
+            .and(query_param("data_stop", "2024-10-14"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        session(&server.uri()).keepalive_at(NaiveDate::from_ymd_opt(2024, 10, 14).unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_for_student_scopes_bacheca() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .and(query_param("a", "aAccounts"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(
+                    r#"{"data":{"accounts":[{"cid":"S1111111A","nome":"Luca","cognome":"Rossi","type":"S"},{"cid":"S2222222B","nome":"Anna","cognome":"Rossi","type":"S"}]},"error":[]}"#,
+                ),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=S2222222B"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":null}}"#, circolare_json("301", 7))))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let session = session(&server.uri());
+        let studenti = session.list_students().await.unwrap();
+        assert_eq!(studenti.len(), 2);
+        let anna = session.for_student(&studenti[1]);
+        assert_eq!(anna.get_bacheca().await.unwrap().read[0].codice, 7);
+    }
+
+    #[tokio::test]
+    async fn test_get_voti_uses_session_cookie() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cvv/app/default/genitori_voti.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<table><tr class="riga_materia_componente"><td class="materia_padre">STORIA</td><td class="cella_voto" title="Orale"><span class="voto_data">05/11/2024</span><p class="s_reg_testo">9</p></td></tr></table>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let voti = session(&server.uri()).get_voti().await.unwrap();
+        assert_eq!(
+            voti,
+            vec![Voto {
+                materia: "STORIA".to_string(),
+                valore: "9".to_string(),
+                data: "05/11/2024".to_string(),
+                tipo: "Orale".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_assenze_uses_session_cookie() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cvv/app/default/genitori_assenze.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                r#"<table id="assenze"><tr class="riga_evento"><td class="evento_data">14/10/2024</td><td class="evento_tipo">Assenza</td><td class="evento_giustificazione da_giustificare"></td><td class="evento_motivo"></td></tr></table>"#,
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let assenze = session(&server.uri()).get_assenze().await.unwrap();
+        assert_eq!(
+            assenze,
+            vec![Assenza {
+                data: "14/10/2024".to_string(),
+                tipo: TipoAssenza::Assenza,
+                giustificata: false,
+                motivo: None,
+            }]
+        );
+    }
+
+    // Sessione di un account docente, con il portale che non si aspetta richieste
+    async fn sessione_docente(server: &MockServer, pagina: &str) -> SpaggiariSession {
+        Mock::given(method("GET")).and(path(pagina)).respond_with(ResponseTemplate::new(200)).expect(0).mount(server).await;
+        let mut docente = session(&server.uri());
+        docente.account_info = Some(AccountInfo {
+            cid: "D1234567X".to_string(),
+            cognome: "Verdi".to_string(),
+            id: 9,
+            nome: "Paola".to_string(),
+            account_type: AccountType::Teacher,
+        });
+        docente
+    }
+
+    #[tokio::test]
+    async fn test_get_voti_forbidden_for_teacher() {
+        let server = MockServer::start().await;
+        let docente = sessione_docente(&server, "/cvv/app/default/genitori_voti.php").await;
+
+        let err = docente.get_voti().await.unwrap_err();
+        assert!(matches!(err, SpaggiariError::Forbidden(_)), "{:?}", err);
+        assert!(docente.activity_log().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_voti_forbidden_for_restored_teacher_identity() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(200)).expect(0).mount(&server).await;
+        // Sessione ripristinata: nessuna informazione sull'account, solo l'identità
+        let mut docente = session(&server.uri());
+        docente.identity = "D1234567X".to_string();
+        docente.account_info = None;
+
+        let err = docente.get_voti().await.unwrap_err();
+        assert!(matches!(err, SpaggiariError::Forbidden(_)), "{:?}", err);
+    }
+
+    #[tokio::test]
+    async fn test_get_assenze_forbidden_for_teacher() {
+        let server = MockServer::start().await;
+        let docente = sessione_docente(&server, "/cvv/app/default/genitori_assenze.php").await;
+
+        let err = docente.get_assenze().await.unwrap_err();
+        assert!(matches!(err, SpaggiariError::Forbidden(_)), "{:?}", err);
+        assert_eq!(
+            err.to_string(),
+            "Operazione non consentita per questo account: Il registro delle assenze è disponibile solo per gli account studente e genitore"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_agenda_sends_dates() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/fml/app/default/agenda_studenti.php"))
+            .and(query_param("ope", "get_events"))
+            .and(query_param("start", "2024-10-14"))
+            .and(query_param("end", "2024-10-20"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"[{"title":"Compiti","start":"2024-10-15 08:00:00","nota_2":"Tema","materia_desc":"ITALIANO","tipo":"compito"}]"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let from = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 10, 20).unwrap();
+        let eventi = session(&server.uri()).get_agenda(from, to).await.unwrap();
+        assert_eq!(eventi.len(), 1);
+        assert_eq!(eventi[0].materia.as_deref(), Some("ITALIANO"));
+        assert_eq!(eventi[0].tipo, TipoEvento::Compito);
+    }
+
+    #[tokio::test]
+    async fn test_get_bacheca_range_sends_dates() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("action", "get_comunicazioni"))
+            .and(query_param("data_start", "2024-09-01"))
+            .and(query_param("data_stop", "2024-09-30"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":null}}"#, circolare_json("301", 7))))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let from = NaiveDate::from_ymd_opt(2024, 9, 1).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 9, 30).unwrap();
+        let bacheca = session(&server.uri()).get_bacheca_range(from, to).await.unwrap();
+        assert_eq!(bacheca.read[0].codice, 7);
+    }
+
+    #[tokio::test]
+    async fn test_list_titles_includes_read_and_new() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                r#"{{"read":[{},{}],"msg_new":[{}]}}"#,
+                circolare_json("301", 7),
+                circolare_json("302", 8),
+                circolare_json("303", 9)
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let titoli = session(&server.uri()).list_titles().await.unwrap();
+
+        assert_eq!(
+            titoli,
+            [("301", "Circolare 7"), ("302", "Circolare 8"), ("303", "Circolare 9")].map(|(id, titolo)| (id.to_string(), titolo.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_comunicazioni_yields_each_circolare() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("action", "get_comunicazioni"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":[{}]}}"#, circolare_json("301", 7), circolare_json("302", 8))))
+            .expect(1)
+            .mount(&server)
+            .await;
+        for (id, testo) in [("301", "Prima"), ("302", "Seconda")] {
+            Mock::given(method("GET"))
+                .and(path("/sif/app/default/bacheca_comunicazione.php"))
+                .and(query_param("com_id", id))
+                .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"<div class="comunicazione_testo">{}</div>"#, testo)))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let session = session(&server.uri());
+        let comunicazioni: Vec<Comunicazione> = session.stream_comunicazioni().try_collect().await.unwrap();
+
+        let testi: Vec<&str> = comunicazioni.iter().map(|c| c.testo.as_str()).collect();
+        assert_eq!(testi, ["Prima", "Seconda"]);
+    }
+
+    #[tokio::test]
+    async fn test_download_all_writes_each_circolare() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("action", "get_comunicazioni"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":[{}]}}"#, circolare_json("301", 7), circolare_json("302", 8))))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(query_param("com_id", "301"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Prima</div>"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(query_param("com_id", "302"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Seconda</div><a class="dwl_allegato" comunicazione_id="302" allegato_id="55" href="">orario.pdf</a>"#),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("action", "file_download"))
+            .and(query_param("com_id", "55"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"orario.pdf\"")
+                    .set_body_bytes(b"%PDF-1.4".to_vec()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let summary = session(&server.uri()).download_all(dir.path()).await.unwrap();
+
+        assert_eq!(summary, DownloadSummary { comunicazioni: 2, file: 3 });
+        assert_eq!(std::fs::read_to_string(dir.path().join("7/README.txt")).unwrap(), "Prima");
+        assert_eq!(std::fs::read_to_string(dir.path().join("8/README.txt")).unwrap(), "Seconda");
+        assert_eq!(std::fs::read(dir.path().join("8/orario.pdf")).unwrap(), b"%PDF-1.4");
+    }
+
+    #[tokio::test]
+    async fn test_allegato_metadata_reads_headers() {
+        let server = MockServer::start().await;
+        Mock::given(method("HEAD"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(query_param("action", "file_download"))
+            .and(query_param("com_id", "55"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"orario.pdf\"")
+                    .insert_header("Content-Type", "application/pdf")
+                    .insert_header("Content-Length", "123456"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let allegato = Allegato {
+            comunicazione_id: "302".to_string(),
+            allegato_id: "55".to_string(),
+            nome: None,
+            incorporato: None,
+        };
+        let session = session(&server.uri());
+        session.enable_activity_log();
+        let meta = session.allegato_metadata(&allegato).await.unwrap();
+        assert_eq!(session.activity_log()[0].endpoint, allegato.download_url(&server.uri()));
+        assert_eq!(
+            meta,
+            AllegatoMeta {
+                filename: "orario.pdf".to_string(),
+                content_length: Some(123456),
+                content_type: Some("application/pdf".to_string()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimate_download_size_sums_content_length() {
+        let server = MockServer::start().await;
+        for (id, size) in [("55", "123456"), ("56", "2048")] {
+            Mock::given(method("HEAD"))
+                .and(path("/sif/app/default/bacheca_personale.php"))
+                .and(query_param("com_id", id))
+                .respond_with(ResponseTemplate::new(200).insert_header("Content-Length", size))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        let allegati: Vec<Allegato> = ["55", "56"]
+            .into_iter()
+            .map(|id| Allegato {
+                comunicazione_id: "302".to_string(),
+                allegato_id: id.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+        let session = session(&server.uri());
+        session.enable_activity_log();
+        assert_eq!(session.estimate_download_size(&allegati).await.unwrap(), 123456 + 2048);
+        let endpoints: Vec<String> = session.activity_log().into_iter().map(|entry| entry.endpoint).collect();
+        assert_eq!(endpoints, allegati.iter().map(|allegato| allegato.download_url(&server.uri())).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_valid_with_relogs_on_expired_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/login_page.html")))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .and(body_string_contains("uid=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=nuovo; path=/").set_body_string(LOGIN_OK))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=nuovo; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .mount(&server)
+            .await;
+
+        let mut session = session(&server.uri());
+        assert!(session.ensure_valid_with("G1234567X", "password").await.unwrap());
+        assert_eq!(session.session_token, "nuovo");
+        assert_eq!(session.account_info().map(|a| a.id), Some(42));
+
+        // Con il nuovo token valido non serve un altro login
+        assert!(!session.ensure_valid_with("G1234567X", "password").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_valid_with_keeps_jar_cookies() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X; consenso=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/login_page.html")))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=nuovo; path=/").set_body_string(LOGIN_OK))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=nuovo; webidentity=G1234567X; consenso=1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Cookie impostato dal portale prima che la sessione scadesse
+        let (client, jar) = create_client_and_jar(&SpaggiariClientConfig::default()).unwrap();
+        jar.add_cookie_str("consenso=1; path=/", &server.uri().parse().unwrap());
+        let mut session = session(&server.uri());
+        session.client = client;
+        session.jar = Some(jar);
+
+        assert!(session.ensure_valid_with("G1234567X", "password").await.unwrap());
+        assert_eq!(session.session_token, "nuovo");
+        session.get_bacheca().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ensure_valid_with_rejects_other_account() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/login_page.html")))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=altro; path=/").set_body_string(r#"{"api":{"AuthSpa":{"version":"4.0"},"env":"test"},"data":{"auth":{"aMode":"pwd","accountInfo":{"cid":"X9999999Z","cognome":"Bianchi","id":7,"nome":"Luca","type":"G"},"actionRequested":false,"errCod":[],"errors":[],"hints":[],"loggedIn":true,"mMode":"","redirects":[],"verified":true},"pfolio":false},"error":[],"time":"2024-01-01T00:00:00"}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut session = session(&server.uri());
+        let err = session.ensure_valid_with("X9999999Z", "password").await.unwrap_err();
+
+        assert!(
+            matches!(&err, SpaggiariError::IdentityMismatch { expected, got } if expected == "G1234567X" && got == "X9999999Z"),
+            "{:?}",
+            err
+        );
+        assert_eq!((session.session_token.as_str(), session.identity.as_str()), ("sess", "G1234567X"));
+        assert!(session.account_info().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ensure_valid_with_does_not_relog_on_server_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(502))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let mut session = session(&server.uri());
+        session.ensure_valid_with("G1234567X", "password").await.unwrap_err();
+        assert_eq!(session.session_token, "sess");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_valid_with_keeps_student_identity() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=S7654321Y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("../tests/fixtures/login_page.html")))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Set-Cookie", "PHPSESSID=nuovo; path=/").set_body_string(LOGIN_OK))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .and(header("Cookie", "PHPSESSID=nuovo; webidentity=S7654321Y"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut genitore = session(&server.uri());
+        genitore.account_info = Some(AccountInfo {
+            cid: "G1234567X".to_string(),
+            cognome: "Rossi".to_string(),
+            id: 42,
+            nome: "Mario".to_string(),
+            account_type: AccountType::Parent,
+        });
+        let studente = StudentRef {
+            id: "S7654321Y".to_string(),
+            nome: "Anna".to_string(),
+            cognome: "Rossi".to_string(),
+        };
+        let mut figlio = genitore.for_student(&studente);
+
+        assert!(figlio.ensure_valid_with("G1234567X", "password").await.unwrap());
+        assert_eq!((figlio.session_token.as_str(), figlio.identity.as_str()), ("nuovo", "S7654321Y"));
+        figlio.get_bacheca().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazione_sends_identity() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Testo</div>"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let comunicazione = session(&server.uri()).get_comunicazione("101").await.unwrap();
+        assert_eq!(comunicazione.testo, "Testo");
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazione_by_codice_resolves_id() {
+        let server = MockServer::start().await;
+        let bacheca = format!(r#"{{"read":[{}],"msg_new":[{}]}}"#, circolare_json("301", 7), circolare_json("302", 8));
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(bacheca))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(query_param("com_id", "302"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Circolare numero 8</div>"#))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let comunicazione = session(&server.uri()).get_comunicazione_by_codice(8).await.unwrap();
+        assert_eq!(comunicazione.testo, "Circolare numero 8");
+
+        let result = session(&server.uri()).get_comunicazione_by_codice(99).await;
+        assert!(matches!(result, Err(SpaggiariError::ComunicazioneNotFound(codice)) if codice == "99"));
+    }
+
+    #[cfg(feature = "debug-api")]
+    #[tokio::test]
+    async fn test_get_comunicazione_debug_returns_raw_html() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<html><body><div class="comunicazione_testo">Testo di prova</div></body></html>"#))
+            .mount(&server)
+            .await;
+
+        let (html, comunicazione) = session(&server.uri()).get_comunicazione_debug("101").await.unwrap();
+
+        assert!(html.contains(r#"<div class="comunicazione_testo">"#));
+        assert_eq!(comunicazione.testo, "Testo di prova");
+    }
+
+    #[cfg(feature = "markdown")]
+    #[tokio::test]
+    async fn test_get_comunicazione_markdown() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .and(query_param("com_id", "101"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(r#"<div class="comunicazione_testo"><p>Leggere <b>attentamente</b> il <a href="https://scuola.example/regolamento">regolamento</a>.</p></div>"#),
+            )
+            .mount(&server)
+            .await;
+
+        let markdown = session(&server.uri()).get_comunicazione_markdown("101").await.unwrap();
+        assert_eq!(markdown, "Leggere **attentamente** il [regolamento](https://scuola.example/regolamento).");
+    }
+
+    #[cfg(feature = "zip")]
+    #[tokio::test]
+    async fn test_download_allegati_zip_entry_names() {
+        let server = MockServer::start().await;
+        for (id, filename) in [("1", "modulo.pdf"), ("2", "modulo.pdf"), ("3", "orario.pdf")] {
+            Mock::given(method("GET"))
+                .and(path("/sif/app/default/bacheca_personale.php"))
+                .and(query_param("com_id", id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", format!("attachment; filename=\"{}\"", filename).as_str())
+                        .set_body_bytes(format!("contenuto {}", id).into_bytes()),
+                )
+                .mount(&server)
+                .await;
+        }
+
+        let allegati: Vec<Allegato> = ["1", "2", "3"]
+            .into_iter()
+            .map(|id| Allegato {
+                comunicazione_id: "302".to_string(),
+                allegato_id: id.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+        let mut out = Vec::new();
+        session(&server.uri()).download_allegati_zip(&allegati, &mut out).await.unwrap();
+
+        let archivio = zip::ZipArchive::new(std::io::Cursor::new(out)).unwrap();
+        let mut nomi: Vec<&str> = archivio.file_names().collect();
+        nomi.sort_unstable();
+        assert_eq!(nomi, ["modulo.pdf", "modulo_2.pdf", "orario.pdf"]);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn test_wait_until_invalid_returns_after_expiry() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET")).respond_with(ResponseTemplate::new(401)).mount(&server).await;
+
+        session(&server.uri()).wait_until_invalid(std::time::Duration::from_secs(5)).await.unwrap();
+
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn test_wait_until_invalid_times_out() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .mount(&server)
+            .await;
+
+        let result = session(&server.uri()).wait_until_invalid(std::time::Duration::from_millis(400)).await;
+
+        assert!(matches!(result, Err(SpaggiariError::Generic(_))));
+    }
+
+    #[tokio::test]
+    async fn test_session_limit_caps_requests_in_flight() {
+        let (indirizzo, massimo) = crate::test_support::server_contatore(Duration::from_millis(100)).await;
+        let session = session(&indirizzo).with_max_concurrent_requests(2);
+        let studente = StudentRef {
+            id: "S7654321Y".to_string(),
+            nome: "Anna".to_string(),
+            cognome: "Rossi".to_string(),
+        };
+        let figlio = session.for_student(&studente);
+
+        let richieste = (0..5).map(|i| if i % 2 == 0 { session.get_bacheca() } else { figlio.get_bacheca() });
+        for bacheca in futures::future::join_all(richieste).await {
+            assert!(bacheca.unwrap().read.is_empty());
+        }
+
+        assert!((1..=2).contains(&massimo.load(std::sync::atomic::Ordering::SeqCst)), "{:?}", massimo);
+    }
+
+    #[tokio::test]
+    async fn test_download_shares_session_limit_with_bacheca() {
+        let (indirizzo, massimo) = crate::test_support::server_contatore(Duration::from_millis(100)).await;
+        let session = session(&indirizzo).with_max_concurrent_requests(1);
+        let url = format!("{}/sif/app/default/bacheca_personale.php?action=file_download&com_id=1", indirizzo);
+
+        let (download, bacheca) = tokio::join!(session.download_file_bytes(&url), session.get_bacheca());
+
+        assert_eq!(download.unwrap().1, crate::test_support::BACHECA_VUOTA.as_bytes());
+        assert!(bacheca.unwrap().read.is_empty());
+        assert_eq!(massimo.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_progress_download_with_more_files_than_limit() {
+        let (indirizzo, massimo) = crate::test_support::server_contatore(Duration::from_millis(50)).await;
+        let session = session(&indirizzo).with_max_concurrent_requests(1);
+        let allegati = (1..=3)
+            .map(|i| Allegato {
+                comunicazione_id: "100".to_string(),
+                allegato_id: i.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+
+        let file = tokio::time::timeout(Duration::from_secs(5), session.download_allegati_bytes_with_progress(allegati, |_, _| {}))
+            .await
+            .expect("download bloccato dal limite")
+            .unwrap();
+
+        assert_eq!(file.len(), 3);
+        assert_eq!(massimo.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_download_allegati_bytes_with_progress_sums_all_files() {
+        let server = MockServer::start().await;
+        let dimensioni = [("1", 1_500), ("2", 64_000), ("3", 250_000)];
+        // Una HEAD per il totale e una GET per il contenuto di ogni file
+        for (com_id, dimensione) in dimensioni {
+            for metodo in ["HEAD", "GET"] {
+                Mock::given(method(metodo))
+                    .and(query_param("action", "file_download"))
+                    .and(query_param("com_id", com_id))
+                    .respond_with(
+                        ResponseTemplate::new(200)
+                            .insert_header("Content-Disposition", format!("attachment; filename=\"allegato_{}.pdf\"", com_id).as_str())
+                            .set_body_bytes(vec![b'x'; dimensione]),
+                    )
+                    .expect(1)
+                    .mount(&server)
+                    .await;
+            }
+        }
+        let allegati = dimensioni
+            .iter()
+            .map(|(com_id, _)| Allegato {
+                comunicazione_id: "100".to_string(),
+                allegato_id: com_id.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+        let avanzamento = std::sync::Mutex::new(Vec::new());
+
+        let file = session(&server.uri())
+            .download_allegati_bytes_with_progress(allegati, |scaricati, totale| avanzamento.lock().unwrap().push((scaricati, totale)))
+            .await
+            .unwrap();
+
+        let somma: u64 = dimensioni.iter().map(|(_, dimensione)| *dimensione as u64).sum();
+        let avanzamento = avanzamento.into_inner().unwrap();
+        assert_eq!(file.iter().map(|(_, contenuto)| contenuto.len() as u64).sum::<u64>(), somma);
+        assert_eq!(avanzamento.first(), Some(&(0, Some(somma))));
+        assert_eq!(avanzamento.last(), Some(&(somma, Some(somma))));
+    }
+
+    #[tokio::test]
+    async fn test_download_retries_server_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(query_param("action", "file_download"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(query_param("action", "file_download"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Disposition", "attachment; filename=\"orario.pdf\"")
+                    .set_body_bytes(b"%PDF".to_vec()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = format!("{}/sif/app/default/bacheca_personale.php?action=file_download&com_id=1", server.uri());
+        let (filename, content) = session(&server.uri()).download_file_bytes(&url).await.unwrap();
+
+        assert_eq!((filename.as_str(), content.as_slice()), ("orario.pdf", b"%PDF".as_slice()));
+    }
+
+    #[tokio::test]
+    async fn test_activity_log_records_operations() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_personale.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"read":[],"msg_new":null}"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Testo</div>"#))
+            .mount(&server)
+            .await;
+
+        let session = session(&server.uri());
+        session.get_bacheca().await.unwrap();
+        assert!(session.activity_log().is_empty());
+
+        session.enable_activity_log();
+        session.get_bacheca().await.unwrap();
+        session.get_comunicazione("101").await.unwrap();
+
+        let log = session.activity_log();
+        let endpoints: Vec<&str> = log.iter().map(|e| e.endpoint.as_str()).collect();
+        assert_eq!(endpoints, ["/sif/app/default/bacheca_personale.php", "/sif/app/default/bacheca_comunicazione.php"]);
+        assert_eq!(log[0].bytes, 26);
+        assert!(log.iter().all(|e| e.errore.is_none() && e.status == Some(200)));
+
+        // Le operazioni fallite registrano lo status ricevuto insieme all'errore
+        session.get_voti().await.unwrap_err();
+        let voti = session.activity_log().pop().unwrap();
+        assert_eq!((voti.endpoint.as_str(), voti.status, voti.errore.is_some()), (PATH_VOTI, Some(404), true));
+    }
+
+    #[tokio::test]
+    async fn test_activity_log_records_each_downloaded_attachment() {
+        let server = MockServer::start().await;
+        for (com_id, dimensione) in [("1", 10), ("2", 20)] {
+            Mock::given(method("GET"))
+                .and(query_param("com_id", com_id))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header("Content-Disposition", format!("attachment; filename=\"allegato_{}.pdf\"", com_id).as_str())
+                        .set_body_bytes(vec![b'x'; dimensione]),
+                )
+                .mount(&server)
+                .await;
+        }
+        let allegati: Vec<Allegato> = ["1", "2"]
+            .into_iter()
+            .map(|com_id| Allegato {
+                comunicazione_id: "100".to_string(),
+                allegato_id: com_id.to_string(),
+                nome: None,
+                incorporato: None,
+            })
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(&server.uri());
+        session.enable_activity_log();
+
+        session.download_allegati(&allegati, &dir.path().to_string_lossy()).await.unwrap();
+        session.download_allegati_sorted(&allegati, &dir.path().join("ordinati").to_string_lossy()).await.unwrap();
+
+        let mut voci: Vec<(String, u64)> = session.activity_log().into_iter().map(|entry| (entry.endpoint, entry.bytes)).collect();
+        // I download in parallelo terminano in ordine sparso
+        voci[..2].sort();
+        let attese = [(allegati[0].download_url(&server.uri()), 10), (allegati[1].download_url(&server.uri()), 20)];
+        assert_eq!(voci, [attese.clone(), attese].concat());
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazione_with_consent_submits_and_refetches() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_consenso"><button>Acconsento</button></div>"#))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sif/app/default/bacheca_comunicazione.php"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Testo riservato</div>"#))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("action=conferma_consenso"))
+            .and(body_string_contains("com_id=101"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let session = session(&server.uri());
+        let result = session.get_comunicazione_with_consent("101", false).await;
+        assert!(matches!(result, Err(SpaggiariError::ConsensoRichiesto(id)) if id == "101"));
+
+        let comunicazione = session.get_comunicazione_with_consent("101", true).await.unwrap();
+        assert_eq!(comunicazione.testo, "Testo riservato");
+        let metodi: Vec<String> = server.received_requests().await.unwrap().iter().map(|r| r.method.to_string()).collect();
+        assert_eq!(metodi, ["GET", "GET", "POST", "GET"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_comunicazione_and_confirm_skips_when_not_required() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(r#"<div class="comunicazione_testo">Informativa</div>"#))
+            .mount(&server)
+            .await;
+
+        let (_, confermata) = session(&server.uri()).get_comunicazione_and_confirm(&circolare("102", 2, "Avvisi")).await.unwrap();
+
+        assert!(!confermata);
+        assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    }
 }
@@ -1,9 +1,15 @@
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::HeaderMap;
 use reqwest::Client;
 use serde::Deserialize;
-use tracing::{debug, error, info, warn};
+use tracing::{debug, trace, warn};
+use url::Url;
 
-use crate::bacheca_personale::get_backeca;
+use crate::bacheca_personale::{cookie_header, get_backeca_at, BASE_URL};
 use crate::error::SpaggiariError;
+use crate::http::Http;
+use crate::retry::RetryPolicy;
+use crate::utils::{parse_json_body, verifica_username};
 
 // Struct per deserializzare la risposta JSON del login
 #[derive(Debug, Deserialize)]
@@ -52,14 +58,58 @@ pub struct Auth {
     pub verified: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct AccountInfo {
     pub cid: String,
     pub cognome: String,
     pub id: i32,
     pub nome: String,
     #[serde(rename = "type")]
-    pub account_type: String, // "type" è una parola riservata in Rust, rinominata
+    pub account_type: AccountType, // "type" è una parola riservata in Rust, rinominata
+}
+
+/// Tipo di account, dal codice `type` restituito dal portale
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountType {
+    /// Studente (`S`)
+    Student,
+    /// Genitore (`G`)
+    Parent,
+    /// Docente (`D`)
+    Teacher,
+    /// Codice non riconosciuto, conservato così come restituito dal portale
+    Unknown(String),
+}
+
+impl AccountType {
+    /// Interpreta il codice del portale (senza distinzione di maiuscole)
+    pub fn from_code(code: &str) -> AccountType {
+        match code.trim().to_ascii_uppercase().as_str() {
+            "S" => AccountType::Student,
+            "G" => AccountType::Parent,
+            "D" => AccountType::Teacher,
+            _ => AccountType::Unknown(code.to_string()),
+        }
+    }
+
+    /// Il codice usato dal portale per questo tipo di account
+    pub fn code(&self) -> &str {
+        match self {
+            AccountType::Student => "S",
+            AccountType::Parent => "G",
+            AccountType::Teacher => "D",
+            AccountType::Unknown(code) => code,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(AccountType::from_code(&String::deserialize(deserializer)?))
+    }
 }
 
 /// Verifica se il token di sessione fornito è ancora valido.
@@ -79,25 +129,191 @@ pub struct AccountInfo {
 /// * `Ok(false)` se il token non è valido o è scaduto.
 /// * `Err(SpaggiariError)` se si verifica un errore durante la verifica.
 pub async fn test_session_token(client: &Client, session_id: &str, webidentity: &str) -> Result<bool, SpaggiariError> {
-    info!("🧪 Testando il token PHPSESSID: {}", session_id);
-    match get_backeca(client, session_id, webidentity).await {
+    test_session_token_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await
+}
+
+// Come `test_session_token`, usando l'indirizzo base indicato
+pub(crate) async fn test_session_token_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<bool, SpaggiariError> {
+    trace!(webidentity, "verifica del token di sessione");
+    match get_backeca_at(http, base_url, session_id, webidentity).await {
         Ok(bacheca) => {
-            let circolari_nuove = if let Some(ref msg_new) = bacheca.msg_new { msg_new.len() } else { 0 };
-            info!("✅ Token valido - Bacheca caricata con {} circolari lette e {} nuove", bacheca.read.len(), circolari_nuove);
+            debug!(circolari_lette = bacheca.read.len(), circolari_nuove = bacheca.unread_count(), "token valido, bacheca caricata");
             Ok(true)
         }
         Err(e) => {
-            warn!("❌ Token scaduto o non valido: {}", e);
+            debug!(errore = %e, "token scaduto o non valido");
             Ok(false)
         }
     }
 }
 
+pub(crate) const PATH_LOGOUT: &str = "/auth-p7/app/default/logout.php";
+
+/// Chiude la sessione sul portale, invalidando il `PHPSESSID`.
+///
+/// Il portale risponde al logout con un redirect alla pagina di accesso:
+/// sia le risposte 2xx che le 3xx sono considerate un logout riuscito.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `session_id` - L'ID di sessione (PHPSESSID) da invalidare.
+/// * `webidentity` - L'identità web associata alla sessione.
+///
+/// # Restituisce
+///
+/// * `Ok(())` se il portale ha accettato il logout.
+/// * `Err(SpaggiariError)` se il portale risponde con un errore o la richiesta non va a buon fine.
+pub async fn logout(client: &Client, session_id: &str, webidentity: &str) -> Result<(), SpaggiariError> {
+    logout_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await
+}
+
+// Come `logout`, usando l'indirizzo base indicato
+pub(crate) async fn logout_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<(), SpaggiariError> {
+    debug!(webidentity, "logout della sessione");
+    let response = http
+        .send_once(|client| client.post(format!("{}{}", base_url, PATH_LOGOUT)).header("Cookie", cookie_header(session_id, webidentity)))
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta logout ricevuta");
+
+    if status.is_success() || status.is_redirection() {
+        debug!("logout completato");
+        Ok(())
+    } else {
+        debug!(status = status.as_u16(), "logout non riuscito");
+        Err(SpaggiariError::ApiError {
+            message: format!("Logout non riuscito (status {})", status),
+        })
+    }
+}
+
+pub(crate) const PATH_ACCOUNTS: &str = "/auth-p7/app/default/AuthApi4.php?a=aAccounts";
+
+/// Studente collegato a un account genitore.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StudentRef {
+    /// Codice dello studente, usato come `webidentity` nelle richieste
+    #[serde(rename = "cid")]
+    pub id: String,
+    pub nome: String,
+    pub cognome: String,
+}
+
+// Risposta di AuthApi con gli account collegati alla sessione
+#[derive(Debug, Deserialize)]
+struct AccountsResponse {
+    data: AccountsData,
+    #[serde(default)]
+    error: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountsData {
+    accounts: Vec<AccountCollegato>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountCollegato {
+    #[serde(flatten)]
+    studente: StudentRef,
+    #[serde(rename = "type")]
+    account_type: AccountType,
+}
+
+/// Elenca gli studenti collegati all'account della sessione.
+///
+/// Gli account genitore possono avere più figli iscritti: gli account
+/// di tipo diverso da `AccountType::Student` vengono scartati.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `session_id` - L'ID di sessione (PHPSESSID).
+/// * `webidentity` - L'identità web associata alla sessione.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<StudentRef>)` con gli studenti collegati (vuoto se non ce ne sono).
+/// * `Err(SpaggiariError)` se la richiesta fallisce o la risposta non è interpretabile.
+pub async fn list_students(client: &Client, session_id: &str, webidentity: &str) -> Result<Vec<StudentRef>, SpaggiariError> {
+    list_students_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await
+}
+
+// Come `list_students`, usando l'indirizzo base indicato
+pub(crate) async fn list_students_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<Vec<StudentRef>, SpaggiariError> {
+    trace!("recupero degli studenti collegati all'account");
+    let response = http
+        .send(|client| client.get(format!("{}{}", base_url, PATH_ACCOUNTS)).header("Cookie", cookie_header(session_id, webidentity)))
+        .await?;
+    if let Err(e) = response.error_for_status_ref() {
+        return Err(e.into());
+    }
+    let text = response.text().await?;
+
+    let accounts: AccountsResponse = parse_json_body(&text)?;
+    if !accounts.error.is_empty() {
+        return Err(SpaggiariError::ApiError {
+            message: format!("Errori nella risposta: {:?}", accounts.error),
+        });
+    }
+
+    let studenti: Vec<StudentRef> = accounts.data.accounts.into_iter().filter(|a| a.account_type == AccountType::Student).map(|a| a.studente).collect();
+    debug!(studenti = studenti.len(), "studenti collegati caricati");
+    Ok(studenti)
+}
+
+/// Endpoint di autenticazione usato per il login.
+///
+/// I valori predefiniti corrispondono all'endpoint attuale del portale
+/// (`/auth-p7/app/default/AuthApi4.php?a=aLoginPwd`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoginConfig {
+    /// Percorso di AuthApi, relativo all'indirizzo del portale
+    pub auth_path: String,
+    /// Azione di autenticazione, inviata come parametro `a`
+    pub action: String,
+    /// Se `true` (default) il nome utente viene verificato prima di contattare il portale:
+    /// disattivare per le scuole che usano login numerici
+    pub validate_username: bool,
+}
+
+impl LoginConfig {
+    // URL completo dell'endpoint di login per il portale indicato
+    pub(crate) fn url(&self, base_url: &str) -> String {
+        format!("{}{}?a={}", base_url, self.auth_path, self.action)
+    }
+
+    // Rifiuta i nomi utente palesemente malformati, se la verifica è attiva
+    pub(crate) fn check_username(&self, username: &str) -> Result<(), SpaggiariError> {
+        if self.validate_username {
+            verifica_username(username)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for LoginConfig {
+    fn default() -> Self {
+        LoginConfig {
+            auth_path: "/auth-p7/app/default/AuthApi4.php".to_string(),
+            action: "aLoginPwd".to_string(),
+            validate_username: true,
+        }
+    }
+}
+
 /// Effettua il login al servizio Spaggiari e restituisce l'ID di sessione.
 ///
 /// Questa funzione invia le credenziali fornite all'endpoint di autenticazione.
+/// Un nome utente palesemente malformato (né codice fiscale, né codice utente, né email)
+/// viene rifiutato con `SpaggiariError::Generic` senza contattare il portale; per i login
+/// numerici usare `login_with_config` con `validate_username: false`.
 /// Se il login ha successo, estrae il `PHPSESSID` dai cookie della risposta e lo restituisce.
-/// Inoltre, salva il token in un file locale `phpsessid.token`.
+/// Il token non viene salvato su disco: è il chiamante a decidere dove conservarlo.
+///
+/// Gli errori di rete transitori vengono ritentati secondo `RetryPolicy::default()`.
 ///
 /// # Argomenti
 ///
@@ -110,15 +326,102 @@ pub async fn test_session_token(client: &Client, session_id: &str, webidentity:
 /// * `Ok(String)` contenente il `PHPSESSID` se il login ha successo.
 /// * `Err(SpaggiariError)` se il login fallisce o se si verifica un errore di rete/parsing.
 pub async fn login(client: &Client, username: &str, password: &str) -> Result<String, SpaggiariError> {
-    let login_action_url = "https://web.spaggiari.eu/auth-p7/app/default/AuthApi4.php?a=aLoginPwd";
+    login_with_retry(client, username, password, &RetryPolicy::default()).await
+}
+
+/// Effettua il login come `login`, usando la `RetryPolicy` indicata.
+///
+/// Vengono ritentati solo gli errori di connessione e le risposte 5xx:
+/// un rifiuto delle credenziali non viene mai ripetuto, per evitare il blocco dell'account.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `username` - Il nome utente per il login.
+/// * `password` - La password per il login.
+/// * `retry` - La politica di ripetizione della richiesta di login.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` contenente il `PHPSESSID` se il login ha successo.
+/// * `Err(SpaggiariError)` se il login fallisce o se si verifica un errore di rete/parsing.
+pub async fn login_with_retry(client: &Client, username: &str, password: &str, retry: &RetryPolicy) -> Result<String, SpaggiariError> {
+    login_with_config(client, username, password, &LoginConfig::default(), retry).await
+}
 
+/// Effettua il login come `login_with_retry`, usando l'endpoint di autenticazione indicato.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `username` - Il nome utente per il login.
+/// * `password` - La password per il login.
+/// * `config` - Il percorso di AuthApi e l'azione di autenticazione da usare.
+/// * `retry` - La politica di ripetizione della richiesta di login.
+///
+/// # Restituisce
+///
+/// * `Ok(String)` contenente il `PHPSESSID` se il login ha successo.
+/// * `Err(SpaggiariError)` se il login fallisce o se si verifica un errore di rete/parsing.
+pub async fn login_with_config(client: &Client, username: &str, password: &str, config: &LoginConfig, retry: &RetryPolicy) -> Result<String, SpaggiariError> {
+    config.check_username(username)?;
+    login_at(&Http::new(client.clone()), &config.url(BASE_URL), username, password, retry).await
+}
+
+/// Effettua il login come `login`, restituendo anche le informazioni sull'account.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `username` - Il nome utente per il login.
+/// * `password` - La password per il login.
+///
+/// # Restituisce
+///
+/// * `Ok((String, AccountInfo))` con il `PHPSESSID` e l'account restituito dal portale.
+/// * `Err(SpaggiariError)` se il login fallisce o se la risposta non contiene le informazioni sull'account.
+pub async fn login_full(client: &Client, username: &str, password: &str) -> Result<(String, AccountInfo), SpaggiariError> {
+    LoginConfig::default().check_username(username)?;
+    login_full_at(&Http::new(client.clone()), &LoginConfig::default().url(BASE_URL), username, password, &RetryPolicy::default()).await
+}
+
+// Come `login_full`, usando l'endpoint indicato
+pub(crate) async fn login_full_at(http: &Http, login_action_url: &str, username: &str, password: &str, retry: &RetryPolicy) -> Result<(String, AccountInfo), SpaggiariError> {
+    let (session_id, account_info) = login_response_at(http, login_action_url, username, password, retry).await?;
+    let account_info = account_info.ok_or_else(|| SpaggiariError::ParseError {
+        details: "La risposta di login non contiene le informazioni sull'account".to_string(),
+    })?;
+    Ok((session_id, account_info))
+}
+
+// Esegue il login contro l'endpoint indicato
+pub(crate) async fn login_at(http: &Http, login_action_url: &str, username: &str, password: &str, retry: &RetryPolicy) -> Result<String, SpaggiariError> {
+    Ok(login_response_at(http, login_action_url, username, password, retry).await?.0)
+}
+
+// Esegue il login restituendo il PHPSESSID e, se il payload è interpretabile, l'account
+pub(crate) async fn login_response_at(http: &Http, login_action_url: &str, username: &str, password: &str, retry: &RetryPolicy) -> Result<(String, Option<AccountInfo>), SpaggiariError> {
+    login_response_with_jar_at(http, None, login_action_url, username, password, retry).await
+}
+
+// Come `login_response_at`; se è disponibile il cookie jar del client, il PHPSESSID viene letto
+// da lì (comprende i cookie ricevuti durante i redirect) e gli header Set-Cookie della risposta
+// finale restano come ripiego
+pub(crate) async fn login_response_with_jar_at(
+    http: &Http,
+    jar: Option<&Jar>,
+    login_action_url: &str,
+    username: &str,
+    password: &str,
+    retry: &RetryPolicy,
+) -> Result<(String, Option<AccountInfo>), SpaggiariError> {
     // 1) Prepara i dati del form
-    info!("🔐 Preparazione dati login per utente: {}", username);
+    debug!(username, "login in corso");
     let form_data = vec![("uid", username), ("pwd", password)];
 
     // 2) Invia il form
-    info!("📤 Invio credenziali a {}...", login_action_url);
-    let res = client.post(login_action_url).form(&form_data).send().await?;
+    trace!(url = login_action_url, "invio delle credenziali");
+    let res = http.send_with(retry, |client| client.post(login_action_url).form(&form_data)).await?;
 
     let final_url = res.url().clone();
     let status = res.status();
@@ -126,21 +429,54 @@ pub async fn login(client: &Client, username: &str, password: &str) -> Result<St
     let response_text = res.text().await?;
 
     // 3) Analizza la risposta del login
-    info!("📥 Risposta ricevuta da: {}", final_url);
-    info!("📊 Status: {}", status);
+    debug!(url = %final_url, status = status.as_u16(), "risposta di login ricevuta");
 
+    let da_jar = jar.zip(Url::parse(login_action_url).ok()).and_then(|(jar, url)| phpsessid_da_jar(jar, &url));
+    interpreta_risposta_login(&headers, &response_text, da_jar)
+}
+
+// PHPSESSID memorizzato nel cookie jar per l'indirizzo indicato
+fn phpsessid_da_jar(jar: &Jar, url: &Url) -> Option<String> {
+    let cookies = jar.cookies(url)?;
+    let phpsessid = cookies.to_str().ok()?.split(';').map(str::trim).find_map(|cookie| cookie.strip_prefix("PHPSESSID="))?.to_string();
+    trace!("PHPSESSID letto dal cookie jar");
+    Some(phpsessid)
+}
+
+// Traduce i codici `errCod` di un login fallito nell'errore corrispondente.
+// I codici non riconosciuti vengono restituiti così come sono insieme ai messaggi `errors`
+fn errore_login(err_cod: Vec<String>, errors: Vec<String>) -> SpaggiariError {
+    if err_cod.is_empty() {
+        return SpaggiariError::AuthenticationFailed;
+    }
+
+    for codice in &err_cod {
+        match codice.trim().to_lowercase().as_str() {
+            "wrong_password" | "pwd_errata" | "bad_credentials" => return SpaggiariError::WrongPassword,
+            "account_locked" | "account_bloccato" => return SpaggiariError::AccountLocked,
+            "password_expired" | "pwd_scaduta" => return SpaggiariError::PasswordExpired,
+            _ => {}
+        }
+    }
+
+    SpaggiariError::LoginRejected { codes: err_cod, errors }
+}
+
+// Estrae PHPSESSID e account dalla risposta di login, senza effettuare richieste:
+// condivisa dall'API asincrona e da quella bloccante. `phpsessid_jar` è il valore già letto
+// dal cookie jar, se disponibile
+pub(crate) fn interpreta_risposta_login(headers: &HeaderMap, response_text: &str, phpsessid_jar: Option<String>) -> Result<(String, Option<AccountInfo>), SpaggiariError> {
     // 3.1) Estrai il PHPSESSID dai cookie della risposta di login
-    let mut phpsessid = None;
+    let mut phpsessid = phpsessid_jar;
 
-    // Cerca PHPSESSID negli header Set-Cookie della risposta di login
-    for (name, value) in &headers {
+    // In mancanza del cookie jar cerca PHPSESSID negli header Set-Cookie della risposta di login
+    for (name, value) in headers.iter().filter(|_| phpsessid.is_none()) {
         if name.as_str().to_lowercase() == "set-cookie" {
-            let cookie_str = value.to_str().unwrap_or("");
-            debug!("🍪 Set-Cookie: {}", cookie_str);
+            let cookie_str = value.to_str()?;
+            trace!(cookie = cookie_str.split('=').next().unwrap_or(""), "cookie ricevuto dal login");
 
-            if cookie_str.starts_with("PHPSESSID=") {
+            if let Some(value_part) = cookie_str.strip_prefix("PHPSESSID=") {
                 // Estrai il valore del PHPSESSID
-                let value_part = &cookie_str["PHPSESSID=".len()..];
                 if let Some(end_pos) = value_part.find(';') {
                     phpsessid = Some(value_part[..end_pos].to_string());
                 } else {
@@ -152,67 +488,419 @@ pub async fn login(client: &Client, username: &str, password: &str) -> Result<St
     }
 
     // 3.2) Analizza il payload JSON usando la struct
-    debug!("📄 Analisi del payload JSON...");
+    trace!("analisi del payload di login");
 
-    match serde_json::from_str::<LoginResponse>(&response_text) {
+    let mut errore_parsing = None;
+    let mut account_info = None;
+    match parse_json_body::<LoginResponse>(response_text) {
         Ok(login_resp) => {
-            info!("✅ Payload JSON deserializzato:");
-            info!("  - Ambiente: {}", login_resp.api.env);
-            info!("  - Versione AuthSpa: {}", login_resp.api.auth_spa.version);
-            info!("  - Logged in: {}", login_resp.data.auth.logged_in);
-            info!(
-                "  - Account: {} {} (ID: {}, Tipo: {})",
-                login_resp.data.auth.account_info.nome, login_resp.data.auth.account_info.cognome, login_resp.data.auth.account_info.id, login_resp.data.auth.account_info.account_type
+            debug!(
+                ambiente = %login_resp.api.env,
+                versione_auth_spa = %login_resp.api.auth_spa.version,
+                logged_in = login_resp.data.auth.logged_in,
+                account_id = login_resp.data.auth.account_info.id,
+                account_type = login_resp.data.auth.account_info.account_type.code(),
+                tempo = %login_resp.time,
+                "payload di login deserializzato"
             );
-            info!("  - Tempo: {}", login_resp.time);
+
+            // Il portale richiede una verifica aggiuntiva (es. codice via email)
+            if login_resp.data.auth.action_requested {
+                debug!(hints = ?login_resp.data.auth.hints, "il login richiede un'azione aggiuntiva");
+                return Err(SpaggiariError::ActionRequired { hints: login_resp.data.auth.hints });
+            }
 
             // Verifica se il login è riuscito
             if !login_resp.data.auth.logged_in {
-                error!("❌ Login fallito: logged_in = false");
-                return Err(SpaggiariError::AuthenticationFailed);
+                debug!(codici = ?login_resp.data.auth.err_cod, "login rifiutato dal portale");
+                return Err(errore_login(login_resp.data.auth.err_cod, login_resp.data.auth.errors));
             }
 
             // Controlla errori
             if !login_resp.error.is_empty() {
-                warn!("⚠️ Errori nella risposta: {:?}", login_resp.error);
+                debug!(errori = ?login_resp.error, "errori nella risposta di login");
                 return Err(SpaggiariError::ApiError {
                     message: format!("Errori nella risposta: {:?}", login_resp.error),
                 });
             }
+
+            account_info = Some(login_resp.data.auth.account_info);
         }
         Err(e) => {
-            error!("❌ Errore nel parsing JSON: {}", e);
-            debug!("📄 Primi 800 caratteri della risposta:");
-            debug!("{}", &response_text[..response_text.len().min(800)]);
+            debug!(errore = %e, "payload di login non interpretabile");
             // Procedi comunque se abbiamo il PHPSESSID
+            errore_parsing = Some(e);
         }
     }
 
     // 4) Restituisci il PHPSESSID se trovato
     match phpsessid {
         Some(session_id) => {
-            info!("✅ PHPSESSID estratto: {}", session_id);
+            trace!("PHPSESSID estratto dalla risposta di login");
+            if let Some(e) = &errore_parsing {
+                warn!(errore = %e, "payload di login non interpretabile, uso comunque il PHPSESSID ricevuto");
+            }
+            Ok((session_id, account_info))
+        }
+        None => {
+            // Solo i nomi degli header: i valori possono contenere cookie di sessione
+            debug!(header = ?headers.keys().map(|name| name.as_str()).collect::<Vec<_>>(), "PHPSESSID non trovato nei cookie della risposta di login");
+
+            // Se il payload non era interpretabile, l'errore riporta l'inizio della risposta
+            Err(errore_parsing.unwrap_or(SpaggiariError::AuthenticationFailed))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create_client;
+    use crate::test_support::LOGIN_OK;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_login_retries_after_dropped_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/auth-p7/app/default/AuthApi4.php?a=aLoginPwd", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            // Primo tentativo: la connessione viene chiusa senza risposta
+            let (socket, _) = listener.accept().await.unwrap();
+            drop(socket);
+
+            // Secondo tentativo: login riuscito
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nSet-Cookie: PHPSESSID=abc123; path=/\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                LOGIN_OK.len(),
+                LOGIN_OK
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = create_client().unwrap();
+        let policy = RetryPolicy::new(3, Duration::from_millis(10));
+        let token = login_at(&Http::new(client.clone()), &url, "G1234567X", "password", &policy).await.unwrap();
+
+        assert_eq!(token, "abc123");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_logout_accepts_redirect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            socket.write_all(b"HTTP/1.1 302 Found\r\nLocation: /\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+
+        let client = Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+        logout_at(&Http::new(client.clone()), &base_url, "abc123", "G1234567X").await.unwrap();
+
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /auth-p7/app/default/logout.php HTTP/1.1"), "{}", request);
+        assert!(request.to_lowercase().contains("cookie: phpsessid=abc123; webidentity=g1234567x"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn test_login_uses_configured_action() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: PHPSESSID=abc123\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                LOGIN_OK.len(),
+                LOGIN_OK
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+
+        let config = LoginConfig {
+            auth_path: "/auth-p8/AuthApi5.php".to_string(),
+            action: "aLoginSso".to_string(),
+            validate_username: true,
+        };
+        let client = create_client().unwrap();
+        let token = login_at(&Http::new(client.clone()), &config.url(&base_url), "G1234567X", "password", &RetryPolicy::disabled())
+            .await
+            .unwrap();
+
+        assert_eq!(token, "abc123");
+        let request = server.await.unwrap();
+        assert!(request.starts_with("POST /auth-p8/AuthApi5.php?a=aLoginSso HTTP/1.1"), "{}", request);
+        assert_eq!(
+            LoginConfig::default().url("https://web.spaggiari.eu"),
+            "https://web.spaggiari.eu/auth-p7/app/default/AuthApi4.php?a=aLoginPwd"
+        );
+    }
 
-            // Salva il token in un file per uso futuro
-            std::fs::write("phpsessid.token", &session_id)?;
-            info!("💾 Token salvato in phpsessid.token");
+    #[tokio::test]
+    async fn test_login_validates_username_before_request() {
+        // Codice fiscale malformato: errore senza contattare il portale
+        let result = login(&create_client().unwrap(), "RSSMRA80A01H50", "password").await;
+        assert!(matches!(result, Err(SpaggiariError::Generic(ref msg)) if msg.contains("RSSMRA80A01H50")));
 
-            Ok(session_id)
+        // Login numerico: rifiutato per default, accettato con la verifica disattivata
+        assert!(LoginConfig::default().check_username("123456").is_err());
+        let config = LoginConfig {
+            validate_username: false,
+            ..LoginConfig::default()
+        };
+        config.check_username("123456").unwrap();
+    }
+
+    #[test]
+    fn test_login_non_utf8_set_cookie_is_an_error() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", reqwest::header::HeaderValue::from_bytes(b"PHPSESSID=\xffabc; path=/").unwrap());
+
+        let result = interpreta_risposta_login(&headers, LOGIN_OK, None);
+        assert!(matches!(result, Err(SpaggiariError::HeaderToStr(_))));
+    }
+
+    #[test]
+    fn test_login_action_requested() {
+        let mut headers = HeaderMap::new();
+        headers.append("set-cookie", reqwest::header::HeaderValue::from_static("PHPSESSID=abc123; path=/"));
+
+        let result = interpreta_risposta_login(&headers, include_str!("../tests/fixtures/login_action_requested.json"), None);
+        match result {
+            Err(SpaggiariError::ActionRequired { hints }) => {
+                assert_eq!(hints, ["Conferma il codice ricevuto via email", "Aggiorna l'indirizzo email di recupero"]);
+            }
+            other => panic!("atteso ActionRequired, ottenuto {:?}", other),
         }
-        None => {
-            error!("❌ PHPSESSID non trovato nei cookie della risposta di login!");
-            warn!("🔍 Questo potrebbe significare che:");
-            warn!("  - Il login non è riuscito");
-            warn!("  - Il server non imposta PHPSESSID in questa risposta");
-            warn!("  - I cookie sono impostati in un header diverso");
-
-            // Mostra tutti gli header per debug
-            debug!("🔍 Tutti gli header della risposta:");
-            for (name, value) in &headers {
-                debug!("{}: {}", name, value.to_str().unwrap_or("[non-UTF8]"));
+    }
+
+    fn risposta_login_fallito(err_cod: &str) -> String {
+        format!(
+            r#"{{"api":{{"AuthSpa":{{"version":"4.0"}},"env":"prod"}},"data":{{"auth":{{"aMode":"pwd","accountInfo":{{"cid":"","cognome":"","id":0,"nome":"","type":"G"}},"actionRequested":false,"errCod":[{}],"errors":["Accesso non riuscito"],"hints":[],"loggedIn":false,"mMode":"","redirects":[],"verified":false}},"pfolio":false}},"error":[],"time":"2024-01-01T00:00:00"}}"#,
+            err_cod
+        )
+    }
+
+    #[test]
+    fn test_login_error_codes() {
+        let errore = |err_cod: &str| interpreta_risposta_login(&HeaderMap::new(), &risposta_login_fallito(err_cod), None).unwrap_err();
+
+        assert!(matches!(errore(r#""pwd_errata""#), SpaggiariError::WrongPassword));
+        assert!(matches!(errore(r#""WRONG_PASSWORD""#), SpaggiariError::WrongPassword));
+        assert!(matches!(errore(r#""account_bloccato""#), SpaggiariError::AccountLocked));
+        assert!(matches!(errore(r#""pwd_scaduta""#), SpaggiariError::PasswordExpired));
+        assert!(matches!(errore(""), SpaggiariError::AuthenticationFailed));
+        match errore(r#""captcha_richiesto""#) {
+            SpaggiariError::LoginRejected { codes, errors } => {
+                assert_eq!(codes, ["captcha_richiesto"]);
+                assert_eq!(errors, ["Accesso non riuscito"]);
             }
+            other => panic!("atteso LoginRejected, ottenuto {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_account_type_codes() {
+        let parse = |code: &str| serde_json::from_str::<AccountType>(&format!("\"{}\"", code)).unwrap();
+        assert_eq!(parse("S"), AccountType::Student);
+        assert_eq!(parse("G"), AccountType::Parent);
+        assert_eq!(parse("D"), AccountType::Teacher);
+        assert_eq!(parse("g"), AccountType::Parent);
+        assert_eq!(parse("X"), AccountType::Unknown("X".to_string()));
+        assert_eq!(parse("X").code(), "X");
+        assert_eq!(AccountType::Teacher.code(), "D");
+    }
+
+    #[tokio::test]
+    async fn test_login_full_returns_account_info() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: PHPSESSID=abc123\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                LOGIN_OK.len(),
+                LOGIN_OK
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = create_client().unwrap();
+        let (token, account) = login_full_at(&Http::new(client.clone()), &url, "G1234567X", "password", &RetryPolicy::disabled()).await.unwrap();
 
-            Err(SpaggiariError::AuthenticationFailed)
+        assert_eq!(token, "abc123");
+        assert_eq!(
+            account,
+            AccountInfo {
+                cid: "G1234567X".to_string(),
+                cognome: "Rossi".to_string(),
+                id: 42,
+                nome: "Mario".to_string(),
+                account_type: AccountType::Parent,
+            }
+        );
+    }
+
+    // Livello e campi (nome, valore) di un evento emesso dal crate
+    type Evento = (tracing::Level, Vec<(String, String)>);
+
+    // Registra gli eventi emessi dal crate durante il test
+    #[derive(Clone, Default)]
+    struct EventiCatturati(std::sync::Arc<std::sync::Mutex<Vec<Evento>>>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for EventiCatturati {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+            struct Campi(Vec<(String, String)>);
+            impl tracing::field::Visit for Campi {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                    self.0.push((field.name().to_string(), format!("{:?}", value)));
+                }
+            }
+            if !event.metadata().target().starts_with("spaggiari_rs") {
+                return;
+            }
+            let mut campi = Campi(Vec::new());
+            event.record(&mut campi);
+            self.0.lock().unwrap().push((*event.metadata().level(), campi.0));
         }
     }
+
+    #[tokio::test]
+    async fn test_login_logs_structured_debug_events() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nSet-Cookie: PHPSESSID=abc123\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                LOGIN_OK.len(),
+                LOGIN_OK
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let eventi = EventiCatturati::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(eventi.clone()));
+        let client = create_client().unwrap();
+        login_full_at(&Http::new(client.clone()), &url, "G1234567X", "password", &RetryPolicy::disabled()).await.unwrap();
+
+        let eventi = eventi.0.lock().unwrap();
+        assert!(!eventi.is_empty());
+        assert!(eventi.iter().all(|(livello, _)| *livello > tracing::Level::INFO));
+        let payload = eventi.iter().find(|(_, campi)| campi.iter().any(|(k, _)| k == "account_type")).expect("evento del payload");
+        assert!(payload.1.contains(&("account_id".to_string(), "42".to_string())));
+        // Né la password né il token di sessione finiscono nei log
+        assert!(eventi.iter().flat_map(|(_, campi)| campi).all(|(_, v)| !v.contains("abc123") && !v.contains("password")));
+    }
+
+    #[tokio::test]
+    async fn test_list_students_multiple_children() {
+        use wiremock::matchers::{header, method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let body = r#"{"data":{"accounts":[{"cid":"G1234567X","nome":"Mario","cognome":"Rossi","type":"G"},{"cid":"S1111111A","nome":"Luca","cognome":"Rossi","type":"S"},{"cid":"S2222222B","nome":"Anna","cognome":"Rossi","type":"S"}]},"error":[]}"#;
+        Mock::given(method("GET"))
+            .and(path("/auth-p7/app/default/AuthApi4.php"))
+            .and(query_param("a", "aAccounts"))
+            .and(header("Cookie", "PHPSESSID=sess; webidentity=G1234567X"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let studenti = list_students_at(&Http::new(create_client().unwrap()), &server.uri(), "sess", "G1234567X").await.unwrap();
+        assert_eq!(
+            studenti,
+            vec![
+                StudentRef {
+                    id: "S1111111A".to_string(),
+                    nome: "Luca".to_string(),
+                    cognome: "Rossi".to_string(),
+                },
+                StudentRef {
+                    id: "S2222222B".to_string(),
+                    nome: "Anna".to_string(),
+                    cognome: "Rossi".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_html_error_includes_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let body = "<html><body>Servizio temporaneamente non disponibile</body></html>";
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let client = create_client().unwrap();
+        let result = login_at(&Http::new(client.clone()), &url, "G1234567X", "password", &RetryPolicy::disabled()).await;
+
+        assert!(matches!(result, Err(SpaggiariError::ParseError { details }) if details.contains("Servizio temporaneamente non disponibile")));
+    }
+
+    #[tokio::test]
+    async fn test_login_response_with_bom() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let body = format!("\u{feff}\n{}", LOGIN_OK.replace(r#""loggedIn":true"#, r#""loggedIn":false"#));
+            let response = format!("HTTP/1.1 200 OK\r\nSet-Cookie: PHPSESSID=abc123\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        // Il payload viene interpretato nonostante il BOM, quindi loggedIn = false viene rilevato
+        let client = create_client().unwrap();
+        let result = login_at(&Http::new(client.clone()), &url, "G1234567X", "password", &RetryPolicy::disabled()).await;
+
+        assert!(matches!(result, Err(SpaggiariError::AuthenticationFailed)));
+    }
+
+    #[tokio::test]
+    async fn test_login_not_retried_without_policy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/login", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            drop(socket);
+        });
+
+        let client = create_client().unwrap();
+        let result = login_at(&Http::new(client.clone()), &url, "G1234567X", "password", &RetryPolicy::disabled()).await;
+
+        assert!(matches!(result, Err(SpaggiariError::HttpError(_))));
+    }
 }
@@ -1,13 +1,21 @@
-use clap::{Parser, Subcommand};
-use spaggiari_rs::{bacheca_personale::Circolare, create_client, test_session_token, SpaggiariError, SpaggiariSession};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use spaggiari_rs::{bacheca_personale::Circolare, Bacheca, Comunicazione, SpaggiariClientConfig, SpaggiariError, SpaggiariSession, DEFAULT_NAME_TEMPLATE};
 use std::env;
 use std::fs;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
 use tracing::{error, info};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// File in cui salvare e da cui leggere il token di sessione
+    #[arg(long, global = true, default_value = "phpsessid.token")]
+    token_file: PathBuf,
+    /// Indirizzo del portale (default https://web.spaggiari.eu), ad esempio un server di test
+    #[arg(long, global = true)]
+    base_url: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -24,7 +32,17 @@ enum Commands {
     /// Verifica se il token salvato è valido
     CheckToken,
     /// Scarica le comunicazioni dalla bacheca
-    Download,
+    Download {
+        /// Elabora le comunicazioni non lette prima di quelle lette
+        #[arg(long, default_value_t = true, action = ArgAction::Set)]
+        unread_first: bool,
+        /// Salva gli allegati di tutte le circolari nella stessa cartella
+        #[arg(long)]
+        flat: bool,
+        /// Modello del nome dei file con --flat ({codice}, {allegato_id}, {filename})
+        #[arg(long, default_value = DEFAULT_NAME_TEMPLATE, requires = "flat")]
+        name_template: String,
+    },
     /// Elenca i titoli delle circolari presenti in bacheca
     List,
     /// Mostra i dettagli di una specifica circolare
@@ -32,6 +50,9 @@ enum Commands {
         /// Il codice della circolare da visualizzare
         #[arg(short, long)]
         code: String,
+        /// Mostra il testo tramite il pager di sistema ($PAGER o less)
+        #[arg(long)]
+        pager: bool,
     },
     /// Scarica gli allegati di una specifica circolare
     DownloadCircolare {
@@ -39,6 +60,22 @@ enum Commands {
         #[arg(short, long)]
         code: String,
     },
+    /// Esporta l'intera bacheca in formato JSON o CSV
+    Export {
+        /// Formato di esportazione
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// File in cui scrivere l'esportazione (default: standard output)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Formato del comando Export
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Json,
+    Csv,
 }
 
 #[tokio::main]
@@ -46,21 +83,25 @@ async fn main() -> Result<(), SpaggiariError> {
     // Carica variabili d'ambiente dal file .env se presente
     dotenvy::dotenv().ok();
 
-    // Inizializza tracing
-    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).init();
+    // Inizializza tracing: i log vanno su stderr, così stdout resta utilizzabile da Export
+    tracing_subscriber::fmt().with_max_level(tracing::Level::INFO).with_writer(std::io::stderr).init();
 
     let cli = Cli::parse();
+    let config = SpaggiariClientConfig {
+        base_url: cli.base_url.clone(),
+        ..Default::default()
+    };
 
     match cli.command {
         Commands::Login { username, password } => {
             let (user, pass) = get_credentials(username, password)?;
             info!("🔐 Effettuo il login per utente: {}", user);
-            match SpaggiariSession::new(&user, &pass).await {
+            match SpaggiariSession::new_with_config(&user, &pass, &config).await {
                 Ok(session) => {
                     info!("✅ Login completato con successo!");
                     // Salva il token
-                    std::fs::write("phpsessid.token", &session.session_token)?;
-                    info!("💾 Token salvato in phpsessid.token");
+                    std::fs::write(&cli.token_file, &session.session_token)?;
+                    info!("💾 Token salvato in {}", cli.token_file.display());
                 }
                 Err(e) => {
                     error!("❌ Login fallito: {}", e);
@@ -70,28 +111,27 @@ async fn main() -> Result<(), SpaggiariError> {
         }
         Commands::CheckToken => {
             info!("🔍 Controllo validità del token salvato...");
-            if let Ok(existing_token) = std::fs::read_to_string("phpsessid.token") {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
                 let existing_token = existing_token.trim();
-                let username = env::var("SPAGGIARI_USERNAME").unwrap_or_else(|_| "G13070983V".to_string()); // Fallback o errore?
+                let username = env::var("SPAGGIARI_USERNAME")?;
 
-                let client = create_client()?;
-                match test_session_token(&client, existing_token, &username).await {
-                    Ok(true) => info!("✅ Il token è valido!"),
-                    Ok(false) => info!("❌ Il token è scaduto o non valido."),
+                match SpaggiariSession::from_token_with_config(existing_token.to_string(), username, &config).await {
+                    Ok(_) => info!("✅ Il token è valido!"),
+                    Err(SpaggiariError::InvalidSessionToken) => info!("❌ Il token è scaduto o non valido."),
                     Err(e) => error!("❌ Errore durante il controllo: {}", e),
                 }
             } else {
-                error!("❌ Nessun token trovato in phpsessid.token");
+                error!("❌ Nessun token trovato in {}", cli.token_file.display());
             }
         }
-        Commands::Download => {
+        Commands::Download { unread_first, flat, name_template } => {
             // Logica di download simile a prima
             // 1. Recupera token
-            if let Ok(existing_token) = std::fs::read_to_string("phpsessid.token") {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
                 let existing_token = existing_token.trim();
                 info!("📁 Token trovato. Avvio sessione...");
 
-                let session = SpaggiariSession::from_token(existing_token.to_string()).await?;
+                let session = apri_sessione(existing_token, &config).await?;
 
                 // Crea la cartella principale download
                 fs::create_dir_all("download")?;
@@ -101,62 +141,46 @@ async fn main() -> Result<(), SpaggiariError> {
                 let bacheca = session.get_bacheca().await?;
 
                 // Per ogni comunicazione in read e msg_new, elabora
-                process_comunicazioni(&session, &bacheca.read).await?;
-                if let Some(msg_new_vec) = &bacheca.msg_new {
-                    process_comunicazioni(&session, msg_new_vec).await?;
-                }
+                let template = flat.then_some(name_template.as_str());
+                process_comunicazioni(&session, &ordine_download(&bacheca, unread_first), template).await?;
                 info!("✅ Download completato.");
             } else {
                 error!("❌ Nessun token trovato. Esegui prima il login.");
             }
         }
         Commands::List => {
-            if let Ok(existing_token) = std::fs::read_to_string("phpsessid.token") {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
                 let existing_token = existing_token.trim();
                 info!("📁 Token trovato. Recupero lista circolari...");
 
-                let session = SpaggiariSession::from_token(existing_token.to_string()).await?;
+                let session = apri_sessione(existing_token, &config).await?;
                 let bacheca = session.get_bacheca().await?;
 
                 println!("📋 Elenco Circolari:");
                 println!("---------------------------------------------------");
 
-                if let Some(msg_new) = &bacheca.msg_new {
-                    for circolare in msg_new {
-                        println!("🆕 ID: {} - {}", circolare.id, circolare.titolo);
-                    }
+                for circolare in bacheca.unread() {
+                    println!("🆕 ID: {} - {}", circolare.id, circolare);
                 }
 
                 for circolare in &bacheca.read {
-                    println!("✅ ID: {} - {}", circolare.id, circolare.titolo);
+                    println!("✅ ID: {} - {}", circolare.id, circolare);
                 }
                 println!("---------------------------------------------------");
             } else {
                 error!("❌ Nessun token trovato. Esegui prima il login.");
             }
         }
-        Commands::Details { code } => {
-            if let Ok(existing_token) = std::fs::read_to_string("phpsessid.token") {
+        Commands::Details { code, pager } => {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
                 let existing_token = existing_token.trim();
                 info!("📁 Token trovato. Recupero dettagli circolare {}...", code);
 
-                let session = SpaggiariSession::from_token(existing_token.to_string()).await?;
+                let session = apri_sessione(existing_token, &config).await?;
 
                 match session.get_comunicazione(&code).await {
                     Ok(comunicazione) => {
-                        println!("📄 Dettagli Circolare:");
-                        println!("---------------------------------------------------");
-                        println!("Testo:\n{}", comunicazione.testo);
-                        println!("---------------------------------------------------");
-                        if !comunicazione.allegati.is_empty() {
-                            println!("📎 Allegati:");
-                            for allegato in comunicazione.allegati {
-                                println!("  - ID Allegato: {}", allegato.allegato_id);
-                            }
-                        } else {
-                            println!("📎 Nessun allegato.");
-                        }
-                        println!("---------------------------------------------------");
+                        show_text(&render_details(&comunicazione), pager, &mut std::io::stdout())?;
                     }
                     Err(e) => {
                         error!("❌ Errore nel recupero della comunicazione: {}", e);
@@ -167,11 +191,11 @@ async fn main() -> Result<(), SpaggiariError> {
             }
         }
         Commands::DownloadCircolare { code } => {
-            if let Ok(existing_token) = std::fs::read_to_string("phpsessid.token") {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
                 let existing_token = existing_token.trim();
                 info!("📁 Token trovato. Scarico circolare {}...", code);
 
-                let session = SpaggiariSession::from_token(existing_token.to_string()).await?;
+                let session = apri_sessione(existing_token, &config).await?;
 
                 match session.get_comunicazione(&code).await {
                     Ok(comunicazione) => {
@@ -198,11 +222,49 @@ async fn main() -> Result<(), SpaggiariError> {
                 error!("❌ Nessun token trovato. Esegui prima il login.");
             }
         }
+        Commands::Export { format, output } => {
+            if let Ok(existing_token) = std::fs::read_to_string(&cli.token_file) {
+                let existing_token = existing_token.trim();
+                info!("📁 Token trovato. Esporto la bacheca...");
+
+                let session = apri_sessione(existing_token, &config).await?;
+                let bacheca = session.get_bacheca().await?;
+
+                match output {
+                    Some(path) => {
+                        esporta_bacheca(&bacheca, format, std::io::BufWriter::new(fs::File::create(&path)?))?;
+                        info!("💾 Bacheca esportata in {}", path.display());
+                    }
+                    None => esporta_bacheca(&bacheca, format, std::io::stdout().lock())?,
+                }
+            } else {
+                error!("❌ Nessun token trovato. Esegui prima il login.");
+            }
+        }
     }
 
     Ok(())
 }
 
+// Ripristina la sessione dal token salvato, per l'utente indicato da SPAGGIARI_USERNAME
+async fn apri_sessione(token: &str, config: &SpaggiariClientConfig) -> Result<SpaggiariSession, SpaggiariError> {
+    let username = env::var("SPAGGIARI_USERNAME")?;
+    SpaggiariSession::from_token_with_config(token.to_string(), username, config).await
+}
+
+// Scrive la bacheca nel formato richiesto dal comando Export
+fn esporta_bacheca(bacheca: &Bacheca, format: ExportFormat, mut out: impl Write) -> Result<(), SpaggiariError> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, bacheca)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => bacheca.to_csv(&mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
+
 fn get_credentials(cli_user: Option<String>, cli_pass: Option<String>) -> Result<(String, String), SpaggiariError> {
     let username = cli_user.or_else(|| env::var("SPAGGIARI_USERNAME").ok());
     let password = cli_pass.or_else(|| env::var("SPAGGIARI_PASSWORD").ok());
@@ -213,14 +275,74 @@ fn get_credentials(cli_user: Option<String>, cli_pass: Option<String>) -> Result
     }
 }
 
-// Nuova funzione per elaborare le comunicazioni usando la sessione
-async fn process_comunicazioni(session: &SpaggiariSession, circolari: &[Circolare]) -> Result<(), SpaggiariError> {
+// Compone il testo mostrato dal comando Details
+fn render_details(comunicazione: &Comunicazione) -> String {
+    let mut out = String::new();
+    out.push_str("📄 Dettagli Circolare:\n");
+    out.push_str("---------------------------------------------------\n");
+    out.push_str(&format!("Testo:\n{}\n", comunicazione.testo));
+    out.push_str("---------------------------------------------------\n");
+    if !comunicazione.allegati.is_empty() {
+        out.push_str("📎 Allegati:\n");
+        for allegato in &comunicazione.allegati {
+            out.push_str(&format!("  - ID Allegato: {}\n", allegato.allegato_id));
+        }
+    } else {
+        out.push_str("📎 Nessun allegato.\n");
+    }
+    out.push_str("---------------------------------------------------\n");
+    out
+}
+
+// Mostra il testo tramite il pager di sistema se richiesto e disponibile,
+// altrimenti lo scrive direttamente su `out`
+fn show_text(text: &str, pager: bool, out: &mut impl Write) -> std::io::Result<()> {
+    if pager && std::io::stdout().is_terminal() {
+        let pager_cmd = env::var("PAGER").unwrap_or_else(|_| "less".to_string());
+        let mut parts = pager_cmd.split_whitespace();
+        if let Some(program) = parts.next() {
+            if let Ok(mut child) = Command::new(program).args(parts).stdin(Stdio::piped()).spawn() {
+                if let Some(mut stdin) = child.stdin.take() {
+                    // Il pager può essere chiuso prima di aver letto tutto il testo
+                    let _ = stdin.write_all(text.as_bytes());
+                }
+                child.wait()?;
+                return Ok(());
+            }
+        }
+    }
+    out.write_all(text.as_bytes())
+}
+
+// Ordine in cui scaricare le circolari: le non lette (msg_new) per prime se richiesto
+fn ordine_download(bacheca: &Bacheca, unread_first: bool) -> Vec<&Circolare> {
+    let nuove = bacheca.unread().iter();
+    if unread_first {
+        nuove.chain(bacheca.read.iter()).collect()
+    } else {
+        bacheca.read.iter().chain(nuove).collect()
+    }
+}
+
+// Nuova funzione per elaborare le comunicazioni usando la sessione.
+// Con `flat_template` tutti i file finiscono direttamente in download/, nominati secondo il modello
+async fn process_comunicazioni(session: &SpaggiariSession, circolari: &[&Circolare], flat_template: Option<&str>) -> Result<(), SpaggiariError> {
     for circolare in circolari {
         info!("📄 Elaborando comunicazione: {} (Codice: {})", circolare.id, circolare.codice);
 
         // Ottieni la comunicazione
         let comunicazione = session.get_comunicazione(&circolare.id).await?;
 
+        if let Some(template) = flat_template {
+            let readme_path = format!("download/{}_README.txt", circolare.codice);
+            fs::write(&readme_path, comunicazione.testo.as_bytes())?;
+            info!("📝 README creato: {}", readme_path);
+
+            session.download_allegati_flat(circolare.codice, &comunicazione.allegati, "download", template).await?;
+            info!("📂 Allegati scaricati in: download");
+            continue;
+        }
+
         // Crea sottocartella con codice
         let subfolder = format!("download/{}", circolare.codice);
         fs::create_dir_all(&subfolder)?;
@@ -231,9 +353,115 @@ async fn process_comunicazioni(session: &SpaggiariSession, circolari: &[Circolar
         readme_file.write_all(comunicazione.testo.as_bytes())?;
         info!("📝 README creato: {}", readme_path);
 
-        // Scarica gli allegati nella sottocartella
-        session.download_allegati(&comunicazione.allegati, &subfolder).await?;
-        info!("📂 Allegati scaricati in: {}", subfolder);
+        // Scarica gli allegati nella sottocartella, saltando quelli già scaricati in precedenza
+        let esiti = session.download_allegati_skip_existing(&comunicazione.allegati, &subfolder).await?;
+        let saltati = esiti.iter().filter(|esito| esito.is_skipped()).count();
+        info!("📂 Allegati scaricati in: {} ({} già presenti)", subfolder, saltati);
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spaggiari_rs::Allegato;
+
+    #[test]
+    fn test_details_pager_flag_parsed() {
+        let cli = Cli::try_parse_from(["spaggiari-cli", "details", "--code", "123", "--pager"]).unwrap();
+        assert!(matches!(cli.command, Commands::Details { pager: true, .. }));
+
+        let cli = Cli::try_parse_from(["spaggiari-cli", "details", "--code", "123"]).unwrap();
+        assert!(matches!(cli.command, Commands::Details { pager: false, .. }));
+    }
+
+    fn circolare(id: &str) -> Circolare {
+        serde_json::from_value(serde_json::json!({
+            "id": id, "codice": "1", "titolo": "Titolo", "testo": "", "data_start": "2024-09-12", "data_stop": "2024-09-30",
+            "tipo_com": "C", "tipo_com_filtro": "GEN", "tipo_com_desc": "Circolari", "nome_file": null, "richieste": null,
+            "id_relazione": "", "conf_lettura": "", "flag_risp": "", "testo_risp": null, "file_risp": null,
+            "flag_accettazione": null, "modificato": "", "evento_data": ""
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_download_unread_first() {
+        let bacheca = Bacheca {
+            read: vec![circolare("letta1"), circolare("letta2")],
+            msg_new: Some(vec![circolare("nuova")]),
+            extra: Default::default(),
+        };
+
+        let ids = |circolari: Vec<&Circolare>| circolari.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        assert_eq!(ids(ordine_download(&bacheca, true)), ["nuova", "letta1", "letta2"]);
+        assert_eq!(ids(ordine_download(&bacheca, false)), ["letta1", "letta2", "nuova"]);
+    }
+
+    #[test]
+    fn test_download_unread_first_flag() {
+        let cli = Cli::try_parse_from(["spaggiari-cli", "download"]).unwrap();
+        assert!(matches!(cli.command, Commands::Download { unread_first: true, .. }));
+
+        let cli = Cli::try_parse_from(["spaggiari-cli", "download", "--unread-first", "false"]).unwrap();
+        assert!(matches!(cli.command, Commands::Download { unread_first: false, .. }));
+    }
+
+    #[test]
+    fn test_export_flags() {
+        let cli = Cli::try_parse_from(["spaggiari-cli", "export"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Commands::Export {
+                format: ExportFormat::Json,
+                output: None
+            }
+        ));
+
+        let cli = Cli::try_parse_from(["spaggiari-cli", "export", "--format", "csv", "--output", "bacheca.csv"]).unwrap();
+        assert!(matches!(cli.command, Commands::Export { format: ExportFormat::Csv, output: Some(ref path) } if path == &PathBuf::from("bacheca.csv")));
+
+        assert!(Cli::try_parse_from(["spaggiari-cli", "export", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_token_file_flag() {
+        let cli = Cli::try_parse_from(["spaggiari-cli", "list"]).unwrap();
+        assert_eq!(cli.token_file, PathBuf::from("phpsessid.token"));
+
+        let cli = Cli::try_parse_from(["spaggiari-cli", "list", "--token-file", "mario.token"]).unwrap();
+        assert_eq!(cli.token_file, PathBuf::from("mario.token"));
+    }
+
+    #[test]
+    fn test_download_flat_flags() {
+        let cli = Cli::try_parse_from(["spaggiari-cli", "download", "--flat"]).unwrap();
+        assert!(matches!(cli.command, Commands::Download { flat: true, ref name_template, .. } if name_template == DEFAULT_NAME_TEMPLATE));
+
+        let cli = Cli::try_parse_from(["spaggiari-cli", "download", "--flat", "--name-template", "{codice}-{filename}"]).unwrap();
+        assert!(matches!(cli.command, Commands::Download { flat: true, ref name_template, .. } if name_template == "{codice}-{filename}"));
+
+        assert!(Cli::try_parse_from(["spaggiari-cli", "download", "--name-template", "{filename}"]).is_err());
+    }
+
+    #[test]
+    fn test_details_without_pager_prints_full_text() {
+        let testo = (1..=200).map(|i| format!("Riga {}", i)).collect::<Vec<_>>().join("\n");
+        let comunicazione = Comunicazione {
+            testo: testo.clone(),
+            allegati: vec![Allegato {
+                comunicazione_id: "1".to_string(),
+                allegato_id: "99".to_string(),
+                nome: None,
+                incorporato: None,
+            }],
+        };
+
+        let mut out = Vec::new();
+        show_text(&render_details(&comunicazione), false, &mut out).unwrap();
+        let printed = String::from_utf8(out).unwrap();
+
+        assert!(printed.contains(&testo));
+        assert!(printed.contains("ID Allegato: 99"));
+    }
+}
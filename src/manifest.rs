@@ -0,0 +1,136 @@
+use crate::dedup::content_hash;
+use crate::error::SpaggiariError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Allegato registrato nel manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Identificativo dell'allegato sul portale
+    pub allegato_id: String,
+    /// Nome del file salvato
+    pub filename: String,
+    /// Dimensione in byte
+    pub size: u64,
+    /// Hash SHA-256 del contenuto in formato esadecimale
+    pub hash: String,
+}
+
+impl ManifestEntry {
+    /// Crea la voce di un allegato a partire dal suo contenuto
+    pub fn new(allegato_id: &str, filename: &str, content: &[u8]) -> Self {
+        ManifestEntry {
+            allegato_id: allegato_id.to_string(),
+            filename: filename.to_string(),
+            size: content.len() as u64,
+            hash: content_hash(content),
+        }
+    }
+}
+
+/// Elenco degli allegati scaricati, salvabile in JSON per confrontare due sincronizzazioni
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Allegati indicizzati per `allegato_id`
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl Manifest {
+    /// Crea un manifest vuoto
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aggiunge un allegato, sostituendo quello con lo stesso `allegato_id`
+    pub fn insert(&mut self, entry: ManifestEntry) {
+        self.entries.insert(entry.allegato_id.clone(), entry);
+    }
+
+    /// Legge un manifest dal suo JSON
+    pub fn from_json(json: &str) -> Result<Self, SpaggiariError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Serializza il manifest in JSON
+    pub fn to_json(&self) -> Result<String, SpaggiariError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+/// Differenze tra due manifest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    /// Allegati presenti solo nel nuovo manifest
+    pub added: Vec<ManifestEntry>,
+    /// Allegati presenti solo nel vecchio manifest
+    pub removed: Vec<ManifestEntry>,
+    /// Allegati presenti in entrambi con nome, dimensione o hash diversi (nella versione nuova)
+    pub changed: Vec<ManifestEntry>,
+}
+
+impl ManifestDiff {
+    /// `true` se i due manifest sono equivalenti
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Confronta due manifest, riportando gli allegati aggiunti, rimossi e modificati.
+///
+/// Gli allegati sono abbinati per `allegato_id`; uno è modificato se cambiano nome del file,
+/// dimensione o hash. Uno strumento di sincronizzazione può così scaricare solo `added` e `changed`.
+///
+/// # Argomenti
+///
+/// * `old` - Il manifest della sincronizzazione precedente.
+/// * `new` - Il manifest attuale.
+///
+/// # Restituisce
+///
+/// * `ManifestDiff` con le differenze, ordinate per `allegato_id`.
+pub fn diff_manifest(old: &Manifest, new: &Manifest) -> ManifestDiff {
+    let mut diff = ManifestDiff::default();
+    for (id, entry) in &new.entries {
+        match old.entries.get(id) {
+            None => diff.added.push(entry.clone()),
+            Some(precedente) if precedente != entry => diff.changed.push(entry.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = old.entries.iter().filter(|(id, _)| !new.entries.contains_key(*id)).map(|(_, entry)| entry.clone()).collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_manifest_reports_each_category() {
+        let mut old = Manifest::new();
+        old.insert(ManifestEntry::new("1", "invariato.pdf", b"uguale"));
+        old.insert(ManifestEntry::new("2", "rimosso.pdf", b"vecchio"));
+        old.insert(ManifestEntry::new("3", "contenuto.pdf", b"prima"));
+        old.insert(ManifestEntry::new("4", "nome.pdf", b"stesso"));
+
+        let mut new = Manifest::new();
+        new.insert(ManifestEntry::new("1", "invariato.pdf", b"uguale"));
+        new.insert(ManifestEntry::new("3", "contenuto.pdf", b"dopo!"));
+        new.insert(ManifestEntry::new("4", "rinominato.pdf", b"stesso"));
+        new.insert(ManifestEntry::new("5", "aggiunto.pdf", b"nuovo"));
+
+        let diff = diff_manifest(&old, &new);
+        assert_eq!(diff.added, [ManifestEntry::new("5", "aggiunto.pdf", b"nuovo")]);
+        assert_eq!(diff.removed, [ManifestEntry::new("2", "rimosso.pdf", b"vecchio")]);
+        assert_eq!(diff.changed, [ManifestEntry::new("3", "contenuto.pdf", b"dopo!"), ManifestEntry::new("4", "rinominato.pdf", b"stesso")]);
+        assert!(diff_manifest(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_manifest_json_round_trip() {
+        let mut manifest = Manifest::new();
+        manifest.insert(ManifestEntry::new("9", "orario.pdf", b"%PDF-1.4"));
+
+        assert_eq!(Manifest::from_json(&manifest.to_json().unwrap()).unwrap(), manifest);
+    }
+}
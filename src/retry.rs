@@ -0,0 +1,70 @@
+use reqwest::{RequestBuilder, Response};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::error::SpaggiariError;
+
+/// Politica di ripetizione delle richieste in caso di errori di rete transitori.
+///
+/// Vengono ripetute solo le richieste fallite per problemi di connessione/timeout
+/// o che hanno ricevuto una risposta 5xx. Una risposta valida del server (ad esempio
+/// credenziali rifiutate) non viene mai ripetuta, per evitare blocchi dell'account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Numero massimo di tentativi (incluso il primo)
+    pub max_attempts: u32,
+    /// Attesa prima del secondo tentativo, moltiplicata per il numero di tentativi già fatti
+    pub backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// Crea una politica con il numero di tentativi e l'attesa indicati
+    pub fn new(max_attempts: u32, backoff: Duration) -> Self {
+        RetryPolicy { max_attempts, backoff }
+    }
+
+    /// Politica che effettua un solo tentativo
+    pub fn disabled() -> Self {
+        RetryPolicy::new(1, Duration::ZERO)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3, Duration::from_millis(500))
+    }
+}
+
+// Errori di rete per cui ha senso ritentare la richiesta
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout() || e.is_request()
+}
+
+/// Invia la richiesta costruita da `build`, ripetendola secondo la `RetryPolicy`.
+///
+/// `build` viene richiamata a ogni tentativo perché un `RequestBuilder` non è riutilizzabile.
+pub(crate) async fn send_with_retry<F>(policy: &RetryPolicy, build: F) -> Result<Response, SpaggiariError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 1;
+    loop {
+        match build().send().await {
+            Ok(response) if response.status().is_server_error() && attempt < policy.max_attempts => {
+                warn!(
+                    tentativo = attempt,
+                    tentativi = policy.max_attempts,
+                    status = response.status().as_u16(),
+                    "richiesta fallita, nuovo tentativo"
+                );
+            }
+            Ok(response) => return Ok(response),
+            Err(e) if is_transient(&e) && attempt < policy.max_attempts => {
+                warn!(tentativo = attempt, tentativi = policy.max_attempts, errore = %e, "richiesta fallita, nuovo tentativo");
+            }
+            Err(e) => return Err(e.into()),
+        }
+        tokio::time::sleep(policy.backoff * attempt).await;
+        attempt += 1;
+    }
+}
@@ -0,0 +1,90 @@
+//! Dati di esempio condivisi dai test del crate
+
+use crate::bacheca_personale::Circolare;
+use crate::SpaggiariSession;
+use reqwest::Client;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// Risposta di login riuscito per il genitore G1234567X
+pub(crate) const LOGIN_OK: &str = r#"{"api":{"AuthSpa":{"version":"4.0"},"env":"prod"},"data":{"auth":{"aMode":"pwd","accountInfo":{"cid":"G1234567X","cognome":"Rossi","id":42,"nome":"Mario","type":"G"},"actionRequested":false,"errCod":[],"errors":[],"hints":[],"loggedIn":true,"mMode":"","redirects":[],"verified":true},"pfolio":false},"error":[],"time":"2024-01-01T00:00:00"}"#;
+
+pub(crate) fn circolare(id: &str, codice: i32, tipo_com_desc: &str) -> Circolare {
+    Circolare {
+        id: id.to_string(),
+        codice,
+        titolo: format!("Circolare {}", codice),
+        testo: String::new(),
+        data_start: "2024-09-12".to_string(),
+        data_stop: "2024-09-30".to_string(),
+        tipo_com: "C".to_string(),
+        tipo_com_filtro: "GEN".to_string(),
+        tipo_com_desc: tipo_com_desc.to_string(),
+        nome_file: None,
+        richieste: None,
+        id_relazione: String::new(),
+        conf_lettura: String::new(),
+        flag_risp: String::new(),
+        testo_risp: None,
+        file_risp: None,
+        flag_accettazione: None,
+        modificato: String::new(),
+        evento_data: String::new(),
+    }
+}
+
+// JSON di una circolare come restituito dalla bacheca
+pub(crate) fn circolare_json(id: &str, codice: i32) -> String {
+    format!(
+        r#"{{"id":"{id}","codice":"{codice}","titolo":"Circolare {codice}","testo":"","data_start":"2024-09-12","data_stop":"2024-09-30","tipo_com":"C","tipo_com_filtro":"GEN","tipo_com_desc":"Circolari","nome_file":null,"richieste":null,"id_relazione":"","conf_lettura":"","flag_risp":"","testo_risp":null,"file_risp":null,"flag_accettazione":null,"modificato":"","evento_data":""}}"#
+    )
+}
+
+// Sessione che punta all'indirizzo indicato (tipicamente un MockServer)
+pub(crate) fn session(base_url: &str) -> SpaggiariSession {
+    SpaggiariSession {
+        client: Client::new(),
+        session_token: "sess".to_string(),
+        identity: "G1234567X".to_string(),
+        base_url: base_url.to_string(),
+        activity: Default::default(),
+        token_store: Arc::new(crate::NoopTokenStore),
+        account_info: None,
+        created_at: chrono::Utc::now(),
+        limite: None,
+        jar: None,
+    }
+}
+
+// Bacheca senza circolari, restituita da `server_contatore`
+pub(crate) const BACHECA_VUOTA: &str = r#"{"read":[],"msg_new":null}"#;
+
+// Server che risponde a ogni richiesta con `BACHECA_VUOTA` dopo `attesa`, contando le richieste
+// in corso: restituisce l'indirizzo e il massimo di richieste contemporanee osservato
+pub(crate) async fn server_contatore(attesa: Duration) -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let indirizzo = format!("http://{}", listener.local_addr().unwrap());
+    let in_corso = Arc::new(AtomicUsize::new(0));
+    let massimo = Arc::new(AtomicUsize::new(0));
+    let risultato = massimo.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let (in_corso, massimo) = (in_corso.clone(), massimo.clone());
+            tokio::spawn(async move {
+                let mut buf = [0u8; 4096];
+                let _ = socket.read(&mut buf).await;
+                let ora = in_corso.fetch_add(1, Ordering::SeqCst) + 1;
+                massimo.fetch_max(ora, Ordering::SeqCst);
+                tokio::time::sleep(attesa).await;
+                in_corso.fetch_sub(1, Ordering::SeqCst);
+                let risposta = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", BACHECA_VUOTA.len(), BACHECA_VUOTA);
+                let _ = socket.write_all(risposta.as_bytes()).await;
+            });
+        }
+    });
+    (indirizzo, risultato)
+}
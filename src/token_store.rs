@@ -0,0 +1,118 @@
+//! Salvataggio del token di sessione
+
+use crate::error::SpaggiariError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Archivio in cui la sessione salva e da cui rilegge il token (`PHPSESSID`).
+///
+/// Implementando questo trait il token può essere conservato dove serve
+/// (file, Redis, database), ad esempio nei server che gestiscono più utenti.
+pub trait TokenStore: Send + Sync {
+    /// Legge il token salvato, `None` se non è presente
+    fn load(&self) -> Result<Option<String>, SpaggiariError>;
+
+    /// Salva il token, sostituendo quello precedente
+    fn save(&self, token: &str) -> Result<(), SpaggiariError>;
+}
+
+/// Sessione salvata con i dati necessari a ripristinarla, serializzabile in JSON.
+///
+/// A differenza del solo token conserva anche l'utente a cui appartiene e il momento del login.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistedSession {
+    /// Token di sessione (`PHPSESSID`)
+    pub token: String,
+    /// Codice fiscale dell'utente che ha effettuato il login
+    pub identity: String,
+    /// Momento in cui la sessione è stata creata
+    pub created_at: DateTime<Utc>,
+}
+
+/// Archivio che non salva nulla: è quello usato di default dalla sessione
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopTokenStore;
+
+impl TokenStore for NoopTokenStore {
+    fn load(&self) -> Result<Option<String>, SpaggiariError> {
+        Ok(None)
+    }
+
+    fn save(&self, _token: &str) -> Result<(), SpaggiariError> {
+        Ok(())
+    }
+}
+
+/// Archivio che salva il token in un file di testo
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    /// Crea un archivio che usa il file indicato (ad esempio `phpsessid.token`)
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTokenStore { path: path.into() }
+    }
+
+    /// Il percorso del file del token
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TokenStore for FileTokenStore {
+    fn load(&self) -> Result<Option<String>, SpaggiariError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(token) => {
+                let token = token.trim();
+                Ok((!token.is_empty()).then(|| token.to_string()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, token: &str) -> Result<(), SpaggiariError> {
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, token)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_token_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = FileTokenStore::new(dir.path().join("utenti").join("mario.token"));
+
+        assert_eq!(store.load().unwrap(), None);
+        store.save("abc123").unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("abc123"));
+        store.save("def456").unwrap();
+        assert_eq!(store.load().unwrap().as_deref(), Some("def456"));
+    }
+
+    #[test]
+    fn test_file_token_store_trims_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("phpsessid.token");
+        std::fs::write(&path, "abc123\n").unwrap();
+        assert_eq!(FileTokenStore::new(&path).load().unwrap().as_deref(), Some("abc123"));
+
+        std::fs::write(&path, "  \n").unwrap();
+        assert_eq!(FileTokenStore::new(&path).load().unwrap(), None);
+    }
+
+    #[test]
+    fn test_noop_token_store() {
+        NoopTokenStore.save("abc123").unwrap();
+        assert_eq!(NoopTokenStore.load().unwrap(), None);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::error::SpaggiariError;
+use scraper::{ElementRef, Html, Selector};
+use serde::de::DeserializeOwned;
+
+/// Formatta una dimensione in byte in forma leggibile (`512 B`, `340 KB`, `1.2 MB`, `3.4 GB`).
+///
+/// Le unità sono in base 1024.
+///
+/// # Example
+///
+/// ```
+/// use spaggiari_rs::format_bytes;
+///
+/// assert_eq!(format_bytes(1536 * 1024), "1.5 MB");
+/// ```
+pub fn format_bytes(n: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    const GB: u64 = MB * 1024;
+
+    // Il confronto avviene sul valore arrotondato, così 1048575 byte diventano `1.0 MB` e non `1024 KB`
+    let kb = (n as f64 / KB as f64).round();
+    let mb = (n as f64 / MB as f64 * 10.0).round() / 10.0;
+    if n < KB {
+        format!("{} B", n)
+    } else if kb < 1024.0 {
+        format!("{} KB", kb as u64)
+    } else if mb < 1024.0 {
+        format!("{:.1} MB", mb)
+    } else {
+        format!("{:.1} GB", n as f64 / GB as f64)
+    }
+}
+
+// Elementi della pagina di accesso che il portale mostra al posto del JSON quando la sessione è scaduta
+const MARKER_LOGIN: &[&str] = &["aloginpwd", "name=\"pwd\"", "id=\"login-form\""];
+
+// Testo delle pagine di manutenzione programmata del portale
+const MARKER_MANUTENZIONE: &[&str] = &["in manutenzione"];
+
+// Riconosce le pagine HTML restituite al posto di una risposta JSON: pagina di accesso
+// (sessione scaduta), sessione aperta altrove o manutenzione. `None` se il body non è HTML
+// o se contiene comunque un JSON da interpretare
+pub(crate) fn classifica_pagina_html(text: &str) -> Option<SpaggiariError> {
+    let body = clean_json_body(text);
+    if !body.starts_with('<') || extract_embedded_json(body).is_some() {
+        return None;
+    }
+
+    let lower = body.to_lowercase();
+    if sessione_sostituita(&lower) {
+        Some(SpaggiariError::SessionSuperseded)
+    } else if pagina_di_accesso(&lower) {
+        Some(SpaggiariError::InvalidSessionToken)
+    } else if MARKER_MANUTENZIONE.iter().any(|marker| lower.contains(marker)) {
+        Some(SpaggiariError::ApiError {
+            message: format!("Portale in manutenzione (risposta: {})", body_snippet(body)),
+        })
+    } else if lower.starts_with("<!doctype") {
+        // Una pagina completa senza JSON è la pagina di accesso in una variante non riconosciuta
+        Some(SpaggiariError::InvalidSessionToken)
+    } else {
+        None
+    }
+}
+
+// `true` se il body contiene il form della pagina di accesso del portale (sessione scaduta)
+pub(crate) fn pagina_di_accesso(text: &str) -> bool {
+    let lower = text.to_lowercase();
+    MARKER_LOGIN.iter().any(|marker| lower.contains(&marker.to_lowercase()))
+}
+
+// Numero massimo di caratteri del body riportati negli errori di parsing
+const MAX_BODY_SNIPPET: usize = 200;
+
+// Primi caratteri del body, da includere nei messaggi di errore
+pub(crate) fn body_snippet(body: &str) -> String {
+    let body = body.trim();
+    let mut snippet: String = body.chars().take(MAX_BODY_SNIPPET).collect();
+    if snippet.len() < body.len() {
+        snippet.push('…');
+    }
+    snippet
+}
+
+// `ParseError` con il messaggio di errore e l'inizio del body che non è stato possibile interpretare
+pub(crate) fn parse_error_with_body(messaggio: impl std::fmt::Display, body: &str) -> SpaggiariError {
+    SpaggiariError::ParseError {
+        details: format!("{} (risposta: {})", messaggio, body_snippet(body)),
+    }
+}
+
+// Rimuove un eventuale BOM UTF-8 e gli spazi iniziali/finali da un body JSON
+pub(crate) fn clean_json_body(text: &str) -> &str {
+    text.trim_start_matches('\u{feff}').trim()
+}
+
+// Testi con cui il portale segnala che la sessione è stata aperta su un altro dispositivo
+const MARKER_SESSIONE_SOSTITUITA: &[&str] = &["sessione aperta altrove", "sessione è stata aperta su un altro dispositivo"];
+
+// Verifica se la risposta è la pagina di sessione invalidata da un altro accesso
+pub(crate) fn sessione_sostituita(text: &str) -> bool {
+    let text = text.to_lowercase();
+    MARKER_SESSIONE_SOSTITUITA.iter().any(|marker| text.contains(marker))
+}
+
+/// Interpreta un body JSON, tollerando BOM iniziale e JSON racchiuso in una pagina HTML.
+///
+/// In alcune condizioni di errore il portale restituisce il JSON dentro un `<pre>`
+/// (o nel `<body>`) con le entità HTML escapate: in questo caso il JSON viene estratto
+/// e interpretato. Se il body non è interpretabile restituisce `ParseError` con il messaggio
+/// di serde e l'inizio del body, oppure `SessionSuperseded` se la pagina segnala un accesso
+/// da un altro dispositivo.
+pub(crate) fn parse_json_body<T: DeserializeOwned>(text: &str) -> Result<T, SpaggiariError> {
+    let body = clean_json_body(text);
+    match serde_json::from_str::<T>(body) {
+        Ok(value) => Ok(value),
+        Err(_) if body.starts_with('<') && sessione_sostituita(body) => Err(SpaggiariError::SessionSuperseded),
+        Err(e) if body.starts_with('<') => {
+            let embedded = extract_embedded_json(body).ok_or_else(|| parse_error_with_body(format!("Risposta HTML senza JSON: {}", e), body))?;
+            serde_json::from_str::<T>(&embedded).map_err(|e| parse_error_with_body(format!("JSON non valido all'interno della risposta HTML: {}", e), body))
+        }
+        Err(e) => Err(parse_error_with_body(format!("JSON non valido: {}", e), body)),
+    }
+}
+
+// Compila un selettore CSS, convertendo l'errore in `SpaggiariError`
+pub(crate) fn selettore(css: &str) -> Result<Selector, SpaggiariError> {
+    Selector::parse(css).map_err(|e| SpaggiariError::ParseError {
+        details: format!("Errore nel parsing del selettore: {}", e),
+    })
+}
+
+// Testo di un elemento con gli spazi normalizzati
+pub(crate) fn testo_elemento(element: ElementRef) -> String {
+    element.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Estrae il testo (con le entità HTML già decodificate) di un `<pre>` o del `<body>`
+// se sembra un documento JSON
+fn extract_embedded_json(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    ["pre", "body"].iter().find_map(|tag| {
+        let selector = Selector::parse(tag).ok()?;
+        let testo = document.select(&selector).next()?.text().collect::<String>();
+        let testo = testo.trim();
+        (testo.starts_with('{') || testo.starts_with('[')).then(|| testo.to_string())
+    })
+}
+
+/// `true` se `valore` ha il formato di un codice fiscale italiano (16 caratteri, maiuscole o minuscole).
+///
+/// Controlla solo la struttura (lettere, cifre e cifre sostituite da omocodia), non il carattere di controllo.
+///
+/// # Example
+///
+/// ```
+/// use spaggiari_rs::utils::codice_fiscale_valido;
+///
+/// assert!(codice_fiscale_valido("RSSMRA80A01H501U"));
+/// assert!(!codice_fiscale_valido("RSSMRA80A01H50"));
+/// ```
+pub fn codice_fiscale_valido(valore: &str) -> bool {
+    let cifra = |c: u8| c.is_ascii_digit() || b"LMNPQRSTUV".contains(&c);
+    let b = valore.as_bytes();
+    b.len() == 16
+        && b.iter().all(u8::is_ascii_alphanumeric)
+        && valore.to_ascii_uppercase().bytes().enumerate().all(|(i, c)| match i {
+            0..=5 | 8 | 11 | 15 => c.is_ascii_uppercase(),
+            _ => cifra(c),
+        })
+}
+
+// Controlla il nome utente prima del login: sono accettati il codice fiscale, il codice
+// utente del portale (es. `G1234567X`) e l'indirizzo email
+pub(crate) fn verifica_username(username: &str) -> Result<(), SpaggiariError> {
+    let username = username.trim();
+    let codice_utente = username.len() >= 8
+        && username.as_bytes()[0].is_ascii_alphabetic()
+        && username[1..].trim_end_matches(|c: char| c.is_ascii_alphabetic()).bytes().all(|c| c.is_ascii_digit())
+        && username[1..].bytes().any(|c| c.is_ascii_digit());
+    if codice_fiscale_valido(username) || codice_utente || username.contains('@') {
+        return Ok(());
+    }
+    Err(SpaggiariError::Generic(format!(
+        "Nome utente '{}' non valido: atteso un codice fiscale di 16 caratteri, un codice utente o un'email \
+         (per i login numerici disattiva la verifica del nome utente)",
+        username
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verifica_username() {
+        // Codici fiscali, anche minuscoli o con omocodia
+        assert!(verifica_username("RSSMRA80A01H501U").is_ok());
+        assert!(verifica_username("rssmra80a01h501u").is_ok());
+        assert!(verifica_username("RSSMRA80A01H5LMU").is_ok());
+        // Codice utente del portale ed email
+        assert!(verifica_username("G1234567X").is_ok());
+        assert!(verifica_username("mario.rossi@example.com").is_ok());
+
+        // Codici fiscali malformati
+        for username in ["RSSMRA80A01H50", "RSSMRA80A01H501UX", "RSSMRA8XA01H501U", "RSS MRA80A01H501", ""] {
+            assert!(matches!(verifica_username(username), Err(SpaggiariError::Generic(_))), "{}", username);
+        }
+        // I login numerici richiedono di disattivare la verifica
+        assert!(verifica_username("123456").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes_boundaries() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1 KB");
+        assert_eq!(format_bytes(340 * 1024), "340 KB");
+        assert_eq!(format_bytes(1048063), "1023 KB");
+        assert_eq!(format_bytes(1048064), "1.0 MB");
+        assert_eq!(format_bytes(1048575), "1.0 MB");
+        assert_eq!(format_bytes(1048576), "1.0 MB");
+        assert_eq!(format_bytes(1258291), "1.2 MB");
+        assert_eq!(format_bytes(1073741823), "1.0 GB");
+        assert_eq!(format_bytes(1073741824), "1.0 GB");
+    }
+
+    #[test]
+    fn test_parse_json_wrapped_in_html() {
+        let html = "<html><head><title>Errore</title></head><body><pre>{&quot;read&quot;:[],&quot;msg_new&quot;:null}</pre></body></html>";
+
+        let value: serde_json::Value = parse_json_body(html).unwrap();
+        assert_eq!(value["read"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_parse_json_html_without_json() {
+        let result = parse_json_body::<serde_json::Value>("<!DOCTYPE html><html><body><h1>Manutenzione</h1></body></html>");
+        assert!(matches!(result, Err(SpaggiariError::ParseError { .. })));
+    }
+
+    #[test]
+    fn test_parse_json_session_superseded() {
+        let html = "<html><body><div class=\"errore\">Sessione aperta altrove: effettua nuovamente l'accesso</div></body></html>";
+
+        let result = parse_json_body::<serde_json::Value>(html);
+        assert!(matches!(result, Err(SpaggiariError::SessionSuperseded)));
+    }
+
+    #[test]
+    fn test_parse_json_plain_error_includes_body() {
+        let result = parse_json_body::<serde_json::Value>("{non json");
+        assert!(matches!(result, Err(SpaggiariError::ParseError { details }) if details.contains("key must be a string") && details.contains("{non json")));
+    }
+
+    #[test]
+    fn test_classifica_pagina_html() {
+        assert!(matches!(
+            classifica_pagina_html(r#"<form action="AuthApi4.php?a=aLoginPwd">"#),
+            Some(SpaggiariError::InvalidSessionToken)
+        ));
+        assert!(matches!(
+            classifica_pagina_html("<!DOCTYPE html><html><body>Benvenuto</body></html>"),
+            Some(SpaggiariError::InvalidSessionToken)
+        ));
+        assert!(matches!(
+            classifica_pagina_html("<html><body>Sito in manutenzione</body></html>"),
+            Some(SpaggiariError::ApiError { .. })
+        ));
+        assert!(classifica_pagina_html("<!DOCTYPE html><html><body><pre>{&quot;read&quot;:[]}</pre></body></html>").is_none());
+        // Il JSON incorporato ha la precedenza sui marker, anche se la pagina contiene un form di accesso
+        assert!(classifica_pagina_html(r#"<html><body><form id="login-form"></form><pre>{&quot;read&quot;:[]}</pre></body></html>"#).is_none());
+        assert!(classifica_pagina_html("<html><body><h1>Manutenzione programmata</h1></body></html>").is_none());
+        assert!(classifica_pagina_html(r#"{"read":[]}"#).is_none());
+    }
+
+    #[test]
+    fn test_body_snippet_truncates() {
+        let lungo = "à".repeat(500);
+        let snippet = body_snippet(&lungo);
+        assert_eq!(snippet.chars().count(), MAX_BODY_SNIPPET + 1);
+        assert!(snippet.ends_with('…'));
+        assert_eq!(body_snippet("  breve \n"), "breve");
+    }
+
+    #[test]
+    fn test_clean_json_body() {
+        assert_eq!(clean_json_body("\u{feff}\n  {\"a\":1}\r\n"), "{\"a\":1}");
+        assert_eq!(clean_json_body("{}"), "{}");
+    }
+}
@@ -0,0 +1,131 @@
+//! Voti dello studente, letti dalla pagina dei voti del portale
+
+use crate::bacheca_personale::{cookie_header, BASE_URL};
+use crate::error::SpaggiariError;
+use crate::http::Http;
+use crate::utils::{classifica_pagina_html, selettore, testo_elemento};
+use reqwest::Client;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, trace};
+
+pub(crate) const PATH_VOTI: &str = "/cvv/app/default/genitori_voti.php";
+
+/// Voto registrato per una materia
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Voto {
+    pub materia: String,
+    /// Valore come mostrato dal portale (es. `7½`, `6-`, `+`)
+    pub valore: String,
+    /// Data del voto come riportata dal portale (es. `12/10/2024`)
+    pub data: String,
+    /// Tipo di prova (es. `Scritto/Grafico`, `Orale`, `Pratico`), vuoto se non indicato
+    pub tipo: String,
+}
+
+/// Estrae i voti dalla pagina dei voti del portale.
+///
+/// Ogni riga `tr.riga_materia_componente` contiene il nome della materia e una cella
+/// `td.cella_voto` per ciascun voto; le celle senza valore vengono ignorate.
+///
+/// # Argomenti
+///
+/// * `html` - Il contenuto HTML della pagina dei voti.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<Voto>)` con i voti nell'ordine della pagina.
+/// * `Err(SpaggiariError::InvalidSessionToken)` se il portale ha risposto con la pagina di accesso.
+/// * `Err(SpaggiariError::ParseError)` se la pagina non contiene la tabella dei voti.
+pub fn parse_voti(html: &str) -> Result<Vec<Voto>, SpaggiariError> {
+    let document = Html::parse_document(html);
+    let riga = selettore("tr.riga_materia_componente")?;
+    let materia = selettore("td.materia_padre")?;
+    let cella = selettore("td.cella_voto")?;
+    let valore = selettore("p.s_reg_testo")?;
+    let data = selettore("span.voto_data")?;
+
+    let righe: Vec<_> = document.select(&riga).collect();
+    if righe.is_empty() {
+        // Pagina di accesso (sessione scaduta) o di manutenzione al posto dei voti
+        if let Some(errore) = classifica_pagina_html(html) {
+            return Err(errore);
+        }
+        return Err(SpaggiariError::ParseError {
+            details: "Tabella dei voti non trovata nella pagina".to_string(),
+        });
+    }
+
+    let mut voti = Vec::new();
+    for riga in righe {
+        let Some(nome_materia) = riga.select(&materia).next().map(testo_elemento) else {
+            continue;
+        };
+        for cella in riga.select(&cella) {
+            let Some(valore) = cella.select(&valore).next().map(testo_elemento).filter(|v| !v.is_empty()) else {
+                continue;
+            };
+            voti.push(Voto {
+                materia: nome_materia.clone(),
+                valore,
+                data: cella.select(&data).next().map(testo_elemento).unwrap_or_default(),
+                tipo: cella.value().attr("title").unwrap_or("").trim().to_string(),
+            });
+        }
+    }
+
+    Ok(voti)
+}
+
+/// Ottiene i voti dello studente.
+///
+/// # Argomenti
+///
+/// * `client` - Il client HTTP da utilizzare per la richiesta.
+/// * `session_id` - L'ID di sessione (PHPSESSID).
+/// * `webidentity` - L'identità web associata alla sessione.
+///
+/// # Restituisce
+///
+/// * `Ok(Vec<Voto>)` con i voti presenti nella pagina.
+/// * `Err(SpaggiariError)` se la richiesta fallisce o la pagina non è interpretabile.
+pub async fn get_voti(client: &Client, session_id: &str, webidentity: &str) -> Result<Vec<Voto>, SpaggiariError> {
+    let html = fetch_voti_at(&Http::new(client.clone()), BASE_URL, session_id, webidentity).await?;
+    parse_voti(&html)
+}
+
+// Scarica la pagina HTML grezza dei voti
+pub(crate) async fn fetch_voti_at(http: &Http, base_url: &str, session_id: &str, webidentity: &str) -> Result<String, SpaggiariError> {
+    let response = http
+        .send(|client| client.get(format!("{}{}", base_url, PATH_VOTI)).header("Cookie", cookie_header(session_id, webidentity)))
+        .await?;
+
+    let status = response.status();
+    trace!(status = status.as_u16(), "risposta voti ricevuta");
+
+    if status.is_success() {
+        Ok(response.text().await?)
+    } else {
+        debug!(status = status.as_u16(), "token non valido o scaduto");
+        Err(SpaggiariError::ParseError {
+            details: "Il token non sembra funzionare".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_voti_rejects_unrelated_page() {
+        let result = parse_voti("<html><body><form id=\"login\"></form></body></html>");
+        assert!(matches!(result, Err(SpaggiariError::ParseError { .. })));
+    }
+
+    #[test]
+    fn test_parse_voti_login_page_is_invalid_token() {
+        let result = parse_voti(include_str!("../tests/fixtures/login_page.html"));
+        assert!(matches!(result, Err(SpaggiariError::InvalidSessionToken)), "{:?}", result);
+    }
+}
@@ -0,0 +1,80 @@
+//! Test del comando `download` della CLI, eseguito contro un portale simulato.
+
+use std::process::{Command, Output};
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn circolare(id: &str, codice: &str) -> String {
+    format!(
+        r#"{{"id":"{}","codice":"{}","titolo":"Circolare {}","testo":"","data_start":"2024-09-12","data_stop":"2024-09-30","tipo_com":"C","tipo_com_filtro":"GEN","tipo_com_desc":"Circolari","nome_file":null,"richieste":null,"id_relazione":"","conf_lettura":"","flag_risp":"","testo_risp":null,"file_risp":null,"flag_accettazione":null,"modificato":"","evento_data":""}}"#,
+        id, codice, codice
+    )
+}
+
+// Portale con una circolare letta (301, codice 7) e una nuova (302, codice 8), entrambe senza allegati
+async fn portale() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sif/app/default/bacheca_personale.php"))
+        .and(query_param("action", "get_comunicazioni"))
+        .and(header("Cookie", "PHPSESSID=tok; webidentity=G1234567X"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":[{}]}}"#, circolare("301", "7"), circolare("302", "8"))))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/sif/app/default/bacheca_comunicazione.php"))
+        .and(query_param("action", "risposta_com"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(include_str!("fixtures/comunicazione_senza_allegati.html")))
+        .mount(&server)
+        .await;
+    server
+}
+
+// Esegue la CLI in una cartella temporanea con il token `tok` già salvato
+fn esegui_cli(server: &MockServer, dir: &std::path::Path, args: &[&str]) -> Output {
+    std::fs::write(dir.join("phpsessid.token"), "tok").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_spaggiari-cli"))
+        .current_dir(dir)
+        .env("SPAGGIARI_USERNAME", "G1234567X")
+        .args(["--base-url", &server.uri()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+// Comunicazioni aperte dalla CLI, nell'ordine in cui sono state richieste: ogni cartella viene
+// creata subito dopo aver letto la sua comunicazione
+async fn comunicazioni_richieste(server: &MockServer) -> Vec<String> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.url.path() == "/sif/app/default/bacheca_comunicazione.php")
+        .filter_map(|request| request.url.query_pairs().find(|(chiave, _)| chiave == "com_id").map(|(_, valore)| valore.into_owned()))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_download_creates_unread_folders_first() {
+    let server = portale().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    esegui_cli(&server, dir.path(), &["download"]);
+
+    assert_eq!(comunicazioni_richieste(&server).await, ["302", "301"]);
+    assert!(dir.path().join("download/8/README.txt").is_file());
+    assert!(dir.path().join("download/7/README.txt").is_file());
+}
+
+#[tokio::test]
+async fn test_download_read_first_when_disabled() {
+    let server = portale().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    esegui_cli(&server, dir.path(), &["download", "--unread-first", "false"]);
+
+    assert_eq!(comunicazioni_richieste(&server).await, ["301", "302"]);
+}
@@ -0,0 +1,59 @@
+//! Test del comando `export` della CLI, eseguito contro un portale simulato.
+
+use std::process::{Command, Output};
+use wiremock::matchers::{header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const CIRCOLARE: &str = r#"{"id":"301","codice":"7","titolo":"Uscita didattica","testo":"","data_start":"2024-09-12","data_stop":"2024-09-30","tipo_com":"C","tipo_com_filtro":"GEN","tipo_com_desc":"Circolari","nome_file":null,"richieste":null,"id_relazione":"","conf_lettura":"","flag_risp":"","testo_risp":null,"file_risp":null,"flag_accettazione":null,"modificato":"","evento_data":""}"#;
+
+async fn portale() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/sif/app/default/bacheca_personale.php"))
+        .and(query_param("action", "get_comunicazioni"))
+        .and(header("Cookie", "PHPSESSID=tok; webidentity=G1234567X"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(format!(r#"{{"read":[{}],"msg_new":null}}"#, CIRCOLARE)))
+        .mount(&server)
+        .await;
+    server
+}
+
+// Esegue la CLI in una cartella temporanea con il token `tok` già salvato
+fn esegui_cli(server: &MockServer, dir: &std::path::Path, args: &[&str]) -> Output {
+    std::fs::write(dir.join("phpsessid.token"), "tok").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_spaggiari-cli"))
+        .current_dir(dir)
+        .env("SPAGGIARI_USERNAME", "G1234567X")
+        .args(["--base-url", &server.uri()])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+#[tokio::test]
+async fn test_export_json_to_stdout() {
+    let server = portale().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = esegui_cli(&server, dir.path(), &["export"]);
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["read"][0]["id"], "301");
+    assert_eq!(json["read"][0]["titolo"], "Uscita didattica");
+}
+
+#[tokio::test]
+async fn test_export_csv_to_file() {
+    let server = portale().await;
+    let dir = tempfile::tempdir().unwrap();
+
+    let output = esegui_cli(&server, dir.path(), &["export", "--format", "csv", "--output", "bacheca.csv"]);
+
+    assert!(output.stdout.is_empty());
+    let csv = std::fs::read_to_string(dir.path().join("bacheca.csv")).unwrap();
+    let righe: Vec<&str> = csv.lines().collect();
+    assert_eq!(righe.len(), 2);
+    assert!(righe[1].contains("301") && righe[1].contains("Uscita didattica"), "{}", csv);
+}
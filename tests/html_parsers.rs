@@ -0,0 +1,165 @@
+//! Test dei parser HTML su pagine salvate dal portale (`tests/fixtures/`).
+
+use spaggiari_rs::assenze::{parse_assenze, Assenza, TipoAssenza};
+use spaggiari_rs::bacheca_personale::{extract_allegati, extract_testo_comunicazione};
+use spaggiari_rs::voti::{parse_voti, Voto};
+
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+// Il testo estratto viene confrontato ignorando le differenze di spaziatura
+fn normalizza(testo: &str) -> String {
+    testo.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// (comunicazione_id, allegato_id, nome) degli allegati estratti
+fn ids_e_nomi(html: &str) -> Vec<(String, String, Option<String>)> {
+    extract_allegati(html).unwrap().into_iter().map(|a| (a.comunicazione_id, a.allegato_id, a.nome)).collect()
+}
+
+#[test]
+fn test_comunicazione_con_allegati() {
+    let html = fixture("comunicazione_con_allegati.html");
+
+    assert_eq!(
+        ids_e_nomi(&html),
+        vec![
+            ("9876543".to_string(), "1122334".to_string(), Some("Circolare_45.pdf".to_string())),
+            ("9876543".to_string(), "1122335".to_string(), Some("Autorizzazione.pdf".to_string())),
+        ]
+    );
+
+    let testo = extract_testo_comunicazione(&html).unwrap();
+    assert_eq!(normalizza(&testo), "Si comunica che il giorno 12 ottobre le classi terze parteciperanno all'uscita didattica.");
+}
+
+#[test]
+fn test_comunicazione_senza_allegati() {
+    let html = fixture("comunicazione_senza_allegati.html");
+
+    assert!(extract_allegati(&html).unwrap().is_empty());
+
+    let testo = extract_testo_comunicazione(&html).unwrap();
+    assert_eq!(normalizza(&testo), "Si avvisano le famiglie che venerdì è previsto uno sciopero del personale.");
+}
+
+#[test]
+fn test_comunicazione_formattata() {
+    let html = fixture("comunicazione_formattata.html");
+
+    assert_eq!(ids_e_nomi(&html), vec![("5550001".to_string(), "7770001".to_string(), Some("Calendario.pdf".to_string()))]);
+
+    let testo = normalizza(&extract_testo_comunicazione(&html).unwrap());
+    assert!(testo.starts_with("Gentili famiglie,"));
+    assert!(testo.contains("lunedì 4 dicembre"));
+    assert!(testo.contains("martedì 5 dicembre"));
+    assert!(testo.ends_with("Il Dirigente Scolastico"));
+}
+
+#[test]
+fn test_nome_allegato_da_title_o_testo() {
+    let html = r##"<div class="allegati">
+        <a class="dwl_allegato" comunicazione_id="1" allegato_id="10" title="Modulo uscita didattica.pdf" href="#"><i class="fa fa-download"></i> Scarica</a>
+        <a class="dwl_allegato" comunicazione_id="1" allegato_id="11" href="#">
+            Programma_gita.docx
+        </a>
+        <a class="dwl_allegato" comunicazione_id="1" allegato_id="12" href="#"><i class="fa fa-paperclip"></i></a>
+    </div>"##;
+
+    assert_eq!(
+        ids_e_nomi(html),
+        vec![
+            ("1".to_string(), "10".to_string(), Some("Modulo uscita didattica.pdf".to_string())),
+            ("1".to_string(), "11".to_string(), Some("Programma_gita.docx".to_string())),
+            ("1".to_string(), "12".to_string(), None),
+        ]
+    );
+}
+
+#[test]
+fn test_testo_mantiene_paragrafi_ed_elenchi() {
+    let html = fixture("comunicazione_formattata.html");
+
+    let testo = extract_testo_comunicazione(&html).unwrap();
+    assert_eq!(
+        testo,
+        "Gentili famiglie,\n\ni colloqui si terranno nelle seguenti date:\n\n- lunedì 4 dicembre\n- martedì 5 dicembre\n\nCordiali saluti,\nIl Dirigente Scolastico"
+    );
+}
+
+#[test]
+fn test_pagina_senza_testo() {
+    let testo = extract_testo_comunicazione("<html><body><p>Pagina vuota</p></body></html>").unwrap();
+    assert_eq!(testo, "");
+}
+
+#[test]
+fn test_tabella_voti() {
+    let html = fixture("voti.html");
+
+    let voti = parse_voti(&html).unwrap();
+    let voto = |materia: &str, valore: &str, data: &str, tipo: &str| Voto {
+        materia: materia.to_string(),
+        valore: valore.to_string(),
+        data: data.to_string(),
+        tipo: tipo.to_string(),
+    };
+    assert_eq!(
+        voti,
+        vec![
+            voto("MATEMATICA", "7½", "12/10/2024", "Scritto/Grafico"),
+            voto("MATEMATICA", "6-", "28/10/2024", "Orale"),
+            voto("LINGUA E LETTERATURA ITALIANA", "8", "03/11/2024", "Orale"),
+        ]
+    );
+}
+
+#[test]
+fn test_assenze_giustificate_e_da_giustificare() {
+    let html = fixture("assenze.html");
+
+    let assenze = parse_assenze(&html).unwrap();
+    assert_eq!(
+        assenze,
+        vec![
+            Assenza {
+                data: "14/10/2024".to_string(),
+                tipo: TipoAssenza::Assenza,
+                giustificata: true,
+                motivo: Some("Motivi di salute".to_string()),
+            },
+            Assenza {
+                data: "21/10/2024".to_string(),
+                tipo: TipoAssenza::Ritardo,
+                giustificata: false,
+                motivo: None,
+            },
+            Assenza {
+                data: "05/11/2024".to_string(),
+                tipo: TipoAssenza::Uscita,
+                giustificata: true,
+                motivo: Some("Visita medica".to_string()),
+            },
+        ]
+    );
+}
+
+#[cfg(feature = "markdown")]
+#[test]
+fn test_markdown_con_link_ed_elenchi() {
+    let html = fixture("comunicazione_con_link.html");
+
+    let markdown = spaggiari_rs::bacheca_personale::extract_markdown_comunicazione(&html).unwrap();
+    assert_eq!(
+        markdown,
+        "### Iscrizioni a.s. 2025/26\n\n\
+         Le domande vanno presentate sul portale [Iscrizioni online](https://www.istruzione.it/iscrizionionline/) entro il **31 gennaio**.\n\n\
+         Documenti *necessari*:\n\n\
+         - codice fiscale dello studente\n\
+         - documento d'identità di un genitore\n\n\
+         1. Registrarsi sul portale\n\
+         2. Compilare la domanda\n\n\
+         La segreteria"
+    );
+}
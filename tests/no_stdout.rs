@@ -0,0 +1,30 @@
+//! I moduli della libreria non devono scrivere su stdout/stderr: i messaggi passano da `tracing`.
+//! Solo la CLI (`src/main.rs`) può stampare output per l'utente.
+
+const MACRO_VIETATE: [&str; 4] = ["println!", "print!", "eprintln!", "dbg!"];
+
+#[test]
+fn test_library_modules_do_not_print() {
+    let src = format!("{}/src", env!("CARGO_MANIFEST_DIR"));
+    let mut violazioni = Vec::new();
+
+    for entry in std::fs::read_dir(&src).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "rs") || path.ends_with("main.rs") {
+            continue;
+        }
+        let sorgente = std::fs::read_to_string(&path).unwrap();
+        for (numero, riga) in sorgente.lines().enumerate() {
+            // Gli esempi nei doc comment possono stampare liberamente
+            let codice = riga.trim_start();
+            if codice.starts_with("//") {
+                continue;
+            }
+            if MACRO_VIETATE.iter().any(|m| codice.contains(m)) {
+                violazioni.push(format!("{}:{}: {}", path.display(), numero + 1, codice));
+            }
+        }
+    }
+
+    assert!(violazioni.is_empty(), "Output diretto nei moduli della libreria:\n{}", violazioni.join("\n"));
+}